@@ -1,12 +1,13 @@
 pub mod model;
 pub mod topo;
 pub mod xml;
+pub mod railml3;
 
 #[cfg(test)]
 mod tests {
     use crate::xml;
-    use crate::model;
     use crate::topo;
+    use crate::railml3;
     #[test]
     fn it_works() {
         println!("Reading xml");
@@ -18,4 +19,91 @@ mod tests {
         println!(" Found topology {:#?}", topo);
         println!(" Found topology {:?}", topo);
     }
+
+    #[test]
+    fn write_and_read() {
+        let s = std::fs::read_to_string("threetrack.railml").unwrap();
+        let railml = xml::parse_railml(&s).expect("railml parse failed");
+        let num_tracks = railml.infrastructure.as_ref().unwrap().tracks.len();
+
+        let written = xml::write_railml(&railml);
+        println!("Written railml {}", written);
+        let reread = xml::parse_railml(&written).expect("written railml parse failed");
+        assert_eq!(reread.infrastructure.as_ref().unwrap().tracks.len(), num_tracks);
+
+        let topo1 = topo::convert_railml_topo(railml).expect("topo conversion failed");
+        let topo2 = topo::convert_railml_topo(reread).expect("topo conversion of written railml failed");
+        assert_eq!(topo1.tracks.len(), topo2.tracks.len());
+        assert_eq!(topo1.nodes.len(), topo2.nodes.len());
+        assert_eq!(topo1.connections.len(), topo2.connections.len());
+    }
+
+    #[test]
+    fn read_objects() {
+        let s = std::fs::read_to_string("threetrack.railml").unwrap();
+        let railml = xml::parse_railml(&s).expect("railml parse failed");
+        let track = &railml.infrastructure.as_ref().unwrap().tracks[0];
+        assert_eq!(track.objects.signals.len(), 3);
+        assert_eq!(track.objects.train_detectors.len(), 5);
+
+        let topo = topo::convert_railml_topo(railml).expect("topo conversion failed");
+        let num_signals :usize = topo.tracks.iter().map(|t| t.objects.signals.len()).sum();
+        assert_eq!(num_signals, 3);
+        for t in topo.tracks.iter() {
+            for d in t.objects.train_detectors.iter() {
+                assert!(d.pos.offset >= t.offset && d.pos.offset <= t.offset + t.length);
+            }
+        }
+    }
+
+    #[test]
+    fn read_railml3() {
+        let s2 = std::fs::read_to_string("twotrack.railml").unwrap();
+        let s3 = std::fs::read_to_string("twotrack3.railml").unwrap();
+        assert!(!railml3::is_railml3(&s2));
+        assert!(railml3::is_railml3(&s3));
+
+        let topo2 = topo::convert_railml_topo(xml::parse_railml(&s2).expect("railml parse failed"))
+            .expect("topo conversion failed");
+        let topo3 = railml3::parse_railml3_topo(&s3).expect("railml 3 conversion failed");
+        println!(" Found topology {:#?}", topo3);
+        assert_eq!(topo2.tracks.len(), topo3.tracks.len());
+        assert_eq!(topo2.nodes.len(), topo3.nodes.len());
+        assert_eq!(topo2.connections.len(), topo3.connections.len());
+
+        let num_signals :usize = topo3.tracks.iter().map(|t| t.objects.signals.len()).sum();
+        assert_eq!(num_signals, 3);
+        assert!(topo3.tracks.iter().flat_map(|t| t.objects.signals.iter())
+                .any(|s| s.id == "sig1" && s.sight == Some(200.0)));
+        for t in topo3.tracks.iter() {
+            for d in t.objects.train_detectors.iter() {
+                assert!(d.pos.offset >= t.offset && d.pos.offset <= t.offset + t.length);
+            }
+        }
+    }
+
+    #[test]
+    fn reject_railml3_slip_crossing() {
+        use railml3::*;
+        // Four net elements meeting at a single slip crossing,
+        // with straight legs 0-1 and 2-3 and a slip from 0 to 3.
+        let inf = Infrastructure3 {
+            net_elements: (0..4).map(|i| NetElement { id: format!("ne{}", i), length: 100.0 }).collect(),
+            net_relations: vec![
+                NetRelation { id: "nr1".to_string(), a: ("ne0".to_string(), topo::AB::B), b: ("ne1".to_string(), topo::AB::A) },
+                NetRelation { id: "nr2".to_string(), a: ("ne2".to_string(), topo::AB::B), b: ("ne3".to_string(), topo::AB::A) },
+                NetRelation { id: "nr3".to_string(), a: ("ne0".to_string(), topo::AB::B), b: ("ne3".to_string(), topo::AB::A) },
+            ],
+            switches: vec![],
+            buffer_stops: vec![],
+            signals: vec![],
+            train_detectors: vec![],
+        };
+        match convert_railml3_topo(inf) {
+            Err(topo::TopoConvErr::CrossingUnsupported(ids)) => {
+                for i in 0..4 { assert!(ids.contains(&format!("ne{}", i))); }
+            },
+            x => panic!("expected crossing to be rejected, found {:?}", x),
+        }
+    }
 }
@@ -124,6 +124,7 @@ pub struct Position {
 #[derive(Debug)]
 pub struct Objects {
     pub signals: Vec<Signal>,
+    pub train_detectors: Vec<TrainDetector>,
     pub balises: Vec<Balise>,
 }
 
@@ -131,6 +132,7 @@ impl Objects {
     pub fn empty() -> Objects {
         Objects {
             signals :Vec::new(),
+            train_detectors :Vec::new(),
             balises :Vec::new(),
         }
     }
@@ -138,21 +140,30 @@ impl Objects {
 
 #[derive(Debug)]
 pub struct Signal {
-    id: Id,
-    pos :Position,
-    name :Option<String>,
-    dir :TrackDirection,
-    sight :Option<f64>,
-    r#type :SignalType,
+    pub id: Id,
+    pub pos :Position,
+    pub name :Option<String>,
+    pub dir :TrackDirection,
+    pub sight :Option<f64>,
+    pub r#type :SignalType,
 }
 
+#[derive(Copy,Clone)]
 #[derive(Debug)]
 pub enum SignalType { Main, Distant, Repeater, Combined, Shunting }
 #[derive(Debug)]
 pub enum SignalFunction { Exit, Home, Blocking, Intermediate }
+#[derive(Copy,Clone)]
 #[derive(Debug)]
 pub enum TrackDirection { Up, Down }
 
+#[derive(Debug)]
+pub struct TrainDetector {
+    pub id :Id,
+    pub pos :Position,
+    pub name :Option<String>,
+}
+
 #[derive(Debug)]
 pub struct Balise {
 }
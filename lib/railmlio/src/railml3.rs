@@ -0,0 +1,418 @@
+use crate::model::*;
+use crate::topo::*;
+use crate::xml::DocErr;
+use roxmltree as xml;
+use std::collections::HashMap;
+use log::*;
+
+type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+//
+//
+//
+// railML 3.x micro topology (netElements/netRelations) and
+// functional infrastructure (switches, buffer stops, signals and
+// train detectors) converted into the same topological model as railML 2.x.
+//
+//
+//
+
+#[derive(Debug)]
+pub struct Infrastructure3 {
+    pub net_elements :Vec<NetElement>,
+    pub net_relations :Vec<NetRelation>,
+    pub switches :Vec<SwitchIS>,
+    pub buffer_stops :Vec<SpotLocation>,
+    pub signals :Vec<(Signal, SpotLocation)>,
+    pub train_detectors :Vec<(TrainDetector, SpotLocation)>,
+}
+
+#[derive(Debug)]
+pub struct NetElement {
+    pub id :Id,
+    pub length :f64,
+}
+
+#[derive(Debug)]
+pub struct NetRelation {
+    pub id :Id,
+    pub a :(IdRef, AB),
+    pub b :(IdRef, AB),
+}
+
+#[derive(Debug)]
+pub struct SwitchIS {
+    pub id :Id,
+    pub left_branch :Option<IdRef>,
+    pub right_branch :Option<IdRef>,
+    pub branch_course :Option<SwitchConnectionCourse>,
+    pub continue_course :Option<SwitchConnectionCourse>,
+}
+
+#[derive(Debug)]
+pub struct SpotLocation {
+    pub net_element :IdRef,
+    pub intrinsic_coord :f64,
+    pub direction :Option<TrackDirection>,
+}
+
+/// Check whether the document is railML 3.x, using the version attribute
+/// or the namespace of the root element.
+pub fn is_railml3(data :&str) -> bool {
+    match roxmltree::Document::parse(data) {
+        Ok(doc) => {
+            let root = doc.root_element();
+            root.attribute("version").map(|v| v.starts_with("3")).unwrap_or(false) ||
+                root.tag_name().namespace().map(|ns| ns.contains("schemas/3")).unwrap_or(false)
+        },
+        Err(_) => false,
+    }
+}
+
+pub fn parse_railml3_topo(data :&str) -> BoxResult<Topological> {
+    let doc = roxmltree::Document::parse(data)?;
+    let inf = parse_railml3(&doc.root_element()).map_err(|e| format!("{:?}", e))?;
+    let topo = convert_railml3_topo(inf).map_err(|e| format!("{:?}", e))?;
+    Ok(topo)
+}
+
+fn parse_railml3(root :&xml::Node) -> Result<Infrastructure3, DocErr> {
+    let inf = root.children().find(|c| c.has_tag_name("infrastructure"))
+        .ok_or(DocErr::ElementMissing("infrastructure", root.range().start))?;
+    let topology = inf.children().find(|c| c.has_tag_name("topology"))
+        .ok_or(DocErr::ElementMissing("topology", inf.range().start))?;
+
+    let mut net_elements = Vec::new();
+    if let Some(nes) = topology.children().find(|c| c.has_tag_name("netElements")) {
+        for ne in nes.children().filter(|c| c.has_tag_name("netElement")) {
+            // Only the micro level net elements describe tracks,
+            // aggregated net elements (meso/macro levels) are skipped.
+            if ne.children().any(|c| c.has_tag_name("elementCollectionOrdered") ||
+                                     c.has_tag_name("elementCollectionUnordered")) {
+                continue;
+            }
+            net_elements.push(parse_net_element(&ne)?);
+        }
+    }
+
+    let mut net_relations = Vec::new();
+    if let Some(nrs) = topology.children().find(|c| c.has_tag_name("netRelations")) {
+        for nr in nrs.children().filter(|c| c.has_tag_name("netRelation")) {
+            net_relations.push(parse_net_relation(&nr)?);
+        }
+    }
+
+    let mut switches = Vec::new();
+    let mut buffer_stops = Vec::new();
+    let mut signals = Vec::new();
+    let mut train_detectors = Vec::new();
+    if let Some(func) = inf.children().find(|c| c.has_tag_name("functionalInfrastructure")) {
+        if let Some(sws) = func.children().find(|c| c.has_tag_name("switchesIS")) {
+            for sw in sws.children().filter(|c| c.has_tag_name("switchIS")) {
+                switches.push(parse_switch(&sw)?);
+            }
+        }
+        if let Some(bss) = func.children().find(|c| c.has_tag_name("bufferStops")) {
+            for bs in bss.children().filter(|c| c.has_tag_name("bufferStop")) {
+                buffer_stops.push(parse_spot_location(&bs)?);
+            }
+        }
+        if let Some(sigs) = func.children().find(|c| c.has_tag_name("signalsIS")) {
+            for sig in sigs.children().filter(|c| c.has_tag_name("signalIS")) {
+                signals.push(parse_signal(&sig)?);
+            }
+        }
+        if let Some(dets) = func.children().find(|c| c.has_tag_name("trainDetectionElements")) {
+            for det in dets.children().filter(|c| c.has_tag_name("trainDetectionElement")) {
+                train_detectors.push((TrainDetector {
+                    id: det.attribute("id").ok_or(DocErr::AttributeMissing("id", det.range().start))?.to_string(),
+                    pos: Position { offset: 0.0, mileage: None },
+                    name: det.attribute("name").map(|x| x.to_string()),
+                }, parse_spot_location(&det)?));
+            }
+        }
+    }
+
+    Ok(Infrastructure3 { net_elements, net_relations, switches, buffer_stops, signals, train_detectors })
+}
+
+fn parse_f64(node :&xml::Node, attr :&'static str) -> Result<Option<f64>, DocErr> {
+    match node.attribute(attr) {
+        Some(x) => Ok(Some(x.parse::<f64>().map_err(|_e| DocErr::NumberError(node.range().start))?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_net_element(ne :&xml::Node) -> Result<NetElement, DocErr> {
+    let length = match parse_f64(ne, "length")? {
+        Some(l) => l,
+        None => {
+            // Use the linear positioning system measures at the net element ends.
+            let mut measures = HashMap::new();
+            for ic in ne.descendants().filter(|c| c.has_tag_name("intrinsicCoordinate")) {
+                let coord = parse_f64(&ic, "intrinsicCoord")?;
+                let measure = match ic.children().find(|c| c.has_tag_name("linearCoordinate")) {
+                    Some(lc) => parse_f64(&lc, "measure")?,
+                    None => None,
+                };
+                if let (Some(coord), Some(measure)) = (coord, measure) {
+                    measures.insert(if coord < 0.5 { AB::A } else { AB::B }, measure);
+                }
+            }
+            match (measures.get(&AB::A), measures.get(&AB::B)) {
+                (Some(a),Some(b)) => (b-a).abs(),
+                _ => { return Err(DocErr::AttributeMissing("length", ne.range().start)); },
+            }
+        }
+    };
+
+    Ok(NetElement {
+        id: ne.attribute("id").ok_or(DocErr::AttributeMissing("id", ne.range().start))?.to_string(),
+        length,
+    })
+}
+
+fn parse_relation_end(nr :&xml::Node, elem :&'static str, pos_attr :&'static str) -> Result<(IdRef,AB), DocErr> {
+    let e = nr.children().find(|c| c.has_tag_name(elem))
+        .ok_or(DocErr::ElementMissing(elem, nr.range().start))?;
+    let r = e.attribute("ref").ok_or(DocErr::AttributeMissing("ref", e.range().start))?.to_string();
+    let end = match nr.attribute(pos_attr).ok_or(DocErr::AttributeMissing(pos_attr, nr.range().start))? {
+        "0" => AB::A,
+        "1" => AB::B,
+        _ => { return Err(DocErr::EnumErr("0, 1", nr.range().start)); }
+    };
+    Ok((r,end))
+}
+
+fn parse_net_relation(nr :&xml::Node) -> Result<NetRelation, DocErr> {
+    Ok(NetRelation {
+        id: nr.attribute("id").ok_or(DocErr::AttributeMissing("id", nr.range().start))?.to_string(),
+        a: parse_relation_end(nr, "elementA", "positionOnA")?,
+        b: parse_relation_end(nr, "elementB", "positionOnB")?,
+    })
+}
+
+fn parse_course(x :Option<&str>, pos :usize) -> Result<Option<SwitchConnectionCourse>, DocErr> {
+    match x {
+        Some("left") => Ok(Some(SwitchConnectionCourse::Left)),
+        Some("right") => Ok(Some(SwitchConnectionCourse::Right)),
+        Some("straight") => Ok(Some(SwitchConnectionCourse::Straight)),
+        None => Ok(None),
+        _ => Err(DocErr::EnumErr("left, right, straight", pos)),
+    }
+}
+
+fn parse_switch(sw :&xml::Node) -> Result<SwitchIS, DocErr> {
+    let branch = |name :&str| sw.children().find(|c| c.has_tag_name(name))
+        .and_then(|b| b.attribute("netRelationRef")).map(|x| x.to_string());
+    Ok(SwitchIS {
+        id: sw.attribute("id").ok_or(DocErr::AttributeMissing("id", sw.range().start))?.to_string(),
+        left_branch: branch("leftBranch"),
+        right_branch: branch("rightBranch"),
+        branch_course: parse_course(sw.attribute("branchCourse"), sw.range().start)?,
+        continue_course: parse_course(sw.attribute("continueCourse"), sw.range().start)?,
+    })
+}
+
+fn parse_spot_location(node :&xml::Node) -> Result<SpotLocation, DocErr> {
+    let spot = node.children().find(|c| c.has_tag_name("spotLocation"))
+        .ok_or(DocErr::ElementMissing("spotLocation", node.range().start))?;
+    Ok(SpotLocation {
+        net_element: spot.attribute("netElementRef")
+            .ok_or(DocErr::AttributeMissing("netElementRef", spot.range().start))?.to_string(),
+        intrinsic_coord: parse_f64(&spot, "intrinsicCoord")?.unwrap_or(0.0),
+        direction: match spot.attribute("applicationDirection") {
+            Some("normal") => Some(TrackDirection::Up),
+            Some("reverse") => Some(TrackDirection::Down),
+            _ => None,
+        },
+    })
+}
+
+fn parse_signal(sig :&xml::Node) -> Result<(Signal,SpotLocation), DocErr> {
+    let spot = parse_spot_location(sig)?;
+    let signal_type = if let Some(tm) = sig.children().find(|c| c.has_tag_name("isTrainMovementSignal")) {
+        match tm.attribute("type") {
+            Some("main") | None => SignalType::Main,
+            Some("distant") => SignalType::Distant,
+            Some("repeater") => SignalType::Repeater,
+            Some("combined") => SignalType::Combined,
+            _ => { return Err(DocErr::EnumErr("main, distant, repeater, combined", tm.range().start)); },
+        }
+    } else if sig.children().any(|c| c.has_tag_name("isShuntingSignal")) {
+        SignalType::Shunting
+    } else {
+        SignalType::Main
+    };
+
+    Ok((Signal {
+        id: sig.attribute("id").ok_or(DocErr::AttributeMissing("id", sig.range().start))?.to_string(),
+        pos: Position { offset: 0.0, mileage: None },
+        name: sig.attribute("name").map(|x| x.to_string()),
+        dir: spot.direction.ok_or(DocErr::AttributeMissing("applicationDirection", sig.range().start))?,
+        sight: parse_f64(sig, "sight")?,
+        r#type: signal_type,
+    }, spot))
+}
+
+type End = (usize, AB);
+
+pub fn convert_railml3_topo(inf :Infrastructure3) -> Result<Topological, TopoConvErr> {
+    let Infrastructure3 { net_elements, net_relations, switches, buffer_stops, signals, train_detectors } = inf;
+    let ne_idxs :HashMap<&str,usize> = net_elements.iter().enumerate()
+        .map(|(i,ne)| (ne.id.as_str(), i)).collect();
+    let ne_idx = |id :&str| ne_idxs.get(id).cloned().ok_or(TopoConvErr::UnknownNetElement(id.to_string()));
+
+    // Connected track ends
+    let mut relations :HashMap<&str,(End,End)> = HashMap::new();
+    let mut adjacent :HashMap<End, Vec<End>> = HashMap::new();
+    for nr in net_relations.iter() {
+        let a = (ne_idx(&(nr.a).0)?, (nr.a).1);
+        let b = (ne_idx(&(nr.b).0)?, (nr.b).1);
+        relations.insert(nr.id.as_str(), (a,b));
+        adjacent.entry(a).or_default().push(b);
+        adjacent.entry(b).or_default().push(a);
+    }
+
+    // Group track ends into nodes
+    let mut node_of :HashMap<End, usize> = HashMap::new();
+    let mut node_ends :Vec<Vec<End>> = Vec::new();
+    for i in 0..net_elements.len() {
+        for ab in &[AB::A, AB::B] {
+            let end = (i,*ab);
+            if node_of.contains_key(&end) { continue; }
+            let idx = node_ends.len();
+            let mut ends = Vec::new();
+            let mut stack = vec![end];
+            node_of.insert(end, idx);
+            while let Some(e) = stack.pop() {
+                ends.push(e);
+                for other in adjacent.get(&e).into_iter().flatten() {
+                    if !node_of.contains_key(other) {
+                        node_of.insert(*other, idx);
+                        stack.push(*other);
+                    }
+                }
+            }
+            node_ends.push(ends);
+        }
+    }
+
+    // Net elements connected end-to-end without switching
+    // are joined into the same track.
+    let continuation = |end :End| -> Option<End> {
+        let ends = &node_ends[node_of[&end]];
+        if ends.len() == 2 { ends.iter().find(|e| **e != end).cloned() } else { None }
+    };
+
+    let mut topo = Topological {
+        tracks: Vec::new(),
+        nodes: Vec::new(),
+        connections: Vec::new(),
+    };
+
+    let mut ne_pos :HashMap<usize,(usize,f64,bool)> = HashMap::new();
+    let mut track_ends :HashMap<End,(usize,AB)> = HashMap::new();
+    for i in 0..net_elements.len() {
+        if ne_pos.contains_key(&i) { continue; }
+
+        let mut start = (i,true);
+        while let Some((j,ab)) = continuation(if start.1 { (start.0,AB::A) } else { (start.0,AB::B) }) {
+            if j == i { return Err(TopoConvErr::NodeUnsupported(format!("loop at {}", net_elements[i].id))); }
+            start = (j, ab == AB::B);
+        }
+
+        let track_idx = new_track(&mut topo, TopoTrack { objects: Objects::empty(), length: 0.0, offset: 0.0 });
+        track_ends.insert(if start.1 { (start.0,AB::A) } else { (start.0,AB::B) }, (track_idx, AB::A));
+        let mut current = start;
+        let mut length = 0.0;
+        loop {
+            ne_pos.insert(current.0, (track_idx, length, current.1));
+            length += net_elements[current.0].length;
+            let out_end = if current.1 { (current.0,AB::B) } else { (current.0,AB::A) };
+            match continuation(out_end) {
+                Some((j,ab)) if !ne_pos.contains_key(&j) => { current = (j, ab == AB::A); },
+                _ => {
+                    track_ends.insert(out_end, (track_idx, AB::B));
+                    break;
+                }
+            }
+        }
+        topo.tracks[track_idx].length = length;
+    }
+
+    let is_at_end = |spot :&SpotLocation, end :End| -> bool {
+        ne_idxs.get(spot.net_element.as_str()) == Some(&end.0) &&
+            (spot.intrinsic_coord < 0.5) == (end.1 == AB::A)
+    };
+
+    // Nodes
+    for ends in node_ends.iter() {
+        match ends.len() {
+            1 => {
+                let end = ends[0];
+                let node_type = if buffer_stops.iter().any(|s| is_at_end(s, end)) {
+                    TopoNode::BufferStop } else { TopoNode::OpenEnd };
+                let nd = new_node(&mut topo, node_type);
+                topo.connections.push((track_ends[&end], (nd, Port::Single)));
+            },
+            2 => {}, // continuation, already joined
+            3 => {
+                let trunk = *ends.iter().find(|e| adjacent.get(e).map(|a| a.len()) == Some(2))
+                    .ok_or(TopoConvErr::NodeUnsupported(format!("{:?}", ends)))?;
+                let branch_end = |r :&Option<IdRef>| -> Option<End> {
+                    let (a,b) = relations.get(r.as_ref()?.as_str())?;
+                    if *a == trunk { Some(*b) } else if *b == trunk { Some(*a) } else { None }
+                };
+                let sw = switches.iter().find(|sw| branch_end(&sw.left_branch).is_some() &&
+                                                      branch_end(&sw.right_branch).is_some())
+                    .ok_or(TopoConvErr::SwitchMissing(format!("{:?}", ends)))?;
+                let side = sw.branch_course.and_then(|c| c.to_side())
+                    .or(sw.continue_course.and_then(|c| c.opposite()).and_then(|c| c.to_side()))
+                    .ok_or(TopoConvErr::SwitchCourseUnknown(sw.id.clone()))?;
+
+                let nd = new_node(&mut topo, TopoNode::Switch(side));
+                topo.connections.push((track_ends[&trunk], (nd, Port::Trunk)));
+                topo.connections.push((track_ends[&branch_end(&sw.left_branch).unwrap()], (nd, Port::Left)));
+                topo.connections.push((track_ends[&branch_end(&sw.right_branch).unwrap()], (nd, Port::Right)));
+            },
+            4 => {
+                // Crossings (with or without slips) cannot be drawn in the schematic plot.
+                let mut ids = ends.iter().map(|(ne,_)| net_elements[*ne].id.as_str()).collect::<Vec<_>>();
+                ids.dedup();
+                return Err(TopoConvErr::CrossingUnsupported(ids.join(", ")));
+            },
+            _ => {
+                return Err(TopoConvErr::NodeUnsupported(format!("{:?}", ends)));
+            },
+        }
+    }
+
+    // Objects
+    let position = |spot :&SpotLocation| -> Result<(usize, f64, bool), TopoConvErr> {
+        let ne = ne_idx(&spot.net_element)?;
+        let (track_idx, offset, fwd) = ne_pos[&ne];
+        let l = net_elements[ne].length * spot.intrinsic_coord;
+        let pos = if fwd { offset + l } else { offset + net_elements[ne].length - l };
+        Ok((track_idx, pos, fwd))
+    };
+
+    for (mut signal, spot) in signals {
+        let (track_idx, pos, fwd) = position(&spot)?;
+        signal.pos = Position { offset: pos, mileage: None };
+        if !fwd {
+            signal.dir = match signal.dir { TrackDirection::Up => TrackDirection::Down,
+                                            TrackDirection::Down => TrackDirection::Up };
+        }
+        topo.tracks[track_idx].objects.signals.push(signal);
+    }
+
+    for (mut detector, spot) in train_detectors {
+        let (track_idx, pos, _) = position(&spot)?;
+        detector.pos = Position { offset: pos, mileage: None };
+        topo.tracks[track_idx].objects.train_detectors.push(detector);
+    }
+
+    debug!("railML 3 CONNECTIONS {:?}", topo.connections);
+    Ok(topo)
+}
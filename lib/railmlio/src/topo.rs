@@ -22,6 +22,8 @@ pub struct Topological {
 
 #[derive(Debug)]
 pub struct TopoTrack {
+    /// Trackside objects, with positions relative to the original railML track.
+    /// Subtract `offset` to get the position on this track.
     pub objects :Objects,
     pub length: f64,
     pub offset :f64,
@@ -55,7 +57,7 @@ impl Port {
             Port::Left => vec![(Port::Right,-1), (Port::Trunk,1)],
             Port::Right => vec![(Port::Left,-1), (Port::Trunk,1)],
             Port::Single => vec![],
-            Port::Crossing(_,_) => unimplemented!(), 
+            Port::Crossing(ab,i) => vec![(Port::Crossing(ab.opposite(),*i),1)],
         }
     }
 }
@@ -116,6 +118,10 @@ pub enum TopoConvErr {
     SwitchCourseUnknown(String),
     SwitchOrientationInvalid(String),
     UnmatchedConnection(String,String),
+    UnknownNetElement(String),
+    SwitchMissing(String),
+    NodeUnsupported(String),
+    CrossingUnsupported(String),
 }
 
 #[derive(Debug)]
@@ -130,21 +136,21 @@ pub fn switch_info(sw :Switch) -> Result<TopoSwitchInfo,TopoConvErr> {
     match sw {
         Switch::Switch { id, pos, connections, track_continue_course, track_continue_radius, .. } => {
             match connections.as_slice() {
-                &[] => Err(TopoConvErr::SwitchConnectionMissing(id)),
-                &[ref connection] =>  {
+                [] => Err(TopoConvErr::SwitchConnectionMissing(id)),
+                [connection] =>  {
                     let sw_course = connection.course
                         .or(track_continue_course.and_then(|c| c.opposite()))
                         .ok_or(TopoConvErr::SwitchCourseUnknown(id.clone()))?;
 
                     let deviating_side = if connection.radius.unwrap_or(0.0) > 
-                                            track_continue_radius.unwrap_or(std::f64::INFINITY) {
+                                            track_continue_radius.unwrap_or(f64::INFINITY) {
                         sw_course.opposite().unwrap().to_side().unwrap()
                     } else { sw_course.to_side().unwrap() };
 
                     Ok(
                         TopoSwitchInfo {
                             connref: (connection.id.clone(), connection.r#ref.clone()),
-                            deviating_side: deviating_side,
+                            deviating_side,
                             pos: pos.offset,
                             dir: match connection.orientation { 
                                 ConnectionOrientation::Outgoing => AB::A,
@@ -181,6 +187,7 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
             });
 
             let mut current_offset = 0.0;
+            let mut segments = vec![track_idx];
 
             track_end(track.begin.connection, (track_idx, AB::A), &mut topo, &mut named_track_ports);
             track.switches.sort_by_key(|s| match s { 
@@ -205,9 +212,22 @@ pub fn convert_railml_topo(doc :RailML) -> Result<Topological,TopoConvErr> {
                     length: 0.0
                 });
                 topo.connections.push(((track_idx,AB::A), (nd, b_port)));
+                segments.push(track_idx);
                 current_offset = sw_info.pos;
             }
 
+            // Place objects on the track segments between switches.
+            let segment_at = |topo :&Topological, pos :f64| *segments.iter().rev()
+                .find(|i| topo.tracks[**i].offset <= pos).unwrap_or(&segments[0]);
+            for signal in track.objects.signals {
+                let i = segment_at(&topo, signal.pos.offset);
+                topo.tracks[i].objects.signals.push(signal);
+            }
+            for detector in track.objects.train_detectors {
+                let i = segment_at(&topo, detector.pos.offset);
+                topo.tracks[i].objects.train_detectors.push(detector);
+            }
+
             track_end(track.end.connection, (track_idx, AB::B), &mut topo, &mut named_track_ports);
             topo.tracks[track_idx].length = track.end.pos.offset - current_offset;
         }
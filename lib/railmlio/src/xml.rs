@@ -57,7 +57,61 @@ fn parse_track(track :&xml::Node) -> Result<Track, DocErr> {
 }
 
 fn parse_objects(track :&xml::Node) -> Result<Objects, DocErr> {
-    Ok(Objects::empty()) // TODO
+    let mut objects = Objects::empty();
+    if let Some(ocs) = track.children().find(|c| c.has_tag_name("ocsElements")) {
+        if let Some(signals) = ocs.children().find(|c| c.has_tag_name("signals")) {
+            for s in signals.children().filter(|c| c.has_tag_name("signal")) {
+                objects.signals.push(parse_signal(&s)?);
+            }
+        }
+        if let Some(dets) = ocs.children().find(|c| c.has_tag_name("trainDetectionElements")) {
+            for d in dets.children().filter(|c| c.has_tag_name("trainDetector")) {
+                objects.train_detectors.push(parse_train_detector(&d)?);
+            }
+        }
+    }
+    Ok(objects)
+}
+
+fn parse_signal(s :&xml::Node) -> Result<Signal, DocErr> {
+    Ok(Signal {
+        id: s.attribute("id").ok_or(DocErr::AttributeMissing("id", s.range().start))?.to_string(),
+        pos: parse_position(s)?,
+        name: s.attribute("name").map(|x| x.to_string()),
+        dir: parse_direction(s.attribute("dir").ok_or(DocErr::AttributeMissing("dir", s.range().start))?, s.range().start)?,
+        sight: match s.attribute("sight") {
+            Some(sight) => Some(sight.parse::<f64>().map_err(|_e| DocErr::NumberError(s.range().start))?),
+            None => None,
+        },
+        r#type: parse_signal_type(s.attribute("type").ok_or(DocErr::AttributeMissing("type", s.range().start))?, s.range().start)?,
+    })
+}
+
+fn parse_direction(x :&str, pos :usize) -> Result<TrackDirection, DocErr> {
+    match x {
+        "up" => Ok(TrackDirection::Up),
+        "down" => Ok(TrackDirection::Down),
+        _ => Err(DocErr::EnumErr("up, down", pos)),
+    }
+}
+
+fn parse_signal_type(x :&str, pos :usize) -> Result<SignalType, DocErr> {
+    match x {
+        "main" => Ok(SignalType::Main),
+        "distant" => Ok(SignalType::Distant),
+        "repeater" => Ok(SignalType::Repeater),
+        "combined" => Ok(SignalType::Combined),
+        "shunting" => Ok(SignalType::Shunting),
+        _ => Err(DocErr::EnumErr("main, distant, repeater, combined, shunting", pos)),
+    }
+}
+
+fn parse_train_detector(d :&xml::Node) -> Result<TrainDetector, DocErr> {
+    Ok(TrainDetector {
+        id: d.attribute("id").ok_or(DocErr::AttributeMissing("id", d.range().start))?.to_string(),
+        pos: parse_position(d)?,
+        name: d.attribute("name").map(|x| x.to_string()),
+    })
 }
 
 fn parse_switches(topo :&xml::Node) -> Result<Vec<Switch>, DocErr> {
@@ -148,7 +202,7 @@ fn parse_orientation(x :&str, pos :usize) -> Result<ConnectionOrientation, DocEr
     }
 }
 
-fn parse_crossing(sw :&xml::Node) -> Result<Switch, DocErr> {
+fn parse_crossing(_sw :&xml::Node) -> Result<Switch, DocErr> {
     unimplemented!()
 }
 
@@ -166,10 +220,10 @@ fn parse_track_connection(node :&xml::Node) -> Result<TrackEndConnection, DocErr
         let idref = e.attribute("ref").ok_or(DocErr::AttributeMissing("ref", e.range().start))?;
         return Ok(TrackEndConnection::Connection(id.to_string(),idref.to_string()));
     }
-    if let Some(e) = node.children().find(|c| c.has_tag_name("bufferStop")) {
+    if node.children().any(|c| c.has_tag_name("bufferStop")) {
         return Ok(TrackEndConnection::BufferStop);
     }
-    if let Some(e) = node.children().find(|c| c.has_tag_name("openEnd")) {
+    if node.children().any(|c| c.has_tag_name("openEnd")) {
         return Ok(TrackEndConnection::OpenEnd);
     }
     if let Some(e) = node.children().find(|c| c.has_tag_name("macroscopicNode")) {
@@ -191,7 +245,191 @@ fn parse_position(node :&xml::Node) -> Result<Position, DocErr> {
 }
 
 
+//
+//
+//
+// railML 2.x writer
+//
+//
+//
+
+/// Write the railML document as railML 2.x XML.
+pub fn write_railml(doc :&RailML) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<railml xmlns=\"http://www.railml.org/schemas/2013\" version=\"2.2\">\n");
+    if let Some(inf) = &doc.infrastructure {
+        write_infrastructure(&mut out, inf);
+    }
+    out.push_str("</railml>\n");
+    out
+}
+
+fn escape(s :&str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn attr(out :&mut String, name :&str, value :&str) {
+    out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+}
+
+fn opt_attr(out :&mut String, name :&str, value :Option<&str>) {
+    if let Some(value) = value { attr(out, name, value); }
+}
+
+fn position_attrs(out :&mut String, pos :&Position) {
+    attr(out, "pos", &format!("{}", pos.offset));
+    if let Some(abs_pos) = pos.mileage { attr(out, "absPos", &format!("{}", abs_pos)); }
+}
+
+fn indent(out :&mut String, level :usize) {
+    for _ in 0..level { out.push_str("  "); }
+}
+
+fn write_infrastructure(out :&mut String, inf :&Infrastructure) {
+    indent(out,1); out.push_str("<infrastructure id=\"inf\">\n");
+    indent(out,2); out.push_str("<tracks>\n");
+    for track in &inf.tracks { write_track(out, track); }
+    indent(out,2); out.push_str("</tracks>\n");
+    indent(out,1); out.push_str("</infrastructure>\n");
+}
+
+fn write_track(out :&mut String, track :&Track) {
+    indent(out,3); out.push_str("<track");
+    attr(out, "id", &track.id);
+    opt_attr(out, "code", track.code.as_deref());
+    opt_attr(out, "name", track.name.as_deref());
+    opt_attr(out, "description", track.description.as_deref());
+    out.push_str(">\n");
+
+    indent(out,4); out.push_str("<trackTopology>\n");
+    write_track_node(out, "trackBegin", &track.begin);
+    write_track_node(out, "trackEnd", &track.end);
+    if !track.switches.is_empty() {
+        indent(out,5); out.push_str("<connections>\n");
+        for sw in &track.switches { write_switch(out, sw); }
+        indent(out,5); out.push_str("</connections>\n");
+    }
+    indent(out,4); out.push_str("</trackTopology>\n");
+
+    if !track.objects.signals.is_empty() || !track.objects.train_detectors.is_empty() {
+        indent(out,4); out.push_str("<ocsElements>\n");
+        if !track.objects.signals.is_empty() {
+            indent(out,5); out.push_str("<signals>\n");
+            for signal in &track.objects.signals { write_signal(out, signal); }
+            indent(out,5); out.push_str("</signals>\n");
+        }
+        if !track.objects.train_detectors.is_empty() {
+            indent(out,5); out.push_str("<trainDetectionElements>\n");
+            for det in &track.objects.train_detectors {
+                indent(out,6); out.push_str("<trainDetector");
+                attr(out, "id", &det.id);
+                opt_attr(out, "name", det.name.as_deref());
+                position_attrs(out, &det.pos);
+                out.push_str("/>\n");
+            }
+            indent(out,5); out.push_str("</trainDetectionElements>\n");
+        }
+        indent(out,4); out.push_str("</ocsElements>\n");
+    }
+
+    indent(out,3); out.push_str("</track>\n");
+}
 
+fn write_track_node(out :&mut String, tag :&str, node :&Node) {
+    indent(out,5); out.push_str(&format!("<{}", tag));
+    attr(out, "id", &node.id);
+    position_attrs(out, &node.pos);
+    out.push_str(">\n");
+    indent(out,6);
+    match &node.connection {
+        TrackEndConnection::Connection(id,idref) => {
+            out.push_str("<connection");
+            attr(out, "id", id);
+            attr(out, "ref", idref);
+        },
+        TrackEndConnection::BufferStop => {
+            out.push_str("<bufferStop");
+            attr(out, "id", &format!("{}_bs", node.id));
+        },
+        TrackEndConnection::OpenEnd => {
+            out.push_str("<openEnd");
+            attr(out, "id", &format!("{}_oe", node.id));
+        },
+        TrackEndConnection::MacroscopicNode(id) => {
+            out.push_str("<macroscopicNode");
+            attr(out, "id", id);
+        },
+    }
+    out.push_str("/>\n");
+    indent(out,5); out.push_str(&format!("</{}>\n", tag));
+}
 
+fn course_str(c :&SwitchConnectionCourse) -> &'static str {
+    match c {
+        SwitchConnectionCourse::Left => "left",
+        SwitchConnectionCourse::Right => "right",
+        SwitchConnectionCourse::Straight => "straight",
+    }
+}
+
+fn write_switch(out :&mut String, sw :&Switch) {
+    let (tag, id, pos, length, connections, continue_course, continue_radius) = match sw {
+        Switch::Switch { id, pos, length, connections, track_continue_course, track_continue_radius, .. } =>
+            ("switch", id, pos, length, connections, track_continue_course, track_continue_radius),
+        Switch::Crossing { id, pos, length, connections, track_continue_course, track_continue_radius, .. } =>
+            ("crossing", id, pos, length, connections, track_continue_course, track_continue_radius),
+    };
 
+    indent(out,6); out.push_str(&format!("<{}", tag));
+    attr(out, "id", id);
+    position_attrs(out, pos);
+    if let Switch::Switch { name, description, .. } = sw {
+        opt_attr(out, "name", name.as_deref());
+        opt_attr(out, "description", description.as_deref());
+    }
+    if let Some(l) = length { attr(out, "length", &format!("{}", l)); }
+    if let Some(c) = continue_course { attr(out, "trackContinueCourse", course_str(c)); }
+    if let Some(r) = continue_radius { attr(out, "trackContinueRadius", &format!("{}", r)); }
+    if let Switch::Crossing { normal_position: Some(c), .. } = sw { attr(out, "normalPosition", course_str(c)); }
+    out.push_str(">\n");
 
+    for c in connections.iter() {
+        indent(out,7); out.push_str("<connection");
+        attr(out, "id", &c.id);
+        attr(out, "ref", &c.r#ref);
+        attr(out, "orientation", match c.orientation {
+            ConnectionOrientation::Incoming => "incoming",
+            ConnectionOrientation::Outgoing => "outgoing",
+            ConnectionOrientation::RightAngled => "rightAngled",
+            ConnectionOrientation::Unknown => "unknown",
+            ConnectionOrientation::Other => "other",
+        });
+        if let Some(course) = &c.course { attr(out, "course", course_str(course)); }
+        if let Some(r) = c.radius { attr(out, "radius", &format!("{}", r)); }
+        if let Some(v) = c.max_speed { attr(out, "maxSpeed", &format!("{}", v)); }
+        if let Some(p) = c.passable { attr(out, "passable", &format!("{}", p)); }
+        out.push_str("/>\n");
+    }
+    indent(out,6); out.push_str(&format!("</{}>\n", tag));
+}
+
+fn write_signal(out :&mut String, signal :&Signal) {
+    indent(out,6); out.push_str("<signal");
+    attr(out, "id", &signal.id);
+    opt_attr(out, "name", signal.name.as_deref());
+    position_attrs(out, &signal.pos);
+    attr(out, "dir", match signal.dir {
+        TrackDirection::Up => "up",
+        TrackDirection::Down => "down",
+    });
+    if let Some(sight) = signal.sight { attr(out, "sight", &format!("{}", sight)); }
+    attr(out, "type", match signal.r#type {
+        SignalType::Main => "main",
+        SignalType::Distant => "distant",
+        SignalType::Repeater => "repeater",
+        SignalType::Combined => "combined",
+        SignalType::Shunting => "shunting",
+    });
+    out.push_str("/>\n");
+}
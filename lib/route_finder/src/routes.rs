@@ -16,21 +16,22 @@ pub enum ConvertRouteError {
 }
 
 pub struct Config {
-    // TODO what can we put in here
-    //
-    // 0. section overlap minimum length
-    // 1. route minimum length
-    // ---
-    // 2. overlap policy
-
-    section_tolerance :f64,
-    route_minimum_length: f64,
+    /// Distance (m) a route may run into a train detection section beyond 
+    /// its exit without the section becoming part of the route.
+    pub section_tolerance :f64,
+    /// Routes shorter than this (m) are reported as `ConvertRouteIssue::RouteTooShort`.
+    pub route_minimum_length: f64,
+    /// Length of the overlap beyond the exit signal of train routes. Each way through
+    /// the facing switches inside the overlap gives an alternative overlap. 
+    /// Zero gives routes without overlaps.
+    pub overlap_length :f64,
 }
 
 impl Default for Config {
     fn default() -> Config { Config {
         section_tolerance: 15.0,
         route_minimum_length: 15.0,
+        overlap_length: 50.0,
     } }
 }
 
@@ -53,155 +54,64 @@ pub struct Path {
 
 pub type RoutePath = Vec<(NodeId,NodeId)>;
 
-pub fn find_routes(config :Config, model :&StaticInfrastructure) -> Result<(Vec<(Route,RoutePath)>, Vec<ConvertRouteIssue>), ConvertRouteError> {
+/// Train routes go between main signals and model boundaries, passing any 
+/// shunting signals. Shunting routes start at a signal and end at the next signal 
+/// of any kind, or at a buffer stop, and do not reserve the section they end in, 
+/// so that they can lead into occupied track.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RouteClass { Train, Shunting }
+
+pub fn find_routes(config :Config, model :&StaticInfrastructure, shunting_signals :&HashSet<ObjectId>) 
+    -> Result<(Vec<(Route,RoutePath,RouteClass)>, Vec<ConvertRouteIssue>), ConvertRouteError> {
 
     let mut routes = Vec::new();
     let mut issues = Vec::new();
 
     let boundary_nodes = model.nodes.iter().enumerate()
         .filter_map(|(i,x)| if let Edges::ModelBoundary = x.edges { Some(i) } 
-                    else { None });
-
-    let mut entry_visited = HashSet::new();
-    for boundary_idx in boundary_nodes {
-        //println!("Boundary start {:?}", boundary_idx);
+                    else { None }).collect::<Vec<_>>();
 
-        let mut entry_stack = Vec::new();
-        entry_stack.push(RouteEntry {
-            node: model.nodes[boundary_idx].other_node,
-            entry: RouteEntryExit::Boundary(Some(boundary_idx)),
-            section: None,
-        });
-        entry_visited.insert(model.nodes[boundary_idx].other_node);
-
-        while entry_stack.len() > 0 {
-            let entry = entry_stack.pop().unwrap();
-            let mut search_stack = Vec::new();
-
-            let mut switches_path_visited : BTreeSet<BTreeSet<(ObjectId, SwitchPosition)>> = BTreeSet::new();
-            search_stack.push(Path {
-                node: entry.node,
-                entered_sections: entry.section.into_iter().map(|x| (x, 0.0)).collect(),
-                exited_sections: SmallVec::new(),
-                switches: SmallVec::new(),
-                length: 0.0,
-                edges_taken: vec![],
-            });
+    if !(boundary_nodes.len() > 0) {
+        issues.push(ConvertRouteIssue::NoBoundaries);
+    }
 
-            while search_stack.len() > 0 {
-                let mut curr_state = search_stack.pop().unwrap();
-                loop { // TODO make absolutely sure this terminates
-                    let mut is_exit = false;
-
-                    // Check what is in here
-                    for obj_idx in model.nodes[curr_state.node].objects.iter() {
-                        match &model.objects[*obj_idx] {
-                            StaticObject::Signal { .. } if curr_state.node != entry.node => {
-                                let exit = RouteEntryExit::Signal(*obj_idx);
-                                match make_route(&config, &curr_state, entry.entry, exit) {
-                                    Ok(route) => routes.push((route, curr_state.edges_taken.clone())),
-                                    Err(err) => issues.push(err),
-                                }
+    for class in &[RouteClass::Train, RouteClass::Shunting] {
+        let mut entry_visited = HashSet::new();
+        for boundary_idx in boundary_nodes.iter().cloned() {
+            //println!("Boundary start {:?}", boundary_idx);
 
-                                if entry_visited.insert(curr_state.node) {
-                                    entry_stack.push(RouteEntry {
-                                        node: curr_state.node,
-                                        entry: RouteEntryExit::Signal(*obj_idx),
-                                        section: curr_state.entered_sections.iter().nth(0).map(|x| x.0),
-                                    });
-                                }
+            let mut entry_stack = Vec::new();
+            entry_stack.push(RouteEntry {
+                node: model.nodes[boundary_idx].other_node,
+                entry: RouteEntryExit::Boundary(Some(boundary_idx)),
+                section: None,
+            });
+            entry_visited.insert(model.nodes[boundary_idx].other_node);
+            search(&config, model, *class, shunting_signals, entry_stack, 
+                   &mut entry_visited, &mut routes, &mut issues)?;
+        }
 
-                                is_exit = true;
-                            },
-                            StaticObject::TVDLimit { enter, exit } => {
-                                if let Some(s) = enter {
-                                    curr_state.entered_sections.push((*s, curr_state.length));
-                                }
-                                if let Some(s) = exit {
-                                    if let Some(i) = curr_state.entered_sections.iter().position(|y| y.0 == *s) {
-                                        let e = curr_state.entered_sections.remove(i);
-                                        curr_state.exited_sections.push((e.0, e.1, 
-                                                             curr_state.length, Some(curr_state.node)));
-                                    } else {
-                                        issues.push(ConvertRouteIssue::ExitedUnenteredSection(entry.entry, *s));
-                                    }
-                                }
-                            },
-                            _ => {} // sight, switch, sections, are not relevant
+        if *class == RouteClass::Shunting {
+            // Signals which are not reachable from the boundaries, 
+            // e.g. signals facing out of a siding.
+            for (node_idx,node) in model.nodes.iter().enumerate() {
+                for obj_idx in node.objects.iter() {
+                    if let StaticObject::Signal { .. } = &model.objects[*obj_idx] {
+                        if entry_visited.insert(node_idx) {
+                            let entry_stack = vec![RouteEntry {
+                                node: node_idx,
+                                entry: RouteEntryExit::Signal(*obj_idx),
+                                section: None,
+                            }];
+                            search(&config, model, *class, shunting_signals, entry_stack, 
+                                   &mut entry_visited, &mut routes, &mut issues)?;
                         }
                     }
-
-                    if is_exit { break; }
-
-                    match model.nodes[curr_state.node].edges {
-                        Edges::Nothing => { break; },
-                        Edges::ModelBoundary => {
-                            let exit = RouteEntryExit::Boundary(Some(curr_state.node));
-                            match make_route(&config, &curr_state, entry.entry, exit) {
-                                Ok(route) => routes.push((route, curr_state.edges_taken.clone())),
-                                Err(err) => issues.push(err),
-                            }
-                            break;
-                        },
-                        Edges::Single(other, d) => {
-                            // Trailing switches: look at the outgoing edges from opposite node.
-                            match model.nodes[other].edges {
-                                Edges::Switchable(sw) => {
-                                    if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
-                                        let pos = if left_link.0 == curr_state.node { SwitchPosition::Left } 
-                                                    else if right_link.0 == curr_state.node { SwitchPosition::Right }
-                                                    else {
-                                                        return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw))); };
-                                        curr_state.switches.push((sw,curr_state.length,pos));
-                                    }  else {
-                                        return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw)));
-                                    }
-
-                                },
-                                _ => {},
-                            };
-                            curr_state.edges_taken.push((curr_state.node, other));
-                            curr_state.node = model.nodes[other].other_node;
-                            curr_state.length += d;
-                        },
-                        Edges::Switchable(sw) => {
-                            if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
-                                let mut right_state = curr_state.clone();
-                                let mut left_state = curr_state;
-
-                                right_state.edges_taken.push((right_state.node, right_link.0));
-                                left_state.edges_taken.push((left_state.node, left_link.0));
-                                right_state.node = model.nodes[right_link.0].other_node;
-                                left_state.node = model.nodes[left_link.0].other_node;
-                                right_state.switches.push((sw, right_state.length, SwitchPosition::Right));
-                                left_state.switches.push((sw, left_state.length, SwitchPosition::Left));
-                                right_state.length += right_link.1;
-                                left_state.length += left_link.1;
-
-                                if switches_path_visited.insert(
-                                    right_state.switches.iter().map(|(sw,_l,pos)| (*sw,*pos)).collect()) {
-                                    search_stack.push(right_state);
-                                }
-                                if switches_path_visited.insert(
-                                    left_state.switches.iter().map(|(sw,_l,pos)| (*sw,*pos)).collect()) {
-                                    search_stack.push(left_state);
-                                }
-
-                                break;
-                            } else {
-                                return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw)));
-                            }
-                        },
-                    };
                 }
             }
         }
     }
 
-    if !(entry_visited.len() > 0) {
-        issues.push(ConvertRouteIssue::NoBoundaries);
-    }
-
     // TODO
 // ///        // Remove release of resources that were not aquired
 // ///    for r in &mut routes {
@@ -215,8 +125,166 @@ pub fn find_routes(config :Config, model :&StaticInfrastructure) -> Result<(Vec<
     Ok((routes,issues))
 }
 
+/// Shunting routes are only needed where a train route does not already
+/// cover the same movement.
+fn is_shunting_movement(shunting_signals :&HashSet<ObjectId>, entry :&RouteEntryExit, 
+                        exit :&RouteEntryExit) -> bool {
+    let is_shunting_signal = |e :&RouteEntryExit| match e {
+        RouteEntryExit::Signal(s) | RouteEntryExit::SignalTrigger { signal: s, .. } => shunting_signals.contains(s),
+        _ => false,
+    };
+    let from_signal = matches!(entry, RouteEntryExit::Signal(_) | RouteEntryExit::SignalTrigger { .. });
+    from_signal && (is_shunting_signal(entry) || is_shunting_signal(exit))
+}
+
+fn search(config :&Config, model :&StaticInfrastructure, class :RouteClass, 
+          shunting_signals :&HashSet<ObjectId>,
+          mut entry_stack :Vec<RouteEntry>, entry_visited :&mut HashSet<NodeId>,
+          routes :&mut Vec<(Route,RoutePath,RouteClass)>, issues :&mut Vec<ConvertRouteIssue>) 
+    -> Result<(), ConvertRouteError> {
+
+    while entry_stack.len() > 0 {
+        let entry = entry_stack.pop().unwrap();
+        let mut search_stack = Vec::new();
+
+        let mut switches_path_visited : BTreeSet<BTreeSet<(ObjectId, SwitchPosition)>> = BTreeSet::new();
+        search_stack.push(Path {
+            node: entry.node,
+            entered_sections: entry.section.into_iter().map(|x| (x, 0.0)).collect(),
+            exited_sections: SmallVec::new(),
+            switches: SmallVec::new(),
+            length: 0.0,
+            edges_taken: vec![],
+        });
+
+        while search_stack.len() > 0 {
+            let mut curr_state = search_stack.pop().unwrap();
+            loop { // TODO make absolutely sure this terminates
+                let mut is_exit = false;
+
+                // Check what is in here
+                for obj_idx in model.nodes[curr_state.node].objects.iter() {
+                    match &model.objects[*obj_idx] {
+                        StaticObject::Signal { .. } if curr_state.node != entry.node => {
+                            // Train routes pass the shunting signals.
+                            if class == RouteClass::Train && shunting_signals.contains(obj_idx) { continue; }
+
+                            let exit = RouteEntryExit::Signal(*obj_idx);
+                            if class == RouteClass::Train || 
+                                is_shunting_movement(shunting_signals, &entry.entry, &exit) {
+                                match make_route(config, model, class, &curr_state, entry.entry, exit) {
+                                    Ok(route) => routes.push((route, curr_state.edges_taken.clone(), class)),
+                                    Err(err) => issues.push(err),
+                                }
+                            }
+
+                            if entry_visited.insert(curr_state.node) {
+                                entry_stack.push(RouteEntry {
+                                    node: curr_state.node,
+                                    entry: RouteEntryExit::Signal(*obj_idx),
+                                    section: curr_state.entered_sections.iter().nth(0).map(|x| x.0),
+                                });
+                            }
+
+                            is_exit = true;
+                        },
+                        StaticObject::TVDLimit { enter, exit } => {
+                            if let Some(s) = enter {
+                                curr_state.entered_sections.push((*s, curr_state.length));
+                            }
+                            if let Some(s) = exit {
+                                if let Some(i) = curr_state.entered_sections.iter().position(|y| y.0 == *s) {
+                                    let e = curr_state.entered_sections.remove(i);
+                                    curr_state.exited_sections.push((e.0, e.1, 
+                                                         curr_state.length, Some(curr_state.node)));
+                                } else {
+                                    issues.push(ConvertRouteIssue::ExitedUnenteredSection(entry.entry, *s));
+                                }
+                            }
+                        },
+                        _ => {} // sight, switch, sections, are not relevant
+                    }
+                }
+
+                if is_exit { break; }
+
+                match model.nodes[curr_state.node].edges {
+                    Edges::Nothing => { 
+                        // Routes towards a buffer stop end at a (shunting) signal
+                        // placed at the buffer stop, so that they are not taken as
+                        // leaving the model.
+                        break; 
+                    },
+                    Edges::ModelBoundary => {
+                        let exit = RouteEntryExit::Boundary(Some(curr_state.node));
+                        if class == RouteClass::Train || 
+                            is_shunting_movement(shunting_signals, &entry.entry, &exit) {
+                            match make_route(config, model, class, &curr_state, entry.entry, exit) {
+                                Ok(route) => routes.push((route, curr_state.edges_taken.clone(), class)),
+                                Err(err) => issues.push(err),
+                            }
+                        }
+                        break;
+                    },
+                    Edges::Single(other, d) => {
+                        // Trailing switches: look at the outgoing edges from opposite node.
+                        match model.nodes[other].edges {
+                            Edges::Switchable(sw) => {
+                                if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
+                                    let pos = if left_link.0 == curr_state.node { SwitchPosition::Left } 
+                                                else if right_link.0 == curr_state.node { SwitchPosition::Right }
+                                                else {
+                                                    return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw))); };
+                                    curr_state.switches.push((sw,curr_state.length,pos));
+                                }  else {
+                                    return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw)));
+                                }
+
+                            },
+                            _ => {},
+                        };
+                        curr_state.edges_taken.push((curr_state.node, other));
+                        curr_state.node = model.nodes[other].other_node;
+                        curr_state.length += d;
+                    },
+                    Edges::Switchable(sw) => {
+                        if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
+                            let mut right_state = curr_state.clone();
+                            let mut left_state = curr_state;
+
+                            right_state.edges_taken.push((right_state.node, right_link.0));
+                            left_state.edges_taken.push((left_state.node, left_link.0));
+                            right_state.node = model.nodes[right_link.0].other_node;
+                            left_state.node = model.nodes[left_link.0].other_node;
+                            right_state.switches.push((sw, right_state.length, SwitchPosition::Right));
+                            left_state.switches.push((sw, left_state.length, SwitchPosition::Left));
+                            right_state.length += right_link.1;
+                            left_state.length += left_link.1;
+
+                            if switches_path_visited.insert(
+                                right_state.switches.iter().map(|(sw,_l,pos)| (*sw,*pos)).collect()) {
+                                search_stack.push(right_state);
+                            }
+                            if switches_path_visited.insert(
+                                left_state.switches.iter().map(|(sw,_l,pos)| (*sw,*pos)).collect()) {
+                                search_stack.push(left_state);
+                            }
+
+                            break;
+                        } else {
+                            return Err(ConvertRouteError::String(format!("Switch misconfigured {}", sw)));
+                        }
+                    },
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
 
-pub fn make_route(config: &Config, state :&Path, entry :RouteEntryExit, exit: RouteEntryExit) 
+pub fn make_route(config: &Config, model :&StaticInfrastructure, class :RouteClass,
+                  state :&Path, entry :RouteEntryExit, exit: RouteEntryExit) 
         -> Result<Route, ConvertRouteIssue> {
 
     if state.length < config.route_minimum_length {
@@ -234,8 +302,10 @@ pub fn make_route(config: &Config, state :&Path, entry :RouteEntryExit, exit: Ro
         _ => entry,
     };
     
-    let add_length = if let RouteEntryExit::Boundary(_) = &exit { 1000.0 } 
-    else { 0.0 };
+    let add_length = match &exit {
+        RouteEntryExit::Boundary(_) => 1000.0,
+        _ => 0.0,
+    };
 
     let mut cleared_length = 0.0;
     let mut releases = sections.iter().map(|(trigger, start, end, node)| {
@@ -264,19 +334,157 @@ pub fn make_route(config: &Config, state :&Path, entry :RouteEntryExit, exit: Ro
 
     if let Some(last_release) = releases.last_mut() { last_release.length += add_length; }
 
+    let mut sections = sections.into_iter().map(|(x,_,_,end_node)| (x,end_node)).collect::<Vec<_>>();
+    // Shunting routes do not reserve the section they end in, 
+    // so that they can lead into occupied track.
+    if class == RouteClass::Shunting && sections.len() > 1 && sections[sections.len()-1].1.is_none() {
+        let (last,_) = sections.pop().unwrap();
+        if let Some(last_release) = releases.last_mut() {
+            last_release.resources.retain(|x| *x != last);
+        }
+    }
+
     Ok(Route {
         entry: entry,
         exit: exit,
         length: state.length + add_length,
 
         resources: RouteResources {
-            sections: sections.into_iter().map(|(x,_,_,_)| x).collect(),
+            sections: sections.into_iter().map(|(x,_)| x).collect(),
             switch_positions: state.switches.iter().map(|(x,_,s)| (*x,*s)).collect(),
             releases: releases.into(),
         },
 
-        overlaps: SmallVec::new(),
+        overlaps: match (class, &exit) {
+            (RouteClass::Train, RouteEntryExit::Signal(_)) => find_overlaps(config, model, state).into(),
+            _ => SmallVec::new(),
+        },
         swinging_overlap: false,
     })
 }
 
+/// Overlaps beyond the exit signal at the end of the path, one for each way 
+/// through the facing switches within the overlap length. Overlaps are
+/// shortened by buffer stops and model boundaries.
+pub fn find_overlaps(config :&Config, model :&StaticInfrastructure, state :&Path) -> Vec<Overlap> {
+    let mut overlaps = Vec::new();
+    if !(config.overlap_length > 0.0) { return overlaps; }
+
+    // Sections entered right at the exit signal belong to the overlap, 
+    // see the section tolerance in `make_route`.
+    let sections :Vec<ObjectId> = state.entered_sections.iter()
+        .filter(|(_,l)| state.length - l <= config.section_tolerance)
+        .map(|(s,_)| *s).collect();
+    let mut stack :Vec<(NodeId, f64, Vec<ObjectId>, Vec<(ObjectId,SwitchPosition)>, bool)> 
+        = vec![(state.node, 0.0, sections, Vec::new(), true)];
+
+    while let Some((mut node, mut length, mut sections, mut switches, mut first)) = stack.pop() {
+        loop {
+            // The objects at the exit signal node are already visited by the route search.
+            if !first {
+                for obj_idx in model.nodes[node].objects.iter() {
+                    if let StaticObject::TVDLimit { enter: Some(s), .. } = &model.objects[*obj_idx] {
+                        if length < config.overlap_length && !sections.contains(s) { sections.push(*s); }
+                    }
+                }
+            }
+            first = false;
+
+            let end = length >= config.overlap_length || 
+                matches!(model.nodes[node].edges, Edges::Nothing | Edges::ModelBoundary);
+            if end {
+                overlaps.push(Overlap {
+                    name: None,
+                    sections: sections.into_iter().collect(),
+                    switch_positions: switches.into_iter().collect(),
+                    timeout: None,
+                });
+                break;
+            }
+
+            match model.nodes[node].edges {
+                Edges::Single(other, d) => {
+                    // Trailing switches in the overlap must be set for the overlap.
+                    if let Edges::Switchable(sw) = model.nodes[other].edges {
+                        if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
+                            if left_link.0 == node { switches.push((sw, SwitchPosition::Left)); }
+                            else if right_link.0 == node { switches.push((sw, SwitchPosition::Right)); }
+                        }
+                    }
+                    node = model.nodes[other].other_node;
+                    length += d;
+                },
+                Edges::Switchable(sw) => {
+                    if let Some(StaticObject::Switch { left_link, right_link, .. }) = model.objects.get(sw) {
+                        for (link,pos) in &[(left_link, SwitchPosition::Left), (right_link, SwitchPosition::Right)] {
+                            let mut switches = switches.clone();
+                            switches.push((sw, *pos));
+                            stack.push((model.nodes[link.0].other_node, length + link.1, 
+                                        sections.clone(), switches, false));
+                        }
+                    }
+                    break;
+                },
+                Edges::Nothing | Edges::ModelBoundary => { break; },
+            }
+        }
+    }
+
+    overlaps
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(other_node :NodeId, edges :Edges, objects :&[ObjectId]) -> Node {
+        let mut n = Node { other_node, edges, objects: Default::default() };
+        for o in objects { n.objects.push(*o); }
+        n
+    }
+
+    /// A signal 20 m before a facing switch, and 30 m from the switch to the 
+    /// model boundary on each side.
+    fn facing_switch_model() -> StaticInfrastructure {
+        StaticInfrastructure {
+            objects: vec![
+                StaticObject::Signal { has_distant: false },
+                StaticObject::Switch { left_link: (6, 30.0), right_link: (8, 30.0), 
+                                       branch_side: SwitchPosition::Left },
+            ],
+            nodes: vec![
+                node(1, Edges::ModelBoundary, &[]),
+                node(0, Edges::Single(2, 100.0), &[]),
+                node(3, Edges::Single(1, 100.0), &[]),
+                node(2, Edges::Single(4, 20.0), &[0]),
+                node(5, Edges::Single(3, 20.0), &[]),
+                node(4, Edges::Switchable(1), &[]),
+                node(7, Edges::Single(5, 30.0), &[]),
+                node(6, Edges::ModelBoundary, &[]),
+                node(9, Edges::Single(5, 30.0), &[]),
+                node(8, Edges::ModelBoundary, &[]),
+            ],
+        }
+    }
+
+    fn overlaps_to_signal(config :Config) -> Vec<Overlap> {
+        let (routes,_) = find_routes(config, &facing_switch_model(), &HashSet::new()).unwrap();
+        let (route,_,_) = routes.into_iter()
+            .find(|(r,_,_)| matches!(r.exit, RouteEntryExit::Signal(0))).unwrap();
+        route.overlaps.into_iter().collect()
+    }
+
+    #[test]
+    fn overlap_across_facing_switch() {
+        let overlaps = overlaps_to_signal(Config::default());
+        assert_eq!(overlaps.len(), 2);
+        let positions = overlaps.iter()
+            .map(|o| o.switch_positions.iter().cloned().collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert!(positions.contains(&vec![(1, SwitchPosition::Left)]));
+        assert!(positions.contains(&vec![(1, SwitchPosition::Right)]));
+
+        let overlaps = overlaps_to_signal(Config { overlap_length: 0.0, ..Default::default() });
+        assert!(overlaps.is_empty());
+    }
+}
@@ -33,9 +33,12 @@ pub struct Windows {
     pub log: bool,
     pub quit: bool,
     pub vehicles: bool,
+    pub settings: bool,
+    pub issues: bool,
     pub diagram_split :Option<f32>,
     pub import_window :import::ImportWindow,
     pub synthesis_window :Option<gui::windows::synthesis::SynthesisWindow>,
+    pub route_table :gui::windows::routetable::RouteTableWindow,
 }
 
 impl Windows {
@@ -46,11 +49,14 @@ impl Windows {
             log: false,
             quit: false,
             vehicles: false,
+            settings: false,
+            issues: false,
 
             diagram_split: None,
 
             import_window: import::ImportWindow::new(bg),
             synthesis_window: None,
+            route_table: gui::windows::routetable::RouteTableWindow::new(),
         }
     }
 }
@@ -91,6 +91,7 @@ lazy_static! {
                 RailUIColorName::GraphCommandTrain => const_cstr!("Graph command train"),
                 RailUIColorName::GraphCommandError => const_cstr!("Graph command error"),
                 RailUIColorName::GraphCommandBorder => const_cstr!("Graph command border"),
+                RailUIColorName::GraphGradient => const_cstr!("Graph gradient"),
         }
     };
 }
@@ -271,6 +272,7 @@ pub fn default_colors() -> EnumMap<RailUIColorName, Color> {
         RailUIColorName::GraphCommandTrain => c(named::AZURE),
         RailUIColorName::GraphCommandError => c(named::RED),
         RailUIColorName::GraphCommandBorder => c(named::BLACK),
+        RailUIColorName::GraphGradient => c(named::DARKKHAKI),
     }
 }
 
@@ -310,6 +312,7 @@ pub enum RailUIColorName {
     GraphCommandTrain,
     GraphCommandError,
     GraphCommandBorder,
+    GraphGradient,
 }
 
 #[test]
@@ -14,6 +14,7 @@ use crate::util;
 use crate::util::VecMap;
 use crate::document::dispatch;
 use crate::document::plan;
+use crate::document::issues::*;
 use std::sync::Arc;
 use nalgebra_glm as glm;
 
@@ -27,6 +28,10 @@ pub struct AnalysisOutput {
     pub dispatch :Vec<Option<(Generation, dispatch::DispatchOutput)>>,
     //pub plandispatches :HashMap<usize, Vec<Option<(Generation, dispatch::DispatchOutput)>>>,
     pub plandispatches :Vec<Option<(Generation, Vec<dispatch::DispatchOutput>)>>,
+    /// Reasons why plans have no dispatches, see `plan::diagnose_plan`.
+    pub plandiagnosis :Vec<Option<(Generation, Vec<plan::PlanDiagnosis>)>>,
+    /// Issues found when analyzing the current model.
+    pub issues :Vec<Issue>,
 }
 
 pub struct Analysis {
@@ -43,6 +48,8 @@ pub enum SetData {
     Interlocking(Generation, Arc<interlocking::Interlocking>),
     Dispatch(Generation, usize,dispatch::DispatchOutput),
     PlanDispatch(Generation, usize,Vec<dispatch::DispatchOutput>),
+    PlanDiagnosis(Generation, usize, Vec<plan::PlanDiagnosis>),
+    Issues(Generation, Vec<Issue>),
 }
 
 impl app::BackgroundUpdates for Analysis {
@@ -61,6 +68,10 @@ impl app::BackgroundUpdates for Analysis {
                         //.vecmap_insert(dispatch_idx, (g, h));
                     self.output.plandispatches.vecmap_insert(plan_idx, (g,hs));
                 },
+                SetData::PlanDiagnosis(g, plan_idx, diagnosis) => {
+                    self.output.plandiagnosis.vecmap_insert(plan_idx, (g, diagnosis));
+                },
+                SetData::Issues(_g, issues) => { self.output.issues.extend(issues); },
             }
         }
     }
@@ -71,7 +82,8 @@ impl Analysis {
     pub fn data(&self) -> &AnalysisOutput { &self.output }
     pub fn generation(&self) -> &Generation { &self.model_generation }
 
-    pub fn from_model(model :Model, bg: app::BackgroundJobs) -> Self {
+    pub fn from_model(mut model :Model, bg: app::BackgroundJobs) -> Self {
+        model.assign_names();
         let mut a = Analysis {
             model: Undoable::from(model),
             model_generation: 0,
@@ -87,7 +99,16 @@ impl Analysis {
         let model = self.model.get().clone(); // persistent structs
         let gen = self.model_generation;
 
-        let topology = Arc::new(topology::convert(&model, 50.0).unwrap());
+        self.output.issues.clear();
+        let topology = match topology::convert(&model, DEFAULT_LINESEG_LENGTH) {
+            Ok(t) => Arc::new(t),
+            Err(_) => {
+                error!("Topology conversion failed");
+                self.output.issues.push(Issue::new(format!("Topology conversion failed."), None));
+                self.chan = None;
+                return;
+            },
+        };
         self.output.topology = Some((gen,topology.clone()));
 
         let (tx,rx) = channel();
@@ -98,55 +119,13 @@ impl Analysis {
             let model = model;  // move model into thread
             let tx = tx;        // move sender into thread
 
-            //let dgraph = dgraph::calc(&model); // calc dgraph from model.
-            let dgraph = DGraphBuilder::convert(&topology).expect("dgraph conversion failed");
-            let dgraph = Arc::new(dgraph);
-
-            info!("Dgraph successful with {:?} nodes", dgraph.rolling_inf.nodes.len());
-
-            let send_ok = tx.send(SetData::DGraph(gen, dgraph.clone()));
-            if !send_ok.is_ok() { println!("job canceled after dgraph"); return; }
             // if tx fails (channel is closed), we don't need 
             // to proceed to next step. Also, there is no harm
             // in *trying* to send the data from an obsolete thread,
             // because the update function will have replaced its 
             // receiver end of the channel, so it will anyway not
             // be placed into the struct.
-
-            let interlocking = interlocking::calc(&dgraph); 
-            let interlocking = Arc::new(interlocking);
-                // calc interlocking from dgraph
-            let send_ok = tx.send(SetData::Interlocking(gen, interlocking.clone()));
-            if !send_ok.is_ok() { println!("job canceled after interlocking"); return; }
-            info!("Interlocking successful with {:?} routes", interlocking.routes.len());
-
-            for (i,dispatch) in model.dispatches.iter() {
-                //let history = dispatch::run(&dgraph, &interlocking, &dispatch);
-                let (history,route_refs) = history::get_history(model.vehicles.data(),
-                                                   &dgraph.rolling_inf,
-                                                   &interlocking,
-                                                   &(dispatch.commands)).unwrap();
-                info!("Simulation successful {:?}", &dispatch.commands);
-                let view = dispatch::DispatchOutput::from_history(dispatch.clone(), &dgraph, history);
-                let send_ok = tx.send(SetData::Dispatch(gen, *i, view));
-                if !send_ok.is_ok() { println!("job canceled after dispatch"); return; }
-            }
-
-            for (plan_idx,plan) in model.plans.iter() {
-                let planresults = plan::get_dispatches(&dgraph, &interlocking,
-                                             model.vehicles.data(),
-                                             plan).unwrap();
-
-                info!("Planning successful. {:?}", planresults);
-
-                let dispatches = planresults.into_iter().map(|(d,h)| {
-                    dispatch::DispatchOutput::from_history(d, &dgraph, h)
-                }).collect();
-
-                let send_ok = tx.send(SetData::PlanDispatch(gen, *plan_idx, dispatches));
-                if !send_ok.is_ok() { println!("job cancelled after plan dispatch {}", plan_idx); }
-            }
-
+            run_pipeline(&model, &topology, gen, |data| tx.send(data).is_ok());
         });
     }
 
@@ -156,8 +135,10 @@ impl Analysis {
         self.set_model(new_model, cl);
     }
 
-    pub fn set_model(&mut self, m :Model, cl :Option<EditClass>) {
+    pub fn set_model(&mut self, mut m :Model, cl :Option<EditClass>) {
         info!("Updating model");
+        m.assign_names();
+        m.relink_named_refs(self.model.get());
         self.model.set(m, cl);
         self.on_changed();
     }
@@ -241,3 +222,103 @@ impl Analysis {
 
 }
 
+/// Derive the dgraph, interlocking, dispatch histories and plan dispatches 
+/// from the model and its topology. Each result is given to `send` as soon as 
+/// it is ready, and the pipeline stops when `send` returns false.
+/// Used by the background thread of `Analysis` and by the headless runner.
+pub fn run_pipeline(model :&Model, topology :&topology::Topology, gen :Generation,
+                    mut send :impl FnMut(SetData) -> bool) {
+    //let dgraph = dgraph::calc(&model); // calc dgraph from model.
+    let dgraph = match DGraphBuilder::convert(topology) {
+        Ok(dgraph) => Arc::new(dgraph),
+        Err(_) => {
+            error!("Dgraph conversion failed");
+            send(SetData::Issues(gen, vec![Issue::new(format!("Dgraph conversion failed."), None)]));
+            return;
+        },
+    };
+
+    info!("Dgraph successful with {:?} nodes", dgraph.rolling_inf.nodes.len());
+
+    if !send(SetData::DGraph(gen, dgraph.clone())) { info!("job canceled after dgraph"); return; }
+
+    let issues = dgraph.sight_truncations.iter().map(|(signal, sight, dist)| {
+        let signal = Ref::Object(*signal);
+        Issue::new(format!("Sight distance to signal {} is shortened from {:.0} m to {:.0} m by a facing switch.",
+                           model.ref_label(&signal), sight, dist),
+                   Some(IssueLocation::Infrastructure(signal)))
+    }).collect::<Vec<_>>();
+    if issues.len() > 0 {
+        if !send(SetData::Issues(gen, issues)) { info!("job canceled after sight issues"); return; }
+    }
+
+    let (interlocking, route_issues) = match interlocking::calc(&dgraph, &model.settings.routes) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Interlocking failed: {}", e);
+            send(SetData::Issues(gen, vec![Issue::new(e, None)]));
+            return;
+        },
+    };
+    let interlocking = Arc::new(interlocking);
+        // calc interlocking from dgraph
+    if !send(SetData::Interlocking(gen, interlocking.clone())) { info!("job canceled after interlocking"); return; }
+    info!("Interlocking successful with {:?} routes", interlocking.routes.len());
+
+    let issues = route_issues.iter()
+        .map(|i| Issue::from_route_issue(model, &dgraph, i)).collect::<Vec<_>>();
+    if issues.len() > 0 {
+        if !send(SetData::Issues(gen, issues)) { info!("job canceled after route issues"); return; }
+    }
+
+    for (i,dispatch) in model.dispatches.iter() {
+        //let history = dispatch::run(&dgraph, &interlocking, &dispatch);
+        let history = history::get_history(model.vehicles.data(),
+                                           &dgraph,
+                                           &interlocking,
+                                           &(dispatch.commands));
+        let (history,_route_refs) = match history {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Simulation of dispatch {} failed: {}", i, e);
+                let issue = Issue::new(format!("Simulation of dispatch \"{}\" failed: {}", 
+                                               dispatch.name, e),
+                                       Some(IssueLocation::Dispatch(*i)));
+                if !send(SetData::Issues(gen, vec![issue])) { info!("job canceled after dispatch"); return; }
+                continue;
+            },
+        };
+        info!("Simulation successful {:?}", &dispatch.commands);
+        let view = dispatch::DispatchOutput::from_history(dispatch.clone(), &dgraph, history);
+        if !send(SetData::Dispatch(gen, *i, view)) { info!("job canceled after dispatch"); return; }
+    }
+
+    for (plan_idx,plan) in model.plans.iter() {
+        let (planresults, diagnosis) = match plan::get_dispatches(&dgraph, &interlocking,
+                                     model.vehicles.data(),
+                                     plan) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("Planning of plan {} failed: {}", plan_idx, e);
+                let issue = Issue::new(format!("Planning of \"{}\" failed: {}", plan.name, e),
+                                       Some(IssueLocation::Plan(*plan_idx)));
+                if !send(SetData::Issues(gen, vec![issue])) { info!("job cancelled after plan {}", plan_idx); return; }
+                continue;
+            },
+        };
+
+        info!("Planning successful. {:?}", planresults);
+
+        if !send(SetData::PlanDiagnosis(gen, *plan_idx, diagnosis)) { 
+            info!("job cancelled after plan diagnosis {}", plan_idx); return; 
+        }
+
+        let dispatches = planresults.into_iter().map(|(d,h)| {
+            dispatch::DispatchOutput::from_history(d, &dgraph, h)
+        }).collect();
+
+        if !send(SetData::PlanDispatch(gen, *plan_idx, dispatches)) { 
+            info!("job cancelled after plan dispatch {}", plan_idx); return; 
+        }
+    }
+}
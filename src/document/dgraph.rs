@@ -6,6 +6,7 @@ use crate::document::model::*;
 use crate::document::objects::*;
 use crate::document::topology::*;
 use crate::document::mileage;
+use crate::util;
 use matches::matches;
 use nalgebra_glm as glm;
 
@@ -21,11 +22,28 @@ pub struct DGraph {
     pub switch_ids :BiMap<rolling_inf::ObjectId, Pt>,
     pub object_ids :BiMap<rolling_inf::ObjectId, PtA>,
     pub detector_ids :BiMap<rolling_inf::NodeId, PtA>,
+    /// Signals which only give shunting routes, see `route_finder::RouteClass`.
+    pub shunting_signals :HashSet<rolling_inf::ObjectId>,
+    /// Signals at buffer stops, which are always at stop, where shunting routes end.
+    /// These are also in `shunting_signals`.
+    pub buffer_stop_signals :HashMap<rolling_inf::ObjectId, Pt>,
     pub tvd_edges :HashMap<rolling_inf::ObjectId, Vec<(rolling_inf::NodeId, rolling_inf::NodeId)>>,
     pub tvd_entry_nodes :HashMap<rolling_inf::ObjectId, Vec<rolling_inf::NodeId>>,
     pub edge_lines :HashMap<(rolling_inf::NodeId, rolling_inf::NodeId), Vec<PtC>>,
+    /// Mean gradient (per mille) of each edge, positive when rising in the edge's direction.
+    pub edge_gradients :HashMap<(rolling_inf::NodeId, rolling_inf::NodeId), f64>,
+    /// Mileage of nodes, continuous through chainage breaks, used for drawing. 
+    /// See `DGraph::chainage` for the chainage of the railway line.
     pub mileage :HashMap<rolling_inf::NodeId, f64>,
+    /// Chainage minus mileage of each node, changing at the chainage breaks.
+    /// See `DGraph::node_chainage`.
+    pub chainage_offsets :HashMap<rolling_inf::NodeId, f64>,
+    /// Chainage breaks as (mileage, chainage before, chainage after), sorted by mileage.
+    pub mileage_breaks :Vec<(f64,f64,f64)>,
     pub all_paths :(f64, Vec<allpaths::Path>),
+    /// Signals whose sight distance is shortened by a facing switch, 
+    /// as (signal, sight distance, shortened sight distance).
+    pub sight_truncations :Vec<(PtA, f64, f64)>,
 }
 
 
@@ -36,6 +54,87 @@ impl DGraph {
         let km_b = *self.mileage.get(&b)?;
         Some(glm::lerp_scalar(km_a,km_b,param))
     }
+
+    /// Chainage of a node, taking the chainage breaks on the way to the node into account.
+    pub fn node_chainage(&self, node :rolling_inf::NodeId) -> Option<f64> {
+        let km = self.mileage.get(&node)?;
+        Some(km + self.chainage_offsets.get(&node).cloned().unwrap_or(0.0))
+    }
+
+    /// Chainage at the given (continuous) mileage along the diagram's mileage axis, 
+    /// where all the chainage breaks are applied. Use `node_chainage` for locations
+    /// in the infrastructure, as breaks only apply to the tracks they are on.
+    pub fn chainage(&self, km :f64) -> f64 {
+        match self.mileage_breaks.iter().rev().find(|(pos,_,_)| *pos <= km) {
+            Some((pos,_,after)) => after + (km - pos),
+            None => match self.mileage_breaks.first() {
+                Some((pos,before,_)) => before + (km - pos),
+                None => km,
+            },
+        }
+    }
+
+    /// Edges, in both directions, of the track passing a point in the drawing.
+    pub fn edges_at(&self, p :PtC) -> Vec<(rolling_inf::NodeId, rolling_inf::NodeId)> {
+        let tolerance = 0.1;
+        self.edge_lines.iter()
+            .filter(|(_,line)| line.iter().zip(line.iter().skip(1))
+                    .any(|(a,b)| util::dist_to_line_sqr(p, *a, *b).0 < tolerance*tolerance))
+            .map(|(edge,_)| *edge).collect()
+    }
+
+    /// Chainage of a node, switch or crossing in the drawing.
+    pub fn location_mileage(&self, pt :&Pt) -> Option<f64> {
+        if let Some(node) = self.node_ids.get_by_right(pt) {
+            return self.node_chainage(*node);
+        }
+        let sw = self.switch_ids.get_by_right(pt)?;
+        self.node_chainage(node_with_object(&self.rolling_inf, *sw)?)
+    }
+
+    /// The signal object in the drawing, or the buffer stop for signals at buffer stops.
+    pub fn signal_ref(&self, signal :rolling_inf::ObjectId) -> Option<Ref> {
+        self.object_ids.get_by_left(&signal).map(|pta| Ref::Object(*pta))
+            .or_else(|| self.buffer_stop_signals.get(&signal).map(|pt| Ref::Node(*pt)))
+    }
+
+    /// The boundary, buffer stop or signal in the drawing at a route's entry or exit.
+    pub fn entry_exit_ref(&self, e :&rolling_inf::RouteEntryExit) -> Option<Ref> {
+        match e {
+            rolling_inf::RouteEntryExit::Boundary(Some(node)) =>
+                self.node_ids.get_by_left(node).map(|pt| Ref::Node(*pt)),
+            rolling_inf::RouteEntryExit::Signal(signal) |
+            rolling_inf::RouteEntryExit::SignalTrigger { signal, .. } => self.signal_ref(*signal),
+            rolling_inf::RouteEntryExit::Boundary(None) => None,
+        }
+    }
+
+    /// Detectors and boundaries at the ends of a train detection section.
+    pub fn section_limits(&self, section :rolling_inf::ObjectId) -> Vec<Ref> {
+        let mut limits = Vec::new();
+        for node in self.tvd_entry_nodes.get(&section).into_iter().flatten() {
+            let limit = self.detector_ids.get_by_left(node).map(|pta| Ref::Object(*pta))
+                .or_else(|| self.node_ids.get_by_left(node).map(|pt| Ref::Node(*pt)));
+            if let Some(limit) = limit {
+                if !limits.contains(&limit) { limits.push(limit); }
+            }
+        }
+        limits
+    }
+
+    /// Chainage of a trackside object in the drawing.
+    pub fn object_mileage(&self, pta :&PtA) -> Option<f64> {
+        if let Some(node) = self.detector_ids.get_by_right(pta) {
+            return self.node_chainage(*node);
+        }
+        let obj = self.object_ids.get_by_right(pta)?;
+        self.node_chainage(node_with_object(&self.rolling_inf, *obj)?)
+    }
+}
+
+fn node_with_object(inf :&rolling_inf::StaticInfrastructure, obj :rolling_inf::ObjectId) -> Option<rolling_inf::NodeId> {
+    inf.nodes.iter().position(|n| n.objects.contains(&obj) ||
+                                   matches!(n.edges, rolling_inf::Edges::Switchable(o) if o == obj))
 }
 
 pub fn edge_length(rolling_inf :&rolling_inf::StaticInfrastructure, a :rolling_inf::NodeId, b: rolling_inf::NodeId) -> Option<f64> {
@@ -55,6 +154,9 @@ pub fn edge_length(rolling_inf :&rolling_inf::StaticInfrastructure, a :rolling_i
 pub struct DGraphBuilder {
     dgraph :rolling_inf::StaticInfrastructure,
     edge_tracks :HashMap<(rolling_inf::NodeId, rolling_inf::NodeId), Interval>,
+    /// Speed changes from switch and crossing speed restrictions, as opposed
+    /// to speed restriction signs.
+    restriction_objects :HashSet<rolling_inf::ObjectId>,
 }
 
 #[derive(Debug)]
@@ -75,11 +177,14 @@ impl DGraphBuilder {
         // Create signals objects separately (they are not actually part of the "geographical" 
         // infrastructure network, they are merely pieces of state referenced by sight objects)
         let mut static_signals :HashMap<PtA, rolling_inf::ObjectId> = HashMap::new();
-        let mut signal_cursors : HashMap<PtA, Cursor> = HashMap::new();
+        let mut signal_cursors : HashMap<PtA, (Cursor, f64)> = HashMap::new();
         let mut detector_nodes : HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)> = HashSet::new();
         let mut object_ids = BiMap::new();
         let mut detector_ids = BiMap::new();
-        let (node_ids, switch_ids, crossing_edges) = m.create_network(
+        let mut shunting_signals = HashSet::new();
+        let mut mileage_anchors :HashMap<rolling_inf::NodeId, f64> = HashMap::new();
+        let mut mileage_breaks :HashMap<rolling_inf::NodeId, (f64,f64)> = HashMap::new();
+        let (node_ids, switch_ids, crossing_edges, location_nodes, buffer_stop_signals) = m.create_network(
             tracks, &locs, 
             |track_idx,mut cursor,dg| {
                 let mut last_pos = 0.0;
@@ -92,6 +197,10 @@ impl DGraphBuilder {
 
                     cursor = dg.insert_node_pair(cursor);
 
+                    if let (Cursor::Node(node), Some(km)) = (cursor, topology.mileage_anchors.get(&Ref::Object(id))) {
+                        mileage_anchors.insert(node, *km);
+                    }
+
                     match func {
                         Function::Detector => { 
                             let (a,b) = cursor.nodes(&dg.dgraph);
@@ -99,25 +208,52 @@ impl DGraphBuilder {
                             detector_ids.insert(a,id);
                             detector_ids.insert(b,id);
                         },
-                        Function::MainSignal { has_distant }=> { 
+                        Function::MainSignal { has_distant, sight }=> { 
                             let c = if matches!(dir,Some(AB::B)) { cursor.reverse(&dg.dgraph) } else { cursor };
-                            signal_cursors.insert(id,c); 
+                            signal_cursors.insert(id,(c, sight.unwrap_or(topology.sight_distance)));
 
                             let (_cursor, obj) = dg.insert_object(c, 
                                   rolling_inf::StaticObject::Signal { has_distant: has_distant });
                             static_signals.insert(id, obj);
                             object_ids.insert(obj, id);
                         },
+                        Function::ShuntingSignal { sight } => {
+                            let c = if matches!(dir,Some(AB::B)) { cursor.reverse(&dg.dgraph) } else { cursor };
+                            signal_cursors.insert(id,(c, sight.unwrap_or(topology.sight_distance)));
+
+                            let (_cursor, obj) = dg.insert_object(c, 
+                                  rolling_inf::StaticObject::Signal { has_distant: false });
+                            static_signals.insert(id, obj);
+                            object_ids.insert(obj, id);
+                            shunting_signals.insert(obj);
+                        },
+                        Function::SpeedRestriction { speed } => {
+                            let c = if matches!(dir,Some(AB::B)) { cursor.reverse(&dg.dgraph) } else { cursor };
+                            dg.insert_object(c, rolling_inf::StaticObject::SpeedChange(speed));
+                        },
+                        Function::MileageBreak { before, after } => {
+                            let (a,b) = cursor.nodes(&dg.dgraph);
+                            mileage_breaks.insert(a, (before, after));
+                            mileage_breaks.insert(b, (before, after));
+                        },
                     }
                     last_pos = pos;
                 }
             } );
 
+        // Signals at buffer stops only end shunting routes.
+        shunting_signals.extend(buffer_stop_signals.keys().cloned());
+
         // Sight to signals
-        for (id,cursor) in signal_cursors {
+        let mut sight_truncations = Vec::new();
+        for (id,(cursor,sight_dist)) in signal_cursors {
             let objid = static_signals[&id];
-            let sight_dist = 200.0; // TODO configurable
             for (cursor,dist) in cursor.reverse(&m.dgraph).advance_nontrailing_truncate(&m.dgraph, sight_dist) {
+                // Sight ending on an edge is truncated by a switch, while sight 
+                // ending on a node has reached the model boundary.
+                if dist < sight_dist && matches!(cursor, Cursor::Edge(_,_)) {
+                    sight_truncations.push((id, sight_dist, dist));
+                }
                 let cursor = cursor.reverse(&m.dgraph);
                 m.insert_object(cursor, rolling_inf::StaticObject::Sight{
                     distance: dist, signal: objid,
@@ -136,6 +272,13 @@ impl DGraphBuilder {
                                                                               &crossing_edges)
             .expect("could not calc tvd sections.");
 
+        let mut edge_gradients = HashMap::new();
+        for ((a,b),Interval { track_idx, start, end }) in m.edge_tracks.iter() {
+            let g = topology.mean_gradient(*track_idx, *start, *end);
+            edge_gradients.insert((*a,*b), g);
+            edge_gradients.insert((*b,*a), -g);
+        }
+
         let mut edge_lines :HashMap<(rolling_inf::NodeId, rolling_inf::NodeId), Vec<PtC>>
             = m.edge_tracks.into_iter()
             .map(|(edge,Interval { track_idx, start, end })| 
@@ -144,7 +287,16 @@ impl DGraphBuilder {
         let rev_edge_lines = edge_lines.iter().map(|((a,b),v)| ((*b,*a),{ let mut v= v.clone(); v.reverse(); v })).collect::<Vec<_>>();
         edge_lines.extend(rev_edge_lines.into_iter());
 
-        let mileage = mileage::auto(&node_ids, &m.dgraph);
+        for (r,km) in topology.mileage_anchors.iter() {
+            if let Ref::Node(pt) = r {
+                if let Some(node) = location_nodes.get(pt) { mileage_anchors.insert(*node, *km); }
+            }
+        }
+
+        let mileage_origins = topology.mileage_origins.iter()
+            .filter_map(|pt| node_ids.get_by_right(pt).cloned()).collect();
+        let (mileage, chainage_offsets, mileage_breaks) = mileage::auto(&node_ids, &m.dgraph, &mileage_origins,
+                                                      &mileage_anchors, &mileage_breaks);
         //println!("MILEAGES {:?}", mileage);
         //mileage::test_lsq();
         //let mileage = std::iter::empty().collect();
@@ -157,11 +309,17 @@ impl DGraphBuilder {
             switch_ids: switch_ids,
             object_ids: object_ids,
             detector_ids: detector_ids,
+            shunting_signals: shunting_signals,
+            buffer_stop_signals: buffer_stop_signals,
             tvd_edges: tvd_edges,
             tvd_entry_nodes: tvd_entry_nodes,
             edge_lines: edge_lines,
+            edge_gradients: edge_gradients,
             mileage: mileage,
+            chainage_offsets: chainage_offsets,
+            mileage_breaks: mileage_breaks,
             all_paths: all_paths,
+            sight_truncations: sight_truncations,
         })
 
     }
@@ -171,7 +329,7 @@ impl DGraphBuilder {
             nodes: Vec::new(), 
             objects: Vec::new(),
         };
-        DGraphBuilder { dgraph: model, edge_tracks: HashMap::new() }
+        DGraphBuilder { dgraph: model, edge_tracks: HashMap::new(), restriction_objects: HashSet::new() }
     }
 
     pub fn new_object(&mut self, obj :rolling_inf::StaticObject) -> rolling_inf::ObjectId {
@@ -186,6 +344,48 @@ impl DGraphBuilder {
         obj_id
     }
 
+    /// Speed restriction for trains leaving `node`, lasting until the end of the
+    /// edges out of `node`, i.e. to the next trackside object or node.
+    /// The simulator keeps a speed restriction until the whole train has passed 
+    /// the point where the restriction is lifted. The restriction is lifted to the
+    /// speed limit in force on the track, see `speed_in_force`.
+    fn restrict_edge_speed(&mut self, node :rolling_inf::NodeId, speed :f64) {
+        let obj = self.new_object_at(rolling_inf::StaticObject::SpeedChange(speed), node);
+        self.restriction_objects.insert(obj);
+        for (next,_) in out_edges(&self.dgraph, &node) {
+            let after = self.dgraph.nodes[next].other_node;
+            let limit = self.speed_in_force(after);
+            let obj = self.new_object_at(rolling_inf::StaticObject::SpeedChange(limit), after);
+            self.restriction_objects.insert(obj);
+        }
+    }
+
+    /// Speed limit given by the nearest speed restriction signs behind `node`, for
+    /// trains leaving `node`. If the tracks behind `node` have different limits, 
+    /// the lowest one is used.
+    fn speed_in_force(&self, node :rolling_inf::NodeId) -> f64 {
+        let mut limit = std::f64::INFINITY;
+        let mut stack = vec![node];
+        let mut visited = HashSet::new();
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node) { continue; }
+            let sign = self.dgraph.nodes[node].objects.iter()
+                .filter(|o| !self.restriction_objects.contains(o))
+                .filter_map(|o| match self.dgraph.objects[*o] {
+                    rolling_inf::StaticObject::SpeedChange(v) => Some(v),
+                    _ => None,
+                }).next();
+            match sign {
+                Some(v) => { limit = limit.min(v); },
+                None => {
+                    let back = self.dgraph.nodes[node].other_node;
+                    stack.extend(out_edges(&self.dgraph, &back).into_iter().map(|(prev,_)| prev));
+                },
+            }
+        }
+        limit
+    }
+
     pub fn new_node_pair(&mut self) -> (rolling_inf::NodeId, rolling_inf::NodeId) {
         let (i1,i2) = (self.dgraph.nodes.len(), self.dgraph.nodes.len() +1);
         self.dgraph.nodes.push(rolling_inf::Node { other_node: i2,
@@ -263,10 +463,13 @@ impl DGraphBuilder {
         mut each_track: impl FnMut(usize,Cursor,&mut Self)) -> 
         (BiMap<rolling_inf::NodeId, Pt>,
          BiMap<rolling_inf::ObjectId, Pt>,
-         HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)>) {
+         HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)>,
+         HashMap<Pt, rolling_inf::NodeId>,
+         HashMap<rolling_inf::ObjectId, Pt>) {
 
         let mut node_ids = BiMap::new();
         let mut switch_ids = BiMap::new();
+        let mut buffer_stop_signals = HashMap::new();
         let mut crossing_edges = HashSet::new();
         let mut ports :HashMap<(Pt,Port), rolling_inf::NodeId>  = HashMap::new();
         for (i,(len,a,b)) in tracks.iter().enumerate() {
@@ -282,7 +485,15 @@ impl DGraphBuilder {
 
         for (pt,(node,_)) in nodes.iter() {
             match node {
-                NDType::BufferStop => {},
+                NDType::BufferStop => {
+                    // Shunting routes end at a signal at the buffer stop, which is 
+                    // always at stop, so that they are not taken as leaving the model.
+                    let node = ports[&(*pt,Port::End)];
+                    node_ids.insert(node, *pt);
+                    let signal = self.new_object_at(
+                        rolling_inf::StaticObject::Signal { has_distant: false }, node);
+                    buffer_stop_signals.insert(signal, *pt);
+                },
                 NDType::OpenEnd => {
                     self.dgraph.nodes[ports[&(*pt, Port::End)]].edges =
                         rolling_inf::Edges::ModelBoundary;
@@ -292,7 +503,7 @@ impl DGraphBuilder {
                     self.connect_linear(ports[&(*pt, Port::ContA)],
                                         ports[&(*pt, Port::ContB)], 0.0);
                 },
-                NDType::Sw(side) => {
+                NDType::Sw(side,speed) => {
                     let sw_obj = self.new_object(rolling_inf::StaticObject::Switch {
                         left_link:  (ports[&(*pt,Port::Left)], 0.0),
                         right_link: (ports[&(*pt,Port::Right)], 0.0),
@@ -307,8 +518,21 @@ impl DGraphBuilder {
                         rolling_inf::Edges::Single(ports[&(*pt,Port::Trunk)], 0.0);
                     self.dgraph.nodes[ports[&(*pt, Port::Trunk)]].edges =
                         rolling_inf::Edges::Switchable(sw_obj);
+
+                    if let Some(speed) = speed {
+                        // The restriction covers the deviating track from the switch to
+                        // the next node or trackside object, in both directions.
+                        let deviating = ports[&(*pt, side.as_port())];
+                        let away = self.dgraph.nodes[deviating].other_node;
+                        // Facing movements onto the deviating track
+                        self.restrict_edge_speed(away, *speed);
+                        // Trailing movements from the deviating track
+                        for (approach,_) in out_edges(&self.dgraph, &away) {
+                            self.restrict_edge_speed(approach, *speed);
+                        }
+                    }
                 },
-                NDType::Crossing(type_) => {
+                NDType::Crossing(type_,speed) => {
                     let left_drivable  = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Left));
                     let right_drivable = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Right));
 
@@ -327,6 +551,15 @@ impl DGraphBuilder {
 
                             self.dgraph.nodes[ports[&(*pt, Port::Cross(*dir, 0))]].edges = rolling_inf::Edges::Switchable(sw_a);
                             self.dgraph.nodes[ports[&(*pt, Port::Cross(dir.other(), 1))]].edges = rolling_inf::Edges::Switchable(sw_b);
+
+                            if let Some(speed) = speed {
+                                // Separate node pair on the slip, restricting movements through 
+                                // the slip in both directions.
+                                let (na,nb) = self.split_edge(ports[&(*pt, Port::Cross(*dir, 0))],
+                                                              ports[&(*pt, Port::Cross(dir.other(), 1))], 0.0);
+                                self.restrict_edge_speed(na, *speed);
+                                self.restrict_edge_speed(nb, *speed);
+                            }
                         } else {
                             self.dgraph.nodes[ports[&(*pt, Port::Cross(*dir, 0))]].edges = 
                                 rolling_inf::Edges::Single(ports[&(*pt, Port::Cross(dir.other(), 0))], 0.0);
@@ -343,7 +576,8 @@ impl DGraphBuilder {
                 NDType::Err => {},
             }
         }
-        (node_ids, switch_ids, crossing_edges)
+        let location_nodes = ports.into_iter().map(|((pt,_),node)| (pt,node)).collect();
+        (node_ids, switch_ids, crossing_edges, location_nodes, buffer_stop_signals)
     }
 }
 
@@ -437,3 +671,28 @@ impl Cursor {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::model::test_models;
+
+    #[test]
+    fn chainage_break_on_own_track() {
+        // Two unconnected lines, with a chainage break on the first one.
+        let mut model = test_models::straight_line(10);
+        test_models::add_line(&mut model, 5, 0, 10);
+        test_models::add_object(&mut model, glm::vec2(5.0,0.0), 
+                                Function::MileageBreak { before: 100.0, after: 5000.0 });
+
+        let (_, dgraph) = test_models::dgraph(&model);
+        let chainage = |x,y| dgraph.location_mileage(&glm::vec2(x,y)).unwrap();
+        let close = |a :f64, b :f64| (a-b).abs() < 1e-3;
+
+        assert!(close(chainage(0,0), 100.0 - 250.0));
+        assert!(close(chainage(10,0), 5000.0 + 250.0));
+        // The break does not apply to the other line.
+        assert!(close(chainage(0,5), 0.0));
+        assert!(close(chainage(10,5), 500.0));
+    }
+}
@@ -24,8 +24,14 @@ pub struct DispatchOutput {
     pub diagram :Diagram, 
 }
 
-fn pos_range(diagram :&Diagram) -> (f32,f32) {
+/// Mileage interval covered by the trains and blocks in the diagram, or by the 
+/// whole infrastructure if there are none.
+fn pos_range(diagram :&Diagram, dgraph :&DGraph) -> (f32,f32) {
     let (mut pmin,mut pmax) = (std::f32::INFINITY, -std::f32::INFINITY);
+    for b in &diagram.blocks {
+        pmin = pmin.min(b.pos.0 as f32);
+        pmax = pmax.max(b.pos.1 as f32);
+    }
     for t in &diagram.trains {
         for seg in &t.segments {
             pmin = pmin.min(seg.kms[0] as f32);
@@ -38,6 +44,13 @@ fn pos_range(diagram :&Diagram) -> (f32,f32) {
             pmax = pmax.max(seg.end_kms[3] as f32);
         }
     }
+    if !(pmin <= pmax) {
+        for km in dgraph.mileage.values() {
+            pmin = pmin.min(*km as f32);
+            pmax = pmax.max(*km as f32);
+        }
+    }
+    if !(pmin <= pmax) { return (0.0, 1000.0); }
     (pmin,pmax)
 }
 
@@ -46,7 +59,7 @@ impl DispatchOutput {
         let t = max_time(&history) as f32;
         let instant = Instant::from(0.0, &history, dgraph);
         let diagram = Diagram::from(&history, dgraph);
-        let (pos1,pos2) = pos_range(&diagram);
+        let (pos1,pos2) = pos_range(&diagram, dgraph);
         DispatchOutput {
             dispatch,
             history: history,
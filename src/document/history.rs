@@ -1,8 +1,9 @@
-use rolling::input::staticinfrastructure as rolling_inf;
 pub use rolling::output::history::History;
+use std::collections::HashMap;
 
 use crate::document::model::*;
 use crate::document::interlocking::*;
+use crate::document::dgraph::*;
 
 pub fn convert_vehicle(vehicle :&Vehicle) -> rolling::railway::dynamics::TrainParams {
     rolling::railway::dynamics::TrainParams {
@@ -13,9 +14,67 @@ pub fn convert_vehicle(vehicle :&Vehicle) -> rolling::railway::dynamics::TrainPa
     }
 }
 
+/// Standard gravity, used for the gradient force on trains.
+const GRAVITY :f64 = 9.81;
+
+/// Lowest acceleration/braking a gradient can reduce a train to, so that
+/// steep gradients don't leave the train unable to move or stop.
+const MIN_GRADIENT_ADJUSTED_ACC :f64 = 0.05;
+
+type Edge = (rolling::input::staticinfrastructure::NodeId, rolling::input::staticinfrastructure::NodeId);
+
+/// Steepest climb and steepest descent (per mille, both positive) on the edges of a path.
+pub fn ruling_gradients(dgraph :&DGraph, path :&[Edge]) -> (f64, f64) {
+    let (mut climb, mut descent) = (0.0f64, 0.0f64);
+    for edge in path.iter() {
+        let gradient = dgraph.edge_gradients.get(edge).cloned().unwrap_or(0.0);
+        climb = climb.max(gradient);
+        descent = descent.max(-gradient);
+    }
+    (climb, descent)
+}
+
+/// Edges each train runs over, by the index of its train command: its entry route, 
+/// followed by the routes continuing from where its previous route ends. The commands 
+/// don't say which train a route is for, so each route is given to the train which 
+/// has waited longest at the route's entry, and is not followed by the other trains.
+fn train_paths(il :&Interlocking, commands :&[(usize, (f64, Command))]) -> HashMap<usize, Vec<Edge>> {
+    // Train command index, exit of the last route, index of the last command, and path.
+    let mut trains :Vec<(usize, Ref, usize, Vec<Edge>)> = Vec::new();
+    for (cmd_idx,(_,(_,c))) in commands.iter().enumerate() {
+        match c {
+            Command::Train(_, spec) => {
+                if let Some(route_idx) = il.find_route(spec) {
+                    trains.push((cmd_idx, spec.to, cmd_idx, il.routes[*route_idx].path.clone()));
+                }
+            },
+            Command::Route(spec) => {
+                let route_idx = match il.find_route(spec) { Some(r) => *r, None => continue };
+                let train = trains.iter_mut().filter(|(_,exit,_,_)| *exit == spec.from)
+                    .min_by_key(|(_,_,last_cmd,_)| *last_cmd);
+                if let Some((_, exit, last_cmd, path)) = train {
+                    path.extend(il.routes[route_idx].path.iter().cloned());
+                    *exit = spec.to;
+                    *last_cmd = cmd_idx;
+                }
+            },
+        }
+    }
+    trains.into_iter().map(|(cmd_idx,_,_,path)| (cmd_idx, path)).collect()
+}
+
+/// Adjust a train's acceleration for the gravity component on the steepest climb, and
+/// its braking for the gravity component on the steepest descent (per mille) on its path.
+pub fn adjust_for_gradient(mut params :rolling::railway::dynamics::TrainParams, climb :f64, descent :f64) 
+    -> rolling::railway::dynamics::TrainParams {
+    params.max_acc = (params.max_acc as f64 - GRAVITY * climb / 1000.0).max(MIN_GRADIENT_ADJUSTED_ACC) as _;
+    params.max_brk = (params.max_brk as f64 - GRAVITY * descent / 1000.0).max(MIN_GRADIENT_ADJUSTED_ACC) as _;
+    params
+}
+
 pub type RouteRefs = Vec<(f32,usize)>;
 pub fn get_history<'a>(vehicles :&[(usize,Vehicle)], 
-                   inf :&rolling_inf::StaticInfrastructure, 
+                   dgraph :&DGraph, 
                    il :&Interlocking,
                    commands :&[(usize, (f64, Command))]) -> Result<(History, RouteRefs) , String> {
 
@@ -27,9 +86,10 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
 
     let mut route_refs = Vec::new();
     let mut dispatch = Vec::new();
+    let train_paths = train_paths(il, commands);
     let mut t0 = 0.0;
     let mut train_no = 0;
-    for (cmd_id,(t,c)) in commands {
+    for (cmd_idx,(_cmd_id,(t,c))) in commands.iter().enumerate() {
         if *t > t0 {
             dispatch.push(DispatchAction::Wait(Some((t-t0) as _ )));
             t0 = *t;
@@ -53,7 +113,12 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
                         max_vel: 180.0 / 3.6, // 180 km/h in m/s
                     });
 
-                    let train_params = convert_vehicle(&vehicle);
+                    // The rolling train dynamics have constant acceleration and braking for 
+                    // each train, so we use the ruling gradients of the edges the train runs 
+                    // over, i.e. the steepest climb and descent, which don't cancel out.
+                    let (climb, descent) = train_paths.get(&cmd_idx)
+                        .map(|path| ruling_gradients(dgraph, path)).unwrap_or((0.0, 0.0));
+                    let train_params = adjust_for_gradient(convert_vehicle(&vehicle), climb, descent);
 
                     // just make some name for now
                     let name = format!("train{}", train_no+1);
@@ -73,7 +138,7 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
 
     // TODO don't convert on the fly?
     //println!("Starting rolling");
-    let history = rolling::evaluate_plan(inf,
+    let history = rolling::evaluate_plan(&dgraph.rolling_inf,
                                          &il.routes.iter().map(|r| r.route.clone()).enumerate().collect(),
                                          &rolling::input::dispatch::Dispatch { actions: dispatch }, None);
 
@@ -83,3 +148,59 @@ pub fn get_history<'a>(vehicles :&[(usize,Vehicle)],
 
     Ok((history,route_refs))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::model::test_models;
+    use crate::document::dispatch;
+    use nalgebra_glm as glm;
+
+    /// Main line from (0,0) to (20,0) with a switch at (8,0),
+    /// diverging to a line ending at (20,1).
+    fn switch_model() -> Model { test_models::switch_model(20, 8) }
+
+    /// Running time of a train entering at (0,0) and leaving the model at `exit`.
+    fn running_time(model :&Model, exit :Pt) -> f64 {
+        let (dgraph, il) = test_models::interlocking(model);
+        let route = RouteSpec { from: Ref::Node(glm::vec2(0,0)), to: Ref::Node(exit), alternative: 0 };
+        assert!(il.find_route(&route).is_some());
+        let commands = vec![(0, (0.0, Command::Train(0, route)))];
+        let (history,_) = get_history(&[], &dgraph, &il, &commands).unwrap();
+        dispatch::max_time(&history)
+    }
+
+    #[test]
+    fn diverging_speed_restriction() {
+        let mut model = switch_model();
+        let straight = running_time(&model, glm::vec2(20,0));
+        let diverging = running_time(&model, glm::vec2(20,1));
+
+        model.node_data.insert(glm::vec2(8,0), NDType::Sw(Side::Left, Some(10.0)));
+        let restricted_straight = running_time(&model, glm::vec2(20,0));
+        let restricted_diverging = running_time(&model, glm::vec2(20,1));
+
+        assert!((restricted_straight - straight).abs() < 1e-3);
+        assert!(restricted_diverging > diverging + 1.0);
+        assert!(restricted_diverging > restricted_straight);
+    }
+
+    #[test]
+    fn uphill_slows_train() {
+        let mut model = switch_model();
+        let flat = running_time(&model, glm::vec2(20,0));
+        for x in 2..10 { model.lineseg_gradients.insert((glm::vec2(x,0), glm::vec2(x+1,0)), 20.0); }
+        let uphill = running_time(&model, glm::vec2(20,0));
+        assert!(uphill > flat + 1.0);
+    }
+
+    #[test]
+    fn climb_and_descent_do_not_cancel() {
+        let mut model = switch_model();
+        let flat = running_time(&model, glm::vec2(20,0));
+        for x in 2..5 { model.lineseg_gradients.insert((glm::vec2(x,0), glm::vec2(x+1,0)), 20.0); }
+        for x in 5..8 { model.lineseg_gradients.insert((glm::vec2(x,0), glm::vec2(x+1,0)), -20.0); }
+        let hill = running_time(&model, glm::vec2(20,0));
+        assert!(hill > flat + 1.0);
+    }
+}
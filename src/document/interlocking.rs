@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use rolling::input::staticinfrastructure as rolling_inf;
 use crate::document::model::*;
 use crate::document::dgraph::*;
@@ -28,13 +28,30 @@ impl Interlocking {
 }
 
 
+pub use route_finder::RouteClass;
+
 #[derive(Debug)]
 pub struct RouteInfo {
     pub route :rolling_inf::Route,
     pub id :RouteSpec,
+    pub class :RouteClass,
+    /// Flank protection for the switches in the route, with the index of the release
+    /// of the protected switch, which holds the flank protection. The flank protection 
+    /// elements are also resources of the route, see `add_flank_resources`.
+    pub flank :Vec<(FlankProtection, usize)>,
     pub path :Vec<(rolling_inf::NodeId, rolling_inf::NodeId)>,
 }
 
+/// Flank protection element, which keeps movements on the other branch of a 
+/// switch in a route from running into the route.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FlankProtection {
+    /// Switch set away from the route.
+    Switch(rolling_inf::ObjectId, rolling_inf::SwitchPosition),
+    /// Signal facing the route, held at stop, so that no route can be set from it.
+    Signal(rolling_inf::ObjectId),
+}
+
 impl RouteInfo {
     pub fn start_mileage(&self, dgraph :&DGraph) -> Option<f64> {
         dgraph.mileage.get(&self.start_node()).cloned()
@@ -46,62 +63,246 @@ impl RouteInfo {
 }
 
 
-pub fn calc(dgraph :&DGraph) -> Interlocking {
-    let (routes,route_issues) = 
-        route_finder::find_routes(Default::default(), &dgraph.rolling_inf)
-        .expect("interlocking route finder failed");
+pub use route_finder::ConvertRouteIssue;
+
+/// Find the routes of the interlocking. Routes which could not be converted 
+/// are left out and reported as issues.
+pub fn calc(dgraph :&DGraph, settings :&RouteSettings) -> Result<(Interlocking, Vec<ConvertRouteIssue>), String> {
+    let (routes,mut route_issues) = 
+        route_finder::find_routes(settings.route_finder_config(), &dgraph.rolling_inf, &dgraph.shunting_signals)
+        .map_err(|e| format!("Route finder failed: {:?}", e))?;
 
     let mut boundary_routes = HashMap::new();
     let mut boundary_out_routes = HashMap::new();
     let mut signal_routes = HashMap::new();
     let mut route_info = Vec::new();
     let mut alternatives : HashMap<(Ref,Ref), Vec<usize>> = HashMap::new();
-    for (route_idx, (route,path)) in routes.into_iter().enumerate() {
-        let from = match route.entry {
-            rolling_inf::RouteEntryExit::Boundary(Some(boundary)) => {
-                // Boundary is a NodeId, which should be tied to a Pt in the Dgraph
-                if let Some(pt) = dgraph.node_ids.get_by_left(&boundary) {
-                    boundary_routes.entry(*pt).or_insert(Vec::new()).push(route_idx);
-                }
-
-                Ref::Node(*dgraph.node_ids.get_by_left(&boundary).unwrap())
+    for (mut route,path,class) in routes.into_iter() {
+        let route_idx = route_info.len();
+        // Routes from or to places which are not in the drawing are reported and left out.
+        let (from, to) = match (dgraph.entry_exit_ref(&route.entry), dgraph.entry_exit_ref(&route.exit)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => {
+                route_issues.push(ConvertRouteIssue::StateConversionFailed(route.entry, route.exit));
+                continue;
             },
-            rolling_inf::RouteEntryExit::Signal(signal) |
-            rolling_inf::RouteEntryExit::SignalTrigger { signal , .. } => {
-                if let Some(pta) = dgraph.object_ids.get_by_left(&signal) {
-                    signal_routes.entry(*pta).or_insert(Vec::new()).push(route_idx);
-                }
-
-                Ref::Object(*dgraph.object_ids.get_by_left(&signal).unwrap())
-            },
-            _ => panic!(), // TODO is Boundary(None)  relevant?
         };
 
-        let to = match route.exit {
-            rolling_inf::RouteEntryExit::Boundary(Some(boundary)) => {
-                if let Some(pt) = dgraph.node_ids.get_by_left(&boundary) {
-                    boundary_out_routes.entry(*pt).or_insert(Vec::new()).push(route_idx);
-                }
-                Ref::Node(*dgraph.node_ids.get_by_left(&boundary).unwrap())
-            },
-            rolling_inf::RouteEntryExit::Signal(signal) |
-            rolling_inf::RouteEntryExit::SignalTrigger { signal , .. } => {
-                Ref::Object(*dgraph.object_ids.get_by_left(&signal).unwrap())
-            },
-            _ => panic!(), // TODO is Boundary(None)  relevant?
-        };
+        match from {
+            Ref::Node(pt) => { boundary_routes.entry(pt).or_insert(Vec::new()).push(route_idx); },
+            Ref::Object(pta) => { signal_routes.entry(pta).or_insert(Vec::new()).push(route_idx); },
+            _ => {},
+        }
+
+        if let (RouteClass::Train, Ref::Node(pt)) = (class, to) {
+            boundary_out_routes.entry(pt).or_insert(Vec::new()).push(route_idx);
+        }
 
         let alternative_vec = alternatives.entry((from,to))
             .or_insert(Vec::new());
         alternative_vec.push(route_idx);
         let alternative = alternative_vec.len()-1;
 
-        route_info.push(RouteInfo { route, id: RouteSpec { from, to, alternative }, path });
+        let flank = route_flank_protection(&dgraph.rolling_inf, &route);
+        add_flank_resources(&mut route, &flank);
+
+        route_info.push(RouteInfo { route, id: RouteSpec { from, to, alternative }, class, flank, path });
     }
 
 
     let interlocking = Interlocking { routes: route_info, 
         boundary_routes, boundary_out_routes, signal_routes, alternatives };
 
-    interlocking
+    Ok((interlocking, route_issues))
+}
+
+/// Find the flank protection for the switches in the route, with the index of 
+/// the release of the protected switch. A flank protection element protecting 
+/// several switches is held until the last of them is released.
+fn route_flank_protection(inf :&rolling_inf::StaticInfrastructure, route :&rolling_inf::Route) -> Vec<(FlankProtection, usize)> {
+    let mut elements :Vec<(FlankProtection, usize)> = Vec::new();
+    let route_switches = &route.resources.switch_positions;
+    for (sw,pos) in route_switches.iter() {
+        let release = release_of_resource(route, *sw);
+        for element in flank_protection(inf, *sw, *pos) {
+            if let FlankProtection::Switch(flank_sw, _) = element {
+                // Switches in the route are set by the route itself.
+                if route_switches.iter().any(|(x,_)| *x == flank_sw) { continue; }
+            }
+            match elements.iter_mut().find(|(e,_)| *e == element) {
+                Some((_,r)) => { *r = (*r).max(release); },
+                None => { elements.push((element, release)); },
+            }
+        }
+    }
+    elements
+}
+
+/// Add the flank protection elements to the resources of the route, in the release 
+/// of the protected switch, so that the flank protection is held by the route in 
+/// both the simulation and the planner. Flank protection switches are set to their 
+/// protecting position. A route from a signal uses the signal as a resource, so that 
+/// it conflicts with the routes holding the signal at stop.
+/// Which uses of the same resource are compatible is decided in `plan::convert_inf`.
+fn add_flank_resources(route :&mut rolling_inf::Route, flank :&[(FlankProtection, usize)]) {
+    let resources = &mut route.resources;
+    for (element, release) in flank.iter() {
+        let id = match element {
+            FlankProtection::Switch(sw, pos) => {
+                resources.switch_positions.push((*sw, *pos));
+                *sw
+            },
+            FlankProtection::Signal(signal) => *signal,
+        };
+        if let Some(release) = resources.releases.get_mut(*release) {
+            release.resources.push(id);
+        }
+    }
+    match route.entry {
+        rolling_inf::RouteEntryExit::Signal(signal) |
+        rolling_inf::RouteEntryExit::SignalTrigger { signal, .. } => {
+            if let Some(release) = resources.releases.first_mut() {
+                release.resources.push(signal);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Index of the release (partial route) which frees the resource, or the last 
+/// release if the resource is not released on its own.
+fn release_of_resource(route :&rolling_inf::Route, resource :rolling_inf::ObjectId) -> usize {
+    route.resources.releases.iter().position(|r| r.resources.contains(&resource))
+        .unwrap_or(route.resources.releases.len().max(1) - 1)
+}
+
+/// Flank protection for a switch in the given position, found by searching 
+/// backwards from the switch along its other branch to the nearest signal facing 
+/// the switch or trailing switch which can be set away from it.
+fn flank_protection(inf :&rolling_inf::StaticInfrastructure, sw :rolling_inf::ObjectId,
+                    pos :rolling_inf::SwitchPosition) -> Vec<FlankProtection> {
+    use rolling_inf::{Edges, StaticObject, SwitchPosition};
+    let mut elements = Vec::new();
+    let (left_link, right_link) = match inf.objects.get(sw) {
+        Some(StaticObject::Switch { left_link, right_link, .. }) => (*left_link, *right_link),
+        _ => { return elements; },
+    };
+    let other_branch = match pos { SwitchPosition::Left => right_link, SwitchPosition::Right => left_link };
+
+    // Nodes facing towards the protected switch.
+    let mut stack = vec![other_branch.0];
+    let mut visited = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if !visited.insert(node) { continue; }
+        let signal = inf.nodes[node].objects.iter()
+            .find(|o| matches!(inf.objects[**o], StaticObject::Signal { .. }));
+        if let Some(signal) = signal {
+            elements.push(FlankProtection::Signal(*signal));
+            continue;
+        }
+
+        let away = inf.nodes[node].other_node;
+        match inf.nodes[away].edges {
+            Edges::Single(next, _) => {
+                match inf.nodes[next].edges {
+                    Edges::Switchable(flank_sw) => {
+                        // Trailing switch, set it to the other branch.
+                        if let Some(StaticObject::Switch { left_link, right_link, .. }) = inf.objects.get(flank_sw) {
+                            if left_link.0 == away {
+                                elements.push(FlankProtection::Switch(flank_sw, SwitchPosition::Right));
+                            } else if right_link.0 == away {
+                                elements.push(FlankProtection::Switch(flank_sw, SwitchPosition::Left));
+                            }
+                        }
+                    },
+                    _ => { stack.push(next); },
+                }
+            },
+            Edges::Switchable(facing_sw) => {
+                // Movements from both branches of a facing switch can reach the protected switch.
+                if let Some(StaticObject::Switch { left_link, right_link, .. }) = inf.objects.get(facing_sw) {
+                    stack.push(left_link.0);
+                    stack.push(right_link.0);
+                }
+            },
+            Edges::ModelBoundary | Edges::Nothing => {},
+        }
+    }
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::objects::*;
+    use crate::document::model::test_models::*;
+    use crate::document::plan;
+    use nalgebra_glm as glm;
+
+    fn route(il :&Interlocking, from :Ref, to :Ref) -> usize {
+        *il.find_route(&RouteSpec { from, to, alternative: 0 }).unwrap()
+    }
+
+    fn node(x :i32, y :i32) -> Ref { Ref::Node(glm::vec2(x,y)) }
+
+    /// Any partial route of route `a` conflicts with a partial route of route `b`.
+    fn conflicts(inf :&planner::input::Infrastructure, a :usize, b :usize) -> bool {
+        inf.partial_routes.iter().filter(|((r,_),_)| *r == a)
+            .any(|(_,pr)| pr.conflicts.iter().flatten().any(|((r,_),_)| *r == b))
+    }
+
+    #[test]
+    fn flank_switch_in_same_position() {
+        // Two parallel lines with a crossover from (3,0) to (4,1), 
+        // and a detection section on each side of the crossover.
+        let mut model = straight_line(10);
+        add_line(&mut model, 1, 0, 10);
+        model.linesegs.insert((glm::vec2(3,0), glm::vec2(4,1)));
+        for pt in &[glm::vec2(1.5,0.0), glm::vec2(6.5,0.0), glm::vec2(3.5,0.5),
+                    glm::vec2(1.5,1.0), glm::vec2(6.5,1.0)] {
+            add_object(&mut model, *pt, Function::Detector);
+        }
+
+        let (dgraph, il) = interlocking(&model);
+        let lower = route(&il, node(0,0), node(10,0));
+        let upper = route(&il, node(0,1), node(10,1));
+        let crossover = route(&il, node(0,0), node(10,1));
+
+        let flank_sw = *dgraph.switch_ids.get_by_right(&glm::vec2(4,1)).unwrap();
+        assert!(il.routes[lower].flank.iter()
+                .any(|(f,_)| matches!(f, FlankProtection::Switch(sw,_) if *sw == flank_sw)));
+        assert!(il.routes[lower].route.resources.switch_positions.iter().any(|(sw,_)| *sw == flank_sw));
+        assert!(il.routes[lower].route.resources.releases.iter().any(|r| r.resources.contains(&flank_sw)));
+
+        let inf = plan::convert_inf(&il.routes.iter().enumerate().collect());
+        // The upper line runs over the flank protection switch in the same position.
+        assert!(!conflicts(&inf, lower, upper));
+        assert!(!conflicts(&inf, upper, lower));
+        assert!(conflicts(&inf, lower, crossover));
+        assert!(conflicts(&inf, upper, crossover));
+    }
+
+    #[test]
+    fn flank_signal_is_held() {
+        // Main line from (0,0) to (10,0) with a siding from a switch at (3,0),
+        // and a signal on the siding facing the switch.
+        let mut model = switch_model(10, 3);
+        for pt in &[glm::vec2(1.5,0.0), glm::vec2(6.5,0.0), glm::vec2(5.5,1.0)] {
+            add_object(&mut model, *pt, Function::Detector);
+        }
+        let signal = add_object(&mut model, glm::vec2(6.5,1.1), main_signal());
+
+        let (dgraph, il) = interlocking(&model);
+        let main = route(&il, node(0,0), node(10,0));
+        let from_signal = route(&il, Ref::Object(signal), node(0,0));
+
+        let signal_id = *dgraph.object_ids.get_by_right(&signal).unwrap();
+        assert!(il.routes[main].flank.contains(&(FlankProtection::Signal(signal_id), 0)));
+        assert!(il.routes[main].route.resources.releases[0].resources.contains(&signal_id));
+        assert!(il.routes[from_signal].route.resources.releases[0].resources.contains(&signal_id));
+
+        let inf = plan::convert_inf(&il.routes.iter().enumerate().collect());
+        assert!(conflicts(&inf, main, from_signal));
+        assert!(conflicts(&inf, from_signal, main));
+    }
 }
@@ -0,0 +1,49 @@
+use route_finder::ConvertRouteIssue;
+use crate::document::model::*;
+use crate::document::dgraph::*;
+
+/// Problem found when analyzing the model, shown in the issues window.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub message :String,
+    pub location :Option<IssueLocation>,
+}
+
+/// Where the user can go to fix an issue.
+#[derive(Debug, Copy, Clone)]
+pub enum IssueLocation {
+    Infrastructure(Ref),
+    Dispatch(usize),
+    Plan(usize),
+}
+
+impl Issue {
+    pub fn new(message :String, location :Option<IssueLocation>) -> Self {
+        Issue { message, location }
+    }
+
+    pub fn from_route_issue(model :&Model, dgraph :&DGraph, issue :&ConvertRouteIssue) -> Self {
+        let name = |r :Option<Ref>| r.map(|r| model.ref_label(&r)).unwrap_or("?".to_string());
+        match issue {
+            ConvertRouteIssue::NoBoundaries =>
+                Issue::new(format!("The infrastructure has no boundaries."), None),
+            ConvertRouteIssue::StateConversionFailed(entry,exit) => {
+                let (a,b) = (dgraph.entry_exit_ref(entry), dgraph.entry_exit_ref(exit));
+                Issue::new(format!("Could not convert route from {} to {}.", name(a), name(b)),
+                           a.map(IssueLocation::Infrastructure))
+            },
+            ConvertRouteIssue::ExitedUnenteredSection(entry,section) => {
+                let a = dgraph.entry_exit_ref(entry);
+                let detector = dgraph.section_limits(*section).into_iter().next();
+                Issue::new(format!("Route from {} exits a train detection section it did not enter.", name(a)),
+                           detector.or(a).map(IssueLocation::Infrastructure))
+            },
+            ConvertRouteIssue::RouteTooShort(entry,exit) => {
+                let (a,b) = (dgraph.entry_exit_ref(entry), dgraph.entry_exit_ref(exit));
+                Issue::new(format!("Route from {} to {} is too short.", name(a), name(b)),
+                           a.map(IssueLocation::Infrastructure))
+            },
+        }
+    }
+}
+
@@ -1,19 +1,40 @@
 use bimap::BiMap;
 use std::collections::{HashMap, HashSet};
+use ordered_float::OrderedFloat;
 use matches::matches;
 use rolling::input::staticinfrastructure::*;
 use petgraph::unionfind::UnionFind;
 use crate::document::model::Pt;
+use log::*;
 
 
-fn take_boundary(node_ids :&BiMap<NodeId, Pt>, boundaries :&mut HashSet<NodeId>) -> Option<NodeId> {
-    if let Some(id) = boundaries.iter().min_by_key(|n| { node_ids.get_by_left(n).map(|pt| (pt.x,-pt.y)).unwrap_or((10000,0)) }) {
+fn take_boundary(node_ids :&BiMap<NodeId, Pt>, origins :&HashSet<NodeId>, boundaries :&mut HashSet<NodeId>) -> Option<NodeId> {
+    if let Some(id) = boundaries.iter().min_by_key(|n| { 
+        (!origins.contains(n), node_ids.get_by_left(n).map(|pt| (pt.x,-pt.y)).unwrap_or((10000,0))) }) {
         let id = *id;
         boundaries.take(&id)
     } else { None }
 }
 
-pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<NodeId, f64> {
+/// Calculate the mileage of all nodes reachable from a model boundary, fitting the 
+/// edge lengths in the least-squares sense. 
+///
+/// The mileage increases away from the first boundary of each connected part of the 
+/// network, which is a boundary in `origins` if there is one, or else the leftmost 
+/// boundary. Nodes in `anchors` have a fixed mileage given by the user, and connected 
+/// parts of the network without any anchors start at 0.0 on their first boundary.
+///
+/// `breaks` are node pairs (keyed by both nodes) where the chainage jumps from the 
+/// first to the second value, in the direction of increasing mileage. The returned 
+/// mileage is continuous through the breaks, so that it can be used for drawing, and 
+/// the breaks are returned as (mileage, chainage before, chainage after), see 
+/// `DGraph::chainage`. The chainage of each node is its mileage plus the returned
+/// offset of the node. Anchors are given as chainage.
+pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure, 
+            origins :&HashSet<NodeId>,
+            anchors :&HashMap<NodeId,f64>,
+            breaks :&HashMap<NodeId,(f64,f64)>) 
+    -> (HashMap<NodeId, f64>, HashMap<NodeId, f64>, Vec<(f64,f64,f64)>) {
     let mut boundaries : HashSet<NodeId> = inf.nodes.iter().enumerate().filter_map(|(i,n)| {
         if matches!(n.edges, Edges::ModelBoundary) { Some(i) } else { None } }).collect();
     // TODO select leftmost boundaries first
@@ -21,20 +42,36 @@ pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<
 
     let mut edges : Vec<(NodeId,NodeId,f64)> = Vec::new();
     let mut km0 : HashMap<NodeId,f64> = HashMap::new();
+    // Chainage minus mileage, changing at each break.
+    let mut offsets : HashMap<NodeId,f64> = HashMap::new();
+    // Node before each break, in the direction of increasing mileage.
+    let mut break_nodes : Vec<(NodeId,f64,f64)> = Vec::new();
     let mut uf = UnionFind::new(inf.nodes.len());
     let mut fixed = Vec::new();
-    while let Some(boundary) = take_boundary(node_ids, &mut boundaries) {
-        fixed.push(boundary);
-        let mut stack = vec![(boundary, 0.0, -1)];
-        while let Some((node,pos,dir)) = stack.pop() {
+    while let Some(boundary) = take_boundary(node_ids, origins, &mut boundaries) {
+        let mut component = Vec::new();
+        let mut stack = vec![(boundary, 0.0, 0.0, -1)];
+        while let Some((node,pos,offset,dir)) = stack.pop() {
             if km0.get(&node).is_some() { continue; }
             km0.insert(node,pos);
-            stack.push((inf.nodes[node].other_node, pos, -dir));
+            offsets.insert(node,offset);
+            component.push(node);
+            let other = inf.nodes[node].other_node;
+            match breaks.get(&node) {
+                Some((before,after)) if !km0.contains_key(&other) => {
+                    // Nodes which are reached in the increasing direction have dir == -1.
+                    let forward = dir < 0;
+                    break_nodes.push((if forward { node } else { other }, *before, *after));
+                    let jump = if forward { after - before } else { before - after };
+                    stack.push((other, pos, offset + jump, -dir));
+                },
+                _ => { stack.push((other, pos, offset, -dir)); },
+            }
             uf.union(node, inf.nodes[node].other_node);
             if matches!(inf.nodes[node].edges, Edges::ModelBoundary) { boundaries.remove(&node); }
             match inf.nodes[node].edges {
                 Edges::Single(b,d) => {
-                    stack.push((b, pos + (dir as f64)*d, -dir));
+                    stack.push((b, pos + (dir as f64)*d, offset, -dir));
                     //println!("edge from {} to {} dir {}", node, b, dir);
                     if !km0.contains_key(&b) { // TODO this smells a little
                         edges.push((node,b,(dir as f64)*d));
@@ -42,8 +79,8 @@ pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<
                 },
                 Edges::Switchable(obj) => {
                     if let StaticObject::Switch { right_link, left_link, .. } = inf.objects[obj] {
-                        stack.push((right_link.0, pos + (dir as f64)*right_link.1, -dir));
-                        stack.push((left_link.0, pos + (dir as f64)*left_link.1, -dir));
+                        stack.push((right_link.0, pos + (dir as f64)*right_link.1, offset, -dir));
+                        stack.push((left_link.0, pos + (dir as f64)*left_link.1, offset, -dir));
                         uf.union(node, right_link.0); // TODO holds only if lengths is zero here.
                         uf.union(node, left_link.0);  // but that is the case for the conversion
                                                       // as it is currently written in dgraph.rs.
@@ -52,6 +89,11 @@ pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<
                 _ => {},
             }
         }
+
+        // Components without user-given mileage start at 0.0.
+        if !component.iter().any(|n| anchors.contains_key(n) || breaks.contains_key(n)) {
+            fixed.push(boundary);
+        }
     }
 
 
@@ -59,101 +101,89 @@ pub fn auto(node_ids :&BiMap<NodeId,Pt>, inf :&StaticInfrastructure) -> HashMap<
     // a list of edges,
     // and a map from original NodeIds to unknown variables
 
-    //println!("edges {:?}", edges);
-
     let mut varmap : HashMap<NodeId, usize> = HashMap::new();
     let mut varidx = -1isize;
-    let mut fixed :HashMap<NodeId, Option<usize>> = fixed.into_iter().map(|n| (n,None)).collect();
-    for (n,km) in km0.iter() {
-        let v = varmap.entry(uf.find_mut(*n)).or_insert_with(|| { varidx += 1; varidx as usize });
-        fixed.entry(*n).and_modify(|nvar| { *nvar = Some(*v); });
+    for (n,_km) in km0.iter() {
+        varmap.entry(uf.find_mut(*n)).or_insert_with(|| { varidx += 1; varidx as usize });
     }
 
-    let mut rhs : Vec<f64> = edges.iter().map(|(_,_,d)| *d).collect();
-    for (_,km) in fixed.iter() { rhs.push(0.0); } // TODO fixed positions can be != 0.0 as well
-
-
-    //println!("varmap {:?}", varmap);
-    //println!("fixed {:?}", fixed);
-    //println!("rhs {:?}", rhs);
-    //for (n,var) in &varmap {
-        //println!("var {:03}/{:03} km {:.3}", n, var, km0[n]);
-    //}
-
-     let params = lsqr::Params {
-         damp: 0.0,
-         rel_mat_err: 1e-6,
-         rel_rhs_err: 1e-6,
-         condlim :0.0,
-         iterlim: inf.nodes.len(), 
-     };
-
-    // Now we have a first guess of the km, indexed by actual variables to solve
-     let (sol,stats) = lsqr::lsqr(|msg| {}, //println!("{}", msg),
-                edges.len() + fixed.len(), varmap.len(), 
-                params,
-                |prod| {
-                    match prod {
-                        lsqr::Product::YAddAx { x, y } => {
-                            //println!("YAddAx");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                            // compute y += A * x
-                            // y contains edge adjustments(?), x contains node kms
-                            // [n_e +1, n_n] * [n_n, 1] = [n_e +1, 1]
-                           for (e, (a,b,d)) in edges.iter().enumerate() {
-                               let (a,b) = (varmap[&uf.find_mut(*a)], varmap[&uf.find_mut(*b)]);
-                               y[e] += x[b] - x[a];
-                           }
-                           for (i,(_,v)) in fixed.iter().enumerate() { if let Some(var) = v { 
-                               //println!("edges.len()  = {}", edges.len());
-                               //println!("i = {}", i);
-                               //println!("var = {}", *var);
-                               y[edges.len() + i] += x[*var]; 
-                           } }
-                             //println!("YAddAx end");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                        },
-                        lsqr::Product::XAddATy { x, y } => {
-                            //println!("XAddATy");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                            // compute x += A^T * y
-                            // y contains something, 
-                            for (e, (a,b,d)) in edges.iter().enumerate() {
-                                let (a,b) = (varmap[&uf.find_mut(*a)], varmap[&uf.find_mut(*b)]);
-                                x[a] += -1. * y[e];
-                                x[b] +=  1. * y[e];
-                            }
-                           for (i,(_,v)) in fixed.iter().enumerate() { if let Some(var) = v { 
-                                x[*var] += y[edges.len() + i] ;
-                           } }
-                            //println!("XAddATy end");
-                            //println!("x = {:?}", x);
-                            //println!("y = {:?}", y);
-                        },
-                    }
-                },
-                &mut rhs);
-
-     //println!("sol {:?}", sol);
-
-     // This should be our solution, now we can map back to node kms
-     let km :HashMap<NodeId,f64> = km0.into_iter().map(|(n,_)| (n, sol[varmap[&uf.find_mut(n)]])).collect();
+    // Anchored variables are known, and are eliminated from the system.
+    // The chainage of the anchors is converted to the continuous mileage.
+    let mut known : HashMap<usize, f64> = HashMap::new();
+    let break_anchors = break_nodes.iter().map(|(n,before,_)| (*n,*before));
+    for (n,km) in anchors.iter().map(|(n,km)| (*n,*km)).chain(break_anchors) {
+        if !km0.contains_key(&n) { continue; }
+        let var = varmap[&uf.find_mut(n)];
+        let km = km - offsets[&n];
+        if let Some(other) = known.insert(var, km) {
+            if other != km {
+                warn!("Conflicting fixed mileage {:.3} and {:.3} on the same location.", other, km);
+            }
+        }
+    }
 
-     let mut v = km.iter().collect::<Vec<_>>();
-     v.sort_by_key(|a| a.0);
-     for (n,k) in v {
-         if n%2 == 0 {
-             //println!("node {:03} km {:.3}", n, k);
-         }
-     }
+    let mut columns : HashMap<usize, usize> = HashMap::new();
+    for var in 0..varmap.len() {
+        if !known.contains_key(&var) { let c = columns.len(); columns.insert(var, c); }
+    }
 
-     //println!("solver stats {:#?}", stats);
+    // Sparse rows of the system, with the known variables moved to the right hand side.
+    let mut rows : Vec<Vec<(usize,f64)>> = Vec::new();
+    let mut rhs : Vec<f64> = Vec::new();
+    for (a,b,d) in edges.iter() {
+        let (a,b) = (varmap[&uf.find_mut(*a)], varmap[&uf.find_mut(*b)]);
+        let mut row = Vec::new();
+        let mut r = *d;
+        match known.get(&a) { Some(km) => { r += km; }, None => { row.push((columns[&a], -1.0)); } }
+        match known.get(&b) { Some(km) => { r -= km; }, None => { row.push((columns[&b],  1.0)); } }
+        if row.len() > 0 { rows.push(row); rhs.push(r); }
+    }
+    for n in fixed.iter() {
+        let var = varmap[&uf.find_mut(*n)];
+        rows.push(vec![(columns[&var], 1.0)]);
+        rhs.push(0.0);
+    }
 
-     km
+    let sol = if columns.len() > 0 {
+        let params = lsqr::Params {
+            damp: 0.0,
+            rel_mat_err: 1e-6,
+            rel_rhs_err: 1e-6,
+            condlim :0.0,
+            iterlim: inf.nodes.len(), 
+        };
+
+        let (sol,_stats) = lsqr::lsqr(|_msg| {}, //println!("{}", msg),
+                   rows.len(), columns.len(), 
+                   params,
+                   |prod| {
+                       match prod {
+                           lsqr::Product::YAddAx { x, y } => {
+                               // compute y += A * x
+                               for (r,row) in rows.iter().enumerate() {
+                                   for (c,coeff) in row.iter() { y[r] += coeff * x[*c]; }
+                               }
+                           },
+                           lsqr::Product::XAddATy { x, y } => {
+                               // compute x += A^T * y
+                               for (r,row) in rows.iter().enumerate() {
+                                   for (c,coeff) in row.iter() { x[*c] += coeff * y[r]; }
+                               }
+                           },
+                       }
+                   },
+                   &mut rhs);
+        sol
+    } else { Vec::new() };
+
+    // Map the solution back to node kms
+    let km :HashMap<NodeId,f64> = km0.into_iter().map(|(n,_)| {
+        let var = varmap[&uf.find_mut(n)];
+        (n, known.get(&var).cloned().unwrap_or_else(|| sol[columns[&var]]))
+    }).collect();
+
+    let mut breaks :Vec<(f64,f64,f64)> = break_nodes.into_iter()
+        .map(|(n,before,after)| (km[&n], before, after)).collect();
+    breaks.sort_by_key(|(km,_,_)| OrderedFloat(*km));
+    (km, offsets, breaks)
 }
-
-
-
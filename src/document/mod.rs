@@ -13,6 +13,7 @@ pub mod history;
 pub mod dispatch;
 pub mod mileage;
 pub mod plan;
+pub mod issues;
 
 // graphical view representation
 pub mod infview;
@@ -71,9 +71,11 @@ pub enum CrossingType {
     DoubleSlip,
 }
 
-#[derive(Debug,Copy,Clone, PartialEq, Eq)]
+/// Node types. Switches and crossings have an optional speed restriction (m/s)
+/// for trains using the deviating track (or the slips).
+#[derive(Debug,Copy,Clone, PartialEq)]
 #[derive(Serialize,Deserialize)]
-pub enum NDType { OpenEnd, BufferStop, Cont, Sw(Side), Crossing(CrossingType), Err }
+pub enum NDType { OpenEnd, BufferStop, Cont, Sw(Side, Option<f64>), Crossing(CrossingType, Option<f64>), Err }
 // TODO crossing switchable, crossing orthogonal?, what settings does a crossing have?
 // Assuming non-switched crossing for now.
 
@@ -202,6 +204,12 @@ pub struct Visit {
 
 pub type PlanLoc = Result<Ref,PtC>;
 
+/// Dwell time (s) for new stops at plan visits.
+pub const DEFAULT_DWELL :f64 = 60.0;
+
+/// Maximum time (s) between visits for new time bounds on ordering constraints.
+pub const DEFAULT_ORDER_MAX_TIME :f64 = 300.0;
+
 pub type ListId = usize;
 
 #[derive(Clone)]
@@ -279,16 +287,96 @@ impl<T:Clone> Default for ImShortGenList<T> {
     fn default() -> Self { ImShortGenList(Arc::new(ShortGenList { generation: 0, list :Vec::new() })) }
 }
 
+/// Track length of a drawn grid segment, unless given in `Model::lineseg_lengths`.
+pub const DEFAULT_LINESEG_LENGTH :f64 = 50.0;
+
+/// Sight distance for signals, unless given on the signal or in the model settings.
+pub const DEFAULT_SIGHT_DISTANCE :f64 = 200.0;
+
+/// Document-level settings used when deriving the infrastructure and running simulations.
+#[derive(Clone, Debug)]
+#[derive(Serialize,Deserialize)]
+pub struct ModelSettings {
+    /// Sight distance (m) for signals which don't have their own sight distance.
+    pub sight_distance :f64,
+    pub routes :RouteSettings,
+}
+
+impl Default for ModelSettings {
+    fn default() -> ModelSettings { ModelSettings {
+        sight_distance: DEFAULT_SIGHT_DISTANCE,
+        routes: Default::default(),
+    } }
+}
+
+/// Parameters for finding the interlocking routes, see `route_finder::Config`.
+#[derive(Clone, Debug)]
+#[derive(Serialize,Deserialize)]
+pub struct RouteSettings {
+    /// Distance (m) a route may run into a train detection section beyond its exit
+    /// before the section is included in the route.
+    pub section_tolerance :f64,
+    /// Minimum length (m) of routes. Shorter routes are left out and reported as issues.
+    pub route_minimum_length :f64,
+    /// Length (m) of the overlap beyond the exit signal of train routes.
+    pub overlap_length :f64,
+}
+
+impl Default for RouteSettings {
+    fn default() -> RouteSettings { 
+        let config = route_finder::Config::default();
+        RouteSettings {
+            section_tolerance: config.section_tolerance,
+            route_minimum_length: config.route_minimum_length,
+            overlap_length: config.overlap_length,
+        }
+    }
+}
+
+impl RouteSettings {
+    /// Parameters for models from before the route finder parameters were stored,
+    /// whose routes were found without overlaps. New models get the default overlap.
+    pub fn without_overlaps() -> RouteSettings {
+        RouteSettings { overlap_length: 0.0, ..Default::default() }
+    }
+
+    pub fn route_finder_config(&self) -> route_finder::Config {
+        route_finder::Config {
+            section_tolerance: self.section_tolerance,
+            route_minimum_length: self.route_minimum_length,
+            overlap_length: self.overlap_length,
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 #[derive(Debug)]
 #[derive(Serialize,Deserialize)]
 pub struct Model {
     pub linesegs: im::HashSet<(Pt,Pt)>,
+    /// Track length in meters of line segments, keyed by the ordered end points
+    /// (see `util::order_ivec`). Segments without an entry have the default length.
+    pub lineseg_lengths: im::HashMap<(Pt,Pt), f64>,
+    /// Gradient in per mille of line segments, keyed like `lineseg_lengths`. The value is
+    /// positive when the track rises from the first to the second (ordered) point.
+    /// Segments without an entry are level.
+    pub lineseg_gradients: im::HashMap<(Pt,Pt), f64>,
     pub objects: im::HashMap<PtA, Object>,
     pub node_data: im::HashMap<Pt, NDType>,
+    /// Fixed mileage (m) of nodes given by the user. Nodes and objects with a fixed
+    /// mileage are used as anchors when calculating the mileage of the infrastructure.
+    pub node_mileage: im::HashMap<Pt, f64>,
+    /// Model boundaries (open ends) where the mileage starts, and increases 
+    /// into the network. Connected parts of the network without an origin 
+    /// start on their leftmost boundary.
+    pub mileage_origins: im::HashSet<Pt>,
+    /// Names of switches, crossings and boundaries, unique in the model 
+    /// together with the object names.
+    pub node_names: im::HashMap<Pt, String>,
     pub vehicles :ImShortGenList<Vehicle>, 
     pub dispatches :ImShortGenList<Dispatch>,
     pub plans :ImShortGenList<PlanSpec>,
+    pub settings :ModelSettings,
 }
 
 
@@ -381,10 +469,137 @@ impl Model {
         r
     }
 
+    pub fn lineseg_length(&self, a :Pt, b :Pt) -> Option<f64> {
+        self.lineseg_lengths.get(&order_ivec(a,b)).cloned()
+    }
+
+    /// Gradient (per mille) of the line segment when travelling from `a` to `b`.
+    pub fn lineseg_gradient(&self, a :Pt, b :Pt) -> f64 {
+        let g = self.lineseg_gradients.get(&order_ivec(a,b)).cloned().unwrap_or(0.0);
+        if order_ivec(a,b) == (a,b) { g } else { -g }
+    }
+
+    /// Name of a node or object, if it has one.
+    pub fn name_of(&self, r :&Ref) -> Option<&str> {
+        match r {
+            Ref::Node(pt) => self.node_names.get(pt).map(|n| n.as_str()),
+            Ref::Object(pta) => self.objects.get(pta)?.name.as_ref().map(|n| n.as_str()),
+            Ref::LineSeg(_,_) => None,
+        }
+    }
+
+    /// Name of a node or object for showing to the user, using the position
+    /// for things without a name.
+    pub fn ref_label(&self, r :&Ref) -> String {
+        match (self.name_of(r), r) {
+            (Some(name),_) => name.to_string(),
+            (None, Ref::Node(pt)) => format!("node ({},{})", pt.x, pt.y),
+            (None, Ref::Object(pta)) => format!("object ({:.1},{:.1})", unround_coord(*pta).x, unround_coord(*pta).y),
+            (None, Ref::LineSeg(a,b)) => format!("track ({},{})-({},{})", a.x, a.y, b.x, b.y),
+        }
+    }
+
+    /// Route name from the names of the entry and exit, such as "S1-S3".
+    pub fn route_name(&self, r :&RouteSpec) -> String {
+        let name = format!("{}-{}", self.ref_label(&r.from), self.ref_label(&r.to));
+        if r.alternative > 0 { format!("{} (alt. {})", name, r.alternative) } else { name }
+    }
+
+    pub fn find_named(&self, name :&str) -> Option<Ref> {
+        if let Some((pt,_)) = self.node_names.iter().find(|(_,n)| n.as_str() == name) {
+            return Some(Ref::Node(*pt));
+        }
+        self.objects.iter().find(|(_,o)| o.name.as_ref().map(|n| n.as_str()) == Some(name))
+            .map(|(pta,_)| Ref::Object(*pta))
+    }
+
+    /// Give all objects without a name a new unique name, such as "S3" for the 
+    /// third signal.
+    pub fn assign_names(&mut self) {
+        let unnamed = self.objects.iter().filter(|(_,o)| o.name.is_none())
+            .map(|(pta,_)| *pta).collect::<Vec<_>>();
+        if unnamed.is_empty() { return; }
+        let mut used :HashSet<String> = self.node_names.values().cloned()
+            .chain(self.objects.values().filter_map(|o| o.name.clone())).collect();
+        let mut unnamed = unnamed;
+        unnamed.sort_by_key(|p| (p.x,p.y));
+        for pta in unnamed {
+            let obj = self.objects.get_mut(&pta).unwrap();
+            let prefix = obj.name_prefix();
+            let name = (1..).map(|i| format!("{}{}", prefix, i)).find(|n| !used.contains(n)).unwrap();
+            used.insert(name.clone());
+            obj.name = Some(name);
+        }
+    }
+
+    /// Names of nodes and objects, leaving out names which are used more than once.
+    fn unique_names(&self) -> HashMap<&str, Ref> {
+        let mut names = HashMap::new();
+        let mut duplicates = HashSet::new();
+        let all = self.node_names.iter().map(|(pt,n)| (n.as_str(), Ref::Node(*pt)))
+            .chain(self.objects.iter().filter_map(|(pta,o)| Some((o.name.as_ref()?.as_str(), Ref::Object(*pta)))));
+        for (name, r) in all {
+            if name.is_empty() { continue; }
+            if names.insert(name, r).is_some() { duplicates.insert(name); }
+        }
+        for name in duplicates { names.remove(name); }
+        names
+    }
+
+    /// Dispatches and plans refer to nodes and objects by their position. When a 
+    /// named node or object has moved relative to the `old` model, the references 
+    /// are updated to the new position of the same name. Names which are not 
+    /// unique in both models are not followed.
+    pub fn relink_named_refs(&mut self, old :&Model) {
+        let renamed :HashMap<Ref,Ref> = {
+            let new_names = self.unique_names();
+            old.unique_names().into_iter().filter_map(|(name, old_ref)| {
+                let new_ref = *new_names.get(name)?;
+                // Only follow the name to a place where it is new, so that 
+                // changing the name of an object does not link to another object.
+                if new_ref != old_ref && old.name_of(&new_ref) != Some(name) {
+                    Some((old_ref, new_ref))
+                } else { None }
+            }).collect()
+        };
+        if renamed.is_empty() { return; }
+
+        for (_,dispatch) in self.dispatches.iter_mut() {
+            for (_,(_,command)) in dispatch.commands.iter_mut() {
+                match command {
+                    Command::Train(_,r) | Command::Route(r) => {
+                        if let Some(x) = renamed.get(&r.from) { r.from = *x; }
+                        if let Some(x) = renamed.get(&r.to) { r.to = *x; }
+                    }
+                };
+            }
+        }
+        for (_,p) in self.plans.iter_mut() {
+            for (_,(_veh, visits)) in p.trains.iter_mut() {
+                for (_,v) in visits.iter_mut() {
+                    for l in v.locs.iter_mut() {
+                        if let Ok(r) = l {
+                            if let Some(x) = renamed.get(&*r) { *r = *x; }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn delete(&mut self, x :Ref) {
         match x {
-            Ref::LineSeg(a,b) => { self.linesegs.remove(&(a,b)); },
-            Ref::Node(a) => { self.node_data.remove(&a); },
+            Ref::LineSeg(a,b) => { 
+                self.linesegs.remove(&(a,b)); 
+                self.lineseg_lengths.remove(&order_ivec(a,b));
+                self.lineseg_gradients.remove(&order_ivec(a,b));
+            },
+            Ref::Node(a) => { 
+                self.node_data.remove(&a); 
+                self.node_mileage.remove(&a);
+                self.mileage_origins.remove(&a);
+                self.node_names.remove(&a);
+            },
             Ref::Object(p) => { self.objects.remove(&p); },
         }
     }
@@ -392,7 +607,7 @@ impl Model {
 
 }
 
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 #[derive(Debug, PartialEq, Eq)]
 pub enum EditClass {
     MoveObjects(HashSet<Ref>),
@@ -405,6 +620,19 @@ pub enum EditClass {
 
     DispatchName(usize),
     PlanName(usize),
+    LineSegLength(Pt,Pt),
+    LineSegGradient(Pt,Pt),
+    NodeSpeed(Pt),
+    ObjectSpeed(PtA),
+    ObjectSight(PtA),
+    SightDistance,
+    RouteSettings,
+    VisitDwell(usize,usize,usize),
+    OrderMaxTime(usize,usize),
+    NodeMileage(Pt),
+    NodeName(Pt),
+    ObjectName(PtA),
+    ObjectMileage(PtA),
 }
 
 
@@ -480,3 +708,64 @@ impl<T : Clone + Default, C : Eq> Undoable<T,C> {
         self.class = Some(cl);
     }
 }
+
+/// Small models shared by the tests.
+#[cfg(test)]
+pub mod test_models {
+    use super::*;
+    use crate::document::topology::{self, Topology};
+    use crate::document::dgraph::{DGraph, DGraphBuilder};
+    use crate::document::interlocking::{self, Interlocking};
+
+    /// Track along y from (x0,y) to (x1,y).
+    pub fn add_line(model :&mut Model, y :i32, x0 :i32, x1 :i32) {
+        for x in x0..x1 { model.linesegs.insert((glm::vec2(x,y), glm::vec2(x+1,y))); }
+    }
+
+    /// Straight line from (0,0) to (length,0).
+    pub fn straight_line(length :i32) -> Model {
+        let mut model = Model::empty();
+        add_line(&mut model, 0, 0, length);
+        model
+    }
+
+    /// Straight line from (0,0) to (length,0) with a switch at (sw,0),
+    /// diverging to a line ending at (length,1).
+    pub fn switch_model(length :i32, sw :i32) -> Model {
+        let mut model = straight_line(length);
+        model.linesegs.insert((glm::vec2(sw,0), glm::vec2(sw+1,1)));
+        add_line(&mut model, 1, sw+1, length);
+        model
+    }
+
+    pub fn main_signal() -> Function { Function::MainSignal { has_distant: false, sight: None } }
+
+    /// Add an object with one function, placed on the track closest to `pt` as in 
+    /// the editor, so that signals face the direction given by the side of the track.
+    pub fn add_object(model :&mut Model, pt :PtC, function :Function) -> PtA {
+        let mut object = Object { loc: pt, tangent: glm::vec2(1,0), functions: vec![function],
+            mileage: None, name: None };
+        object.move_to(model, pt);
+        let pta = round_coord(object.loc);
+        model.objects.insert(pta, object);
+        pta
+    }
+
+    pub fn add_named_object(model :&mut Model, pt :PtC, function :Function, name :&str) -> PtA {
+        let pta = add_object(model, pt, function);
+        model.objects.get_mut(&pta).unwrap().name = Some(name.to_string());
+        pta
+    }
+
+    pub fn dgraph(model :&Model) -> (Topology, DGraph) {
+        let topology = topology::convert(model, DEFAULT_LINESEG_LENGTH).unwrap();
+        let dgraph = DGraphBuilder::convert(&topology).unwrap();
+        (topology, dgraph)
+    }
+
+    pub fn interlocking(model :&Model) -> (DGraph, Interlocking) {
+        let (_, dgraph) = dgraph(model);
+        let (il, _) = interlocking::calc(&dgraph, &model.settings.routes).unwrap();
+        (dgraph, il)
+    }
+}
@@ -17,16 +17,47 @@ pub struct Object {
     pub loc :PtC,
     pub tangent :Vc,
     pub functions :Vec<Function>,
+    /// Fixed mileage (m) given by the user, see `Model::node_mileage`.
+    #[serde(default)]
+    pub mileage :Option<f64>,
+    /// Name of the object, such as "A1", unique in the model. Dispatches and plans
+    /// are re-linked by name when an object's position changes, see `Model::relink_named_refs`.
+    #[serde(default)]
+    pub name :Option<String>,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[derive(Serialize,Deserialize)]
-pub enum Function { MainSignal { has_distant :bool }, Detector }
+pub enum Function { 
+    /// Main signal, with sight distance (m) from the approaching train to the signal.
+    /// Signals without a sight distance use the model's `ModelSettings::sight_distance`.
+    MainSignal { has_distant :bool, #[serde(default)] sight :Option<f64> }, 
+    /// Shunting signal, giving shunting routes only, see `route_finder::RouteClass`.
+    ShuntingSignal { sight :Option<f64> },
+    Detector,
+    /// Speed limit sign (m/s), facing the same direction as signals on the same side of the track.
+    SpeedRestriction { speed :f64 },
+    /// Chainage break, where the chainage (m) jumps from `before` to `after`
+    /// in the direction of increasing mileage.
+    MileageBreak { before :f64, after :f64 },
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ObjectState { SignalStop, SignalProceed, DistantStop, DistantProceed }
 
 impl Object {
+    /// Prefix for automatically assigned names.
+    pub fn name_prefix(&self) -> &'static str {
+        match self.functions.first() {
+            Some(Function::MainSignal { .. }) => "S",
+            Some(Function::ShuntingSignal { .. }) => "Sh",
+            Some(Function::Detector) => "D",
+            Some(Function::SpeedRestriction { .. }) => "V",
+            Some(Function::MileageBreak { .. }) => "K",
+            None => "O",
+        }
+    }
+
     pub fn move_to(&mut self, model :&Model, pt :PtC) -> Option<()> {
         if let Some((l,_param,(d1,d2))) = model.get_closest_lineseg(pt) {
             let (pt_on_line,_param) = project_to_line(pt, glm::vec2(l.0.x as _ ,l.0.y as _ ),
@@ -35,13 +66,13 @@ impl Object {
             let normal : PtC   = glm::vec2(-tangent.y,tangent.x);
             self.tangent = glm::vec2(tangent.x.round() as _, tangent.y.round() as _);
 
-            if self.functions.iter().find(|c| matches!(c, Function::MainSignal { .. })).is_some() {
+            if self.functions.iter().find(|c| matches!(c, Function::MainSignal { .. } | Function::ShuntingSignal { .. } | Function::SpeedRestriction { .. })).is_some() {
                     let factor = if glm::angle(&(pt_on_line - pt), &normal) > glm::half_pi() {
                         1.0 } else { -1.0 };
                     let offset = 0.25*normal*factor;
                     if factor > 0.0 { self.tangent *= -1; }
                     self.loc = pt_on_line + offset;
-            } else if self.functions.iter().find(|c| matches!(c, Function::Detector)).is_some() {
+            } else if self.functions.iter().find(|c| matches!(c, Function::Detector | Function::MileageBreak { .. })).is_some() {
                 self.loc = pt_on_line;
             }
 
@@ -67,7 +98,7 @@ impl Object {
                     Function::Detector => {
                         ImDrawList_AddLine(draw_list, p - normal, p + normal, c, 2.0);
                     },
-                    Function::MainSignal { has_distant } => {
+                    Function::MainSignal { has_distant, .. } => {
                         // base
                         ImDrawList_AddLine(draw_list, p + normal, p - normal, c, 2.0);
 
@@ -103,6 +134,45 @@ impl Object {
                         // main signal
                         ImDrawList_AddCircle(draw_list, p + stem*tangent + tangent, scale, c, 8, 2.0);
                     },
+                    Function::ShuntingSignal { .. } => {
+                        // base and post
+                        ImDrawList_AddLine(draw_list, p + normal, p - normal, c, 2.0);
+                        ImDrawList_AddLine(draw_list, p, p + tangent, c, 2.0);
+
+                        // arrow head instead of the main signal's lamp
+                        let (a,b,tip) = (p + tangent + normal, p + tangent - normal, p + 2.5*tangent);
+                        for s in state.iter() {
+                            let fill = match s {
+                                ObjectState::SignalStop => config.color_u32(RailUIColorName::CanvasSignalStop),
+                                ObjectState::SignalProceed => config.color_u32(RailUIColorName::CanvasSignalProceed),
+                                _ => continue,
+                            };
+                            ImDrawList_AddTriangleFilled(draw_list, a, b, tip, fill);
+                        }
+                        ImDrawList_AddTriangle(draw_list, a, b, tip, c, 2.0);
+                    },
+                    Function::SpeedRestriction { speed } => {
+                        // base and post
+                        ImDrawList_AddLine(draw_list, p + normal, p - normal, c, 2.0);
+                        ImDrawList_AddLine(draw_list, p, p + tangent, c, 2.0);
+
+                        // sign board with the speed in km/h
+                        let center = p + 2.0*tangent;
+                        let corner = ImVec2 { x: 1.2*scale, y: 1.2*scale };
+                        ImDrawList_AddRect(draw_list, center - corner, center + corner, c, 0.0, 0, 2.0);
+                        let text = format!("{:.0}", speed * 3.6);
+                        ImDrawList_AddText(draw_list, center + ImVec2 { x: 1.5*scale, y: -scale },
+                                           c, text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+                    },
+                    Function::MileageBreak { before, after } => {
+                        // two short bars across the track
+                        let gap = 0.3*tangent;
+                        ImDrawList_AddLine(draw_list, p - gap + normal, p - gap - normal, c, 2.0);
+                        ImDrawList_AddLine(draw_list, p + gap + normal, p + gap - normal, c, 2.0);
+                        let text = format!("km {:.3} = {:.3}", before / 1000.0, after / 1000.0);
+                        ImDrawList_AddText(draw_list, p + normal + ImVec2 { x: 2.0, y: 0.0 },
+                                           c, text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+                    },
                 }
 
             }
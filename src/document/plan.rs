@@ -4,81 +4,285 @@ use rolling::input::staticinfrastructure as rolling_inf;
 use crate::document::model::*;
 use crate::document::history;
 use crate::document::dgraph::DGraph;
+use crate::document::infview::unround_coord;
 use rolling::output::history::*;
+use rolling::railway::dynamics::DistanceVelocity;
+use matches::matches;
+use ordered_float::OrderedFloat;
+use log::*;
+use nalgebra_glm as glm;
 
 #[derive(Debug)]
 pub enum ConvertPlanErr {
     VehicleRefMissing,
     VehicleMissing,
+    VisitMissing,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TestPlanErr {
-    MissingVisits,
-    MissingTrain
+    MissingVisits(VisitRef),
+    MissingTrain(ListId),
+    MissingDwell(VisitRef),
+    /// Ordering constraint, by index in `PlanSpec::order`, was violated.
+    WrongOrder(usize),
+    MaxTimeExceeded(usize),
+    /// The candidate dispatch could not be scheduled or simulated.
+    DispatchFailed,
 }
 
-pub fn eval_plan(dgraph :&DGraph, plan_spec :&PlanSpec, history :&History) -> Result<(), TestPlanErr> {
+/// Reason why a plan has no dispatch, on the train and visit where the
+/// problem is, if known.
+#[derive(Debug, Clone)]
+pub struct PlanDiagnosis {
+    pub train :Option<ListId>,
+    pub visit :Option<ListId>,
+    pub message :String,
+}
+
+impl PlanDiagnosis {
+    fn new(train :Option<ListId>, visit :Option<ListId>, message :String) -> Self {
+        PlanDiagnosis { train, visit, message }
+    }
+}
+
+/// Check the simulated dispatch `history` of the `commands` against the plan.
+pub fn eval_plan(dgraph :&DGraph, il :&Interlocking, plan_spec :&PlanSpec, 
+                 commands :&[TrainCommand], history :&History) -> Result<(), TestPlanErr> {
 
+    let history_trains = history_trains(commands);
+
+    // time of each visit
+    let mut visit_times :HashMap<VisitRef, f64> = HashMap::new();
 
     //
     // 1. check train's visits
-    for (train_idx, (train_id, (veh, visits))) in plan_spec.trains.iter().enumerate() {
-        let mut current_visit = 0;
-        let (train_name, train_params, train_log) = history.trains.get(train_idx).ok_or(TestPlanErr::MissingTrain)?;
-        for ev in train_log.iter() {
-            if !(current_visit < visits.data().len()) { 
+    for (train_id, (veh, visits)) in plan_spec.trains.iter() {
+        let visits = visits.data();
+        let location_events = visits.iter().map(|(_,v)| LocationEvents::new(dgraph, v)).collect::<Vec<_>>();
+        let train_idx = history_trains.iter().position(|t| t == train_id).ok_or(TestPlanErr::MissingTrain(*train_id))?;
+        let (train_name, train_params, train_log) = history.trains.get(train_idx).ok_or(TestPlanErr::MissingTrain(*train_id))?;
+        // index of the event where each visit happened
+        let mut visit_events = Vec::new();
+        for (ev_idx,ev) in train_log.iter().enumerate() {
+            if !(visit_events.len() < visits.len()) { 
                 break;
             }
 
-            if event_matches_spec(dgraph, &visits.data()[current_visit].1, ev) {
-                current_visit += 1;
+            if location_events[visit_events.len()].matches(ev) {
+                visit_events.push(ev_idx);
             }
         }
-        if current_visit < visits.data().len() {
+        if visit_events.len() < visits.len() {
             // train train_idx failed to reach its planned locations
-            return Err(TestPlanErr::MissingVisits); 
+            return Err(TestPlanErr::MissingVisits((*train_id, visits[visit_events.len()].0))); 
+        }
+
+        let mut t = 0.0;
+        let mut next_visit = 0;
+        for (ev_idx,ev) in train_log.iter().enumerate() {
+            while next_visit < visit_events.len() && visit_events[next_visit] == ev_idx {
+                visit_times.insert((*train_id, visits[next_visit].0), t);
+                next_visit += 1;
+            }
+            match ev {
+                TrainLogEvent::Wait(dt) | TrainLogEvent::Move(dt, _, _) => { t += dt; },
+                _ => {},
+            }
+        }
+
+        // The train stops at the end of the route reaching the visit (see `schedule_dwells`), 
+        // which can be before or after the visited location, so we look for the stop 
+        // at the end of a route reaching the visit, between the previous and the next visit. 
+        // The train leaves the model after the last visit, so it has no dwell.
+        for (visit_idx, (_,visit)) in visits.iter().enumerate() {
+            if visit_idx + 1 == visits.len() { break; }
+            if let Some(dwell) = visit.dwell {
+                let start = if visit_idx > 0 { visit_events[visit_idx-1] } else { 0 };
+                let end = visit_events[visit_idx+1];
+                let route_ends = visit.locs.iter()
+                    .flat_map(|loc| location_routes(dgraph, il, loc, visit_idx == 0))
+                    .filter_map(|r| il.routes[r].path.last().cloned()).collect::<HashSet<_>>();
+                if standstill_on_edges(&train_log[start..end], &route_ends) + 1e-3 < dwell {
+                    return Err(TestPlanErr::MissingDwell((*train_id, visits[visit_idx].0)));
+                }
+            }
         }
     }
 
     // 2. check ordering constraints and time diff
-    for (ra,rb,dt) in plan_spec.order.iter() {
-        // TODO
+    for (order_idx,(ra,rb,dt)) in plan_spec.order.iter().enumerate() {
+        let ta = *visit_times.get(ra).ok_or(TestPlanErr::MissingVisits(*ra))?;
+        let tb = *visit_times.get(rb).ok_or(TestPlanErr::MissingVisits(*rb))?;
+        if tb < ta { return Err(TestPlanErr::WrongOrder(order_idx)); }
+        if let Some(dt) = dt {
+            if tb - ta > *dt { return Err(TestPlanErr::MaxTimeExceeded(order_idx)); }
+        }
     }
 
     Ok(())
 }
 
+/// Longest time the train stands still on one of the edges, i.e. after entering 
+/// the edge and before entering the next one.
+fn standstill_on_edges(events :&[TrainLogEvent], edges :&HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)>) -> f64 {
+    let (mut longest, mut current, mut on_edge) = (0.0, 0.0, false);
+    for ev in events {
+        match ev {
+            TrainLogEvent::Edge(a, b) => {
+                on_edge = b.map(|b| edges.contains(&(*a,b))).unwrap_or(false);
+                current = 0.0;
+            },
+            TrainLogEvent::Wait(dt) if on_edge => { current += dt; },
+            TrainLogEvent::Move(dt, _, DistanceVelocity { dx, .. }) if on_edge => {
+                if *dx > 0.0 { current = 0.0; } else { current += dt; }
+            },
+            _ => {},
+        }
+        if current > longest { longest = current; }
+    }
+    longest
+}
+
+/// Time when the train came to a stop after its last movement.
+fn stop_time(events :&[TrainLogEvent]) -> f64 {
+    let (mut t, mut stop) = (0.0, 0.0);
+    for ev in events {
+        match ev {
+            TrainLogEvent::Wait(dt) => { t += dt; },
+            TrainLogEvent::Move(dt, _, DistanceVelocity { dx, .. }) => {
+                t += dt;
+                if *dx > 0.0 { stop = t; }
+            },
+            _ => {},
+        }
+    }
+    stop
+}
+
 pub fn test_plan(dgraph :&DGraph,
                  il :&Interlocking, 
                  vehicles :&[(usize,Vehicle)],
                  plan_spec :&PlanSpec,
+                 plan_usage :&planner::input::Usage,
                  candidate :&planner::input::RoutePlan) 
     -> Result<Result<(Commands, History),TestPlanErr>,String> {
     let commands = convert_dispatch_commands(candidate, il, plan_spec)?;
+    let train_commands = schedule_dwells(dgraph, il, vehicles, plan_spec, plan_usage, commands)?;
+    let commands = dispatch_commands(&train_commands);
 
     // simulate the dispatch
     let (history,route_refs) =
-         history::get_history(vehicles, &dgraph.rolling_inf, il, &commands)?;
+         history::get_history(vehicles, dgraph, il, &commands)?;
 
     // then check that the plan is satisfied
-    if let Err(e) = eval_plan(dgraph, plan_spec, &history) { return Ok(Err(e)); }
+    if let Err(e) = eval_plan(dgraph, il, plan_spec, &train_commands, &history) { return Ok(Err(e)); }
     return Ok(Ok((commands,history)));
 }
 
-fn event_matches_spec(dgraph :&DGraph, visit :&Visit, event :&TrainLogEvent) -> bool {
-    if let TrainLogEvent::Node(n) = event {
-        for l in visit.locs.iter() {
-            match l {
-                Ok(Ref::Node(pt)) => {
-                    // have to check both boundaries and switch nodes in the dgraph
-                    if dgraph.node_ids.get_by_left(n) == Some(pt) { return true; }
-                    if dgraph.switch_ids.get_by_left(n) == Some(pt) { return true; }
+/// Position of a plan location in the drawing.
+fn plan_loc_point(loc :&PlanLoc) -> PtC {
+    match loc {
+        Ok(Ref::Node(pt)) => glm::vec2(pt.x as f32, pt.y as f32),
+        Ok(Ref::LineSeg(a,b)) => glm::vec2(0.5*(a.x+b.x) as f32, 0.5*(a.y+b.y) as f32),
+        Ok(Ref::Object(pta)) => unround_coord(*pta),
+        Err(p) => *p,
+    }
+}
+
+/// Train log events where a train reaches one of the locations of a visit.
+/// Boundaries, detectors and signals are reached at their nodes, other 
+/// locations when the train enters the edge passing them.
+struct LocationEvents {
+    nodes :HashSet<rolling_inf::NodeId>,
+    edges :HashSet<(rolling_inf::NodeId, rolling_inf::NodeId)>,
+}
+
+impl LocationEvents {
+    fn new(dgraph :&DGraph, visit :&Visit) -> Self {
+        let mut events = LocationEvents { nodes: HashSet::new(), edges: HashSet::new() };
+        for loc in visit.locs.iter() {
+            let nodes :Vec<rolling_inf::NodeId> = match loc {
+                Ok(Ref::Node(pt)) => match dgraph.switch_ids.get_by_right(pt) {
+                    Some(sw) => switch_nodes(&dgraph.rolling_inf, *sw),
+                    None => dgraph.node_ids.get_by_right(pt).cloned().into_iter().collect(),
+                },
+                Ok(Ref::Object(pta)) => {
+                    if let Some(node) = dgraph.detector_ids.get_by_right(pta) {
+                        vec![*node]
+                    } else if let Some(obj) = dgraph.object_ids.get_by_right(pta) {
+                        dgraph.rolling_inf.nodes.iter().enumerate()
+                            .filter(|(_,n)| n.objects.contains(obj)).map(|(i,_)| i).collect()
+                    } else { Vec::new() }
                 },
-                _ => unimplemented!(), // TODO
+                _ => Vec::new(),
             };
+            if nodes.len() > 0 {
+                events.nodes.extend(nodes);
+            } else {
+                events.edges.extend(dgraph.edges_at(plan_loc_point(loc)));
+            }
         }
+        events
     }
-    false
+
+    /// The visit has no locations on the tracks.
+    fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.edges.is_empty()
+    }
+
+    fn matches(&self, event :&TrainLogEvent) -> bool {
+        match event {
+            TrainLogEvent::Node(n) => self.nodes.contains(n),
+            TrainLogEvent::Edge(a, Some(b)) => self.edges.contains(&(*a,*b)),
+            _ => false,
+        }
+    }
+}
+
+/// Nodes at a switch, on the trunk and on both branches, in both directions.
+fn switch_nodes(inf :&rolling_inf::StaticInfrastructure, sw :rolling_inf::ObjectId) -> Vec<rolling_inf::NodeId> {
+    let mut nodes = Vec::new();
+    for (i,node) in inf.nodes.iter().enumerate() {
+        if matches!(node.edges, rolling_inf::Edges::Switchable(x) if x == sw) {
+            nodes.push(i);
+            nodes.push(node.other_node);
+        }
+    }
+    if let Some(rolling_inf::StaticObject::Switch { left_link, right_link, .. }) = inf.objects.get(sw) {
+        for link in &[left_link, right_link] {
+            nodes.push(link.0);
+            nodes.push(inf.nodes[link.0].other_node);
+        }
+    }
+    nodes
+}
+
+/// Train routes which take a train to a plan location. The first visit is 
+/// at the model boundary where the train enters, later boundaries are 
+/// reached by the routes to them, signals by the routes ending at them, 
+/// and other locations by the routes passing them.
+fn location_routes(dgraph :&DGraph, il :&Interlocking, loc :&PlanLoc, first_visit :bool) -> HashSet<usize> {
+    match loc {
+        Ok(Ref::Node(pt)) => {
+            let bdry = if first_visit { &il.boundary_routes } else { &il.boundary_out_routes };
+            if let Some(routes) = bdry.get(pt) {
+                return routes.iter().cloned().collect();
+            }
+        },
+        Ok(Ref::Object(pta)) => {
+            let routes = il.routes.iter().enumerate()
+                .filter(|(_,r)| r.class == RouteClass::Train && r.id.to == Ref::Object(*pta))
+                .map(|(i,_)| i).collect::<HashSet<_>>();
+            if routes.len() > 0 { return routes; }
+        },
+        _ => {},
+    }
+
+    let edges = dgraph.edges_at(plan_loc_point(loc)).into_iter().collect::<HashSet<_>>();
+    il.routes.iter().enumerate()
+        .filter(|(_,r)| r.class == RouteClass::Train && r.path.iter().any(|e| edges.contains(e)))
+        .map(|(i,_)| i).collect()
 }
 
 pub fn get_dispatches(
@@ -86,15 +290,16 @@ pub fn get_dispatches(
       il :&Interlocking, 
       vehicles :&[(usize,Vehicle)],
       plan :&PlanSpec,
-      ) -> Result<Vec<(Dispatch, History)>, String> {
+      ) -> Result<(Vec<(Dispatch, History)>, Vec<PlanDiagnosis>), String> {
 
-    let routes : HashMap<usize,rolling_inf::Route> = 
-        il.routes.iter().map(|r| r.route.clone()).enumerate().collect();
+    // The planner uses train routes only.
+    let routes : HashMap<usize,&RouteInfo> = il.routes.iter().enumerate()
+        .filter(|(_,r)| r.class == RouteClass::Train).collect();
     let route_specs : HashMap<usize,RouteSpec> = 
         il.routes.iter().map(|r| r.id.clone()).enumerate().collect();
 
     let plan_inf = convert_inf(&routes);
-    let plan_usage = convert_plan(il, vehicles, plan).
+    let plan_usage = convert_plan(dgraph, il, vehicles, plan).
         map_err(|e| format!("{:?}", e))?;
     let config = planner::input::Config {
         n_before: 3, n_after: 3, exact_n: None, optimize_signals: false,
@@ -105,21 +310,177 @@ pub fn get_dispatches(
     //println!("usage {:#?}", plan_usage);
 
     let mut output = Vec::new();
+    let mut n_candidates = 0;
+    let mut failures :HashMap<TestPlanErr, usize> = HashMap::new();
     planner::solver::plan(&config, &plan_inf, &plan_usage, |candidate| {
         //println!("got one plan");
-        if let Ok((cmds,p)) = test_plan(dgraph, il, vehicles, plan, candidate).unwrap() {
-            let name = format!("Dispatch {}", output.len()+1);
-            output.push((Dispatch::from_vec(name,cmds),p));
+        n_candidates += 1;
+        let result = test_plan(dgraph, il, vehicles, plan, &plan_usage, candidate)
+            .unwrap_or_else(|e| {
+                warn!("Candidate dispatch failed: {}", e);
+                Err(TestPlanErr::DispatchFailed)
+            });
+        match result {
+            Ok((cmds,p)) => {
+                let name = format!("Dispatch {}", output.len()+1);
+                output.push((Dispatch::from_vec(name,cmds),p));
+            },
+            Err(e) => { *failures.entry(e).or_insert(0) += 1; },
         }
         false
     });
     //println!("planner finished");
-    Ok(output)
+
+    let diagnosis = if output.len() == 0 {
+        diagnose_plan(dgraph, il, plan, &plan_usage, n_candidates, &failures)
+    } else { Vec::new() };
+    Ok((output, diagnosis))
+}
+
+/// Find out why a plan has no dispatch. Visits which no sequence of routes 
+/// reaches and ordering constraints which contradict each other are
+/// reported first. Otherwise, the failures of the planner's candidates
+/// are reported.
+fn diagnose_plan(dgraph :&DGraph, il :&Interlocking, plan :&PlanSpec, usage :&planner::input::Usage,
+                 n_candidates :usize, failures :&HashMap<TestPlanErr, usize>) -> Vec<PlanDiagnosis> {
+    let mut diagnosis = Vec::new();
+
+    // 1. visits which can not be reached
+    let successors = route_successors(il);
+    for (train_id, (_, visits)) in plan.trains.iter() {
+        let train = match usage.trains.get(train_id) { Some(t) => t, None => continue };
+        let mut reached :HashSet<usize> = HashSet::new();
+        for (visit_idx, ((visit_id,visit), routes)) in visits.iter().zip(train.visits.iter()).enumerate() {
+            if LocationEvents::new(dgraph, visit).is_empty() {
+                diagnosis.push(PlanDiagnosis::new(Some(*train_id), Some(*visit_id), 
+                                                  format!("This location is not on a track.")));
+                break;
+            }
+            if routes.is_empty() {
+                diagnosis.push(PlanDiagnosis::new(Some(*train_id), Some(*visit_id), 
+                                                  format!("No route goes to this location.")));
+                break;
+            }
+            reached = if visit_idx == 0 {
+                routes.iter().filter(|r| matches!(il.routes[**r].route.entry, 
+                                                  rolling_inf::RouteEntryExit::Boundary(_)))
+                    .cloned().collect()
+            } else {
+                reachable_routes(&successors, &reached).intersection(routes).cloned().collect()
+            };
+            if reached.is_empty() {
+                if visit_idx == 0 {
+                    diagnosis.push(PlanDiagnosis::new(Some(*train_id), Some(*visit_id), 
+                        format!("The first visit must be at a model boundary where the train enters.")));
+                } else {
+                    diagnosis.push(PlanDiagnosis::new(Some(*train_id), Some(*visit_id), 
+                        format!("No sequence of routes goes here from the previous visit.")));
+                }
+                break;
+            }
+        }
+    }
+
+    // 2. contradicting ordering constraints
+    let mut next_visits :HashMap<VisitRef, Vec<VisitRef>> = HashMap::new();
+    for (train_id, (_, visits)) in plan.trains.iter() {
+        for ((a,_),(b,_)) in visits.iter().zip(visits.iter().skip(1)) {
+            next_visits.entry((*train_id,*a)).or_insert(Vec::new()).push((*train_id,*b));
+        }
+    }
+    for (a,b,_) in plan.order.iter() {
+        next_visits.entry(*a).or_insert(Vec::new()).push(*b);
+    }
+    for (a,b,_) in plan.order.iter() {
+        if visit_reachable(&next_visits, *b, *a) {
+            diagnosis.push(PlanDiagnosis::new(Some(a.0), Some(a.1), 
+                format!("Ordering constraint before train {} visit {} contradicts the other constraints \
+                         or the order of the visits.", b.0, b.1)));
+        }
+    }
+
+    if diagnosis.len() > 0 { return diagnosis; }
+
+    // 3. failed candidates
+    if n_candidates == 0 {
+        diagnosis.push(PlanDiagnosis::new(None, None, 
+                       format!("The planner found no candidate dispatch with these trains and visits.")));
+    }
+    let mut failures = failures.iter().collect::<Vec<_>>();
+    failures.sort_by_key(|(_,n)| std::cmp::Reverse(**n));
+    for (err,n) in failures {
+        let of = format!("in {} of {} candidate dispatches", n, n_candidates);
+        let (train, visit, message) = match err {
+            TestPlanErr::MissingVisits((t,v)) => 
+                (Some(*t), Some(*v), format!("The train did not reach this visit {}.", of)),
+            TestPlanErr::MissingTrain(t) => 
+                (Some(*t), None, format!("The train was missing from the simulation {}.", of)),
+            TestPlanErr::MissingDwell((t,v)) => 
+                (Some(*t), Some(*v), format!("The train did not stop for the dwell time {}.", of)),
+            TestPlanErr::WrongOrder(i) => {
+                let (a,b,_) = plan.order[*i];
+                (Some(a.0), Some(a.1), format!("The train came after train {} visit {} {}.", b.0, b.1, of))
+            },
+            TestPlanErr::MaxTimeExceeded(i) => {
+                let (a,b,_) = plan.order[*i];
+                (Some(a.0), Some(a.1), format!("The maximum time to train {} visit {} was exceeded {}.", 
+                                               b.0, b.1, of))
+            },
+            TestPlanErr::DispatchFailed => 
+                (None, None, format!("The dispatch could not be simulated {}.", of)),
+        };
+        diagnosis.push(PlanDiagnosis::new(train, visit, message));
+    }
+    diagnosis
 }
 
+/// Train routes which can follow each train route, i.e. start at its exit signal.
+fn route_successors(il :&Interlocking) -> HashMap<usize, Vec<usize>> {
+    let signal = |e :&rolling_inf::RouteEntryExit| match e {
+        rolling_inf::RouteEntryExit::Signal(s) |
+        rolling_inf::RouteEntryExit::SignalTrigger { signal: s, .. } => Some(*s),
+        _ => None,
+    };
+    let mut signal_routes :HashMap<rolling_inf::ObjectId, Vec<usize>> = HashMap::new();
+    for (i,r) in il.routes.iter().enumerate() {
+        if r.class != RouteClass::Train { continue; }
+        if let Some(s) = signal(&r.route.entry) { signal_routes.entry(s).or_insert(Vec::new()).push(i); }
+    }
+    il.routes.iter().enumerate()
+        .filter(|(_,r)| r.class == RouteClass::Train)
+        .map(|(i,r)| (i, signal(&r.route.exit).and_then(|s| signal_routes.get(&s)).cloned().unwrap_or(Vec::new())))
+        .collect()
+}
+
+/// Routes reachable from the given routes, including themselves.
+fn reachable_routes(successors :&HashMap<usize, Vec<usize>>, start :&HashSet<usize>) -> HashSet<usize> {
+    let mut reached = start.clone();
+    let mut stack = start.iter().cloned().collect::<Vec<_>>();
+    while let Some(r) = stack.pop() {
+        for next in successors.get(&r).into_iter().flatten() {
+            if reached.insert(*next) { stack.push(*next); }
+        }
+    }
+    reached
+}
+
+fn visit_reachable(next_visits :&HashMap<VisitRef, Vec<VisitRef>>, from :VisitRef, to :VisitRef) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(v) = stack.pop() {
+        if v == to { return true; }
+        if !visited.insert(v) { continue; }
+        stack.extend(next_visits.get(&v).into_iter().flatten().cloned());
+    }
+    false
+}
+
+
+/// Dispatch command with the train and the route it sets.
+pub type TrainCommand = (ListId, usize, (f64, Command));
 
 fn convert_dispatch_commands(routeplan :&planner::input::RoutePlan, il :&Interlocking,
-                          plan :&PlanSpec) -> Result<Commands,String> {
+                          plan :&PlanSpec) -> Result<Vec<TrainCommand>,String> {
 
     use std::collections::BTreeSet;
 
@@ -139,13 +500,13 @@ fn convert_dispatch_commands(routeplan :&planner::input::RoutePlan, il :&Interlo
             match il.routes[*new_route].route.entry {
                 rolling_inf::RouteEntryExit::Boundary(_) => {
                     // Spawn new train
-                    commands.push((0.0, Command::Train(
+                    commands.push((*train_id, *new_route, (0.0, Command::Train(
                                 plan.trains.get(*train_id).unwrap().0.unwrap(), //vehicle id
-                                il.routes[*new_route].id)));
+                                il.routes[*new_route].id))));
                 },
                 rolling_inf::RouteEntryExit::Signal(_) 
                     | rolling_inf::RouteEntryExit::SignalTrigger { .. } => {
-                        commands.push((0.0, Command::Route(il.routes[*new_route].id)));
+                        commands.push((*train_id, *new_route, (0.0, Command::Route(il.routes[*new_route].id))));
                 },
             }
         }
@@ -153,15 +514,179 @@ fn convert_dispatch_commands(routeplan :&planner::input::RoutePlan, il :&Interlo
         last_active_routes = active_routes;
     }
 
-    Ok(commands.into_iter().enumerate().collect())
+    Ok(commands)
+}
+
+/// Sort the commands by time, keeping the planned order for commands at the same time.
+fn dispatch_commands(commands :&[TrainCommand]) -> Commands {
+    let mut commands = commands.iter().map(|(_,_,c)| *c).collect::<Vec<_>>();
+    commands.sort_by_key(|(t,_)| OrderedFloat(*t));
+    commands.into_iter().enumerate().collect()
+}
+
+/// Plan train of each train in the simulated dispatch. The trains are simulated in 
+/// the order of their `Command::Train` in the commands sorted by time, see `dispatch_commands`.
+fn history_trains(commands :&[TrainCommand]) -> Vec<ListId> {
+    let mut sorted = commands.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|(_,_,(t,_))| OrderedFloat(*t));
+    sorted.into_iter().filter(|(_,_,(_,c))| matches!(c, Command::Train(..)))
+        .map(|(train_id,_,_)| *train_id).collect()
 }
 
+/// Find the commands where trains leave visits which have a dwell time, with the 
+/// route reaching the visit and the dwell time. A visit is reached by the first of the 
+/// train's routes in the visit's set of routes (see `convert_plan`), and the train 
+/// leaves with its next route.
+fn dwell_departures(plan :&PlanSpec, usage :&planner::input::Usage, 
+                    commands :&[TrainCommand]) -> Vec<(usize, usize, f64)> {
+    let mut departures = Vec::new();
+    for (train_id, (_, visits)) in plan.trains.iter() {
+        let train = match usage.trains.get(train_id) { Some(t) => t, None => continue };
+        let train_commands = commands.iter().enumerate()
+            .filter(|(_,(t,_,_))| t == train_id)
+            .map(|(cmd_idx,(_,route_idx,_))| (cmd_idx, *route_idx)).collect::<Vec<_>>();
+        let mut i = 0;
+        for ((_,visit), routes) in visits.iter().zip(train.visits.iter()) {
+            while i < train_commands.len() && !routes.contains(&train_commands[i].1) { i += 1; }
+            if i >= train_commands.len() { break; }
+            if let (Some(dwell), Some((departure,_))) = (visit.dwell, train_commands.get(i+1)) {
+                departures.push((*departure, train_commands[i].1, dwell));
+            }
+        }
+    }
+    departures.sort_by_key(|(cmd_idx,_,_)| *cmd_idx);
+    departures
+}
 
-pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infrastructure {
+/// The last edge the train entered.
+fn last_edge(events :&[TrainLogEvent]) -> Option<(rolling_inf::NodeId, rolling_inf::NodeId)> {
+    events.iter().rev().find_map(|ev| match ev {
+        TrainLogEvent::Edge(a, Some(b)) => Some((*a,*b)),
+        _ => None,
+    })
+}
+
+/// Delay the departures from visits with a dwell time. Each train is held at 
+/// the end of the route reaching its next visit with a dwell time by simulating 
+/// the dispatch without its departure, and the departure and the train's following 
+/// routes are set the dwell time after the train has stopped. All trains are held 
+/// in the same simulation, so the dispatch is simulated once for each dwell of 
+/// a train. A train kept from reaching its visit by another held train is held 
+/// again after the other train's departure is set.
+fn schedule_dwells(dgraph :&DGraph, il :&Interlocking, vehicles :&[(usize,Vehicle)],
+                   plan :&PlanSpec, usage :&planner::input::Usage,
+                   mut commands :Vec<TrainCommand>) -> Result<Vec<TrainCommand>, String> {
+    let mut pending = dwell_departures(plan, usage, &commands);
+    while pending.len() > 0 {
+        // The first pending departure of each train.
+        let mut held :Vec<(usize, usize, f64)> = Vec::new();
+        for d in pending.iter() {
+            if !held.iter().any(|(h,_,_)| commands[*h].0 == commands[d.0].0) { held.push(*d); }
+        }
+        let held_commands = commands.iter().enumerate()
+            .filter(|(cmd_idx,(t,_,_))| !held.iter().any(|(h,_,_)| *cmd_idx >= *h && commands[*h].0 == *t))
+            .map(|(_,c)| *c).collect::<Vec<_>>();
+        let trains = history_trains(&held_commands);
+        let (history,_route_refs) = history::get_history(vehicles, dgraph, il, &dispatch_commands(&held_commands))?;
+
+        let mut stops = Vec::new();
+        for (departure, arrival, dwell) in held.iter() {
+            let train_id = commands[*departure].0;
+            let train_idx = trains.iter().position(|t| *t == train_id)
+                .ok_or(format!("Train missing from dispatch"))?;
+            let (_,_,train_log) = history.trains.get(train_idx)
+                .ok_or(format!("Train missing from simulation"))?;
+            let at_visit = last_edge(train_log) == il.routes[*arrival].path.last().cloned();
+            stops.push((*departure, at_visit, stop_time(train_log) + dwell));
+        }
+
+        // Trains which did not reach their visits wait for other held trains.
+        // If no train reached its visit, the first departure is set anyway.
+        if stops.iter().any(|(_,at_visit,_)| *at_visit) {
+            stops.retain(|(_,at_visit,_)| *at_visit);
+        } else {
+            stops.truncate(1);
+        }
+        for (departure, _, time) in stops {
+            let train_id = commands[departure].0;
+            for (cmd_idx,(t,_,(cmd_time,_))) in commands.iter_mut().enumerate() {
+                if cmd_idx >= departure && *t == train_id && *cmd_time < time { *cmd_time = time; }
+            }
+            pending.retain(|(d,_,_)| *d != departure);
+        }
+    }
+    Ok(commands)
+}
+
+
+fn is_last_partial(route :&rolling_inf::Route, i :usize) -> bool {
+    i + 1 == route.resources.releases.len()
+}
+
+/// Number of choices for a partial route, which is the number of 
+/// overlap alternatives for the last partial route of the route.
+fn n_choices(route :&rolling_inf::Route, i :usize) -> usize {
+    if is_last_partial(route, i) { route.overlaps.len().max(1) } else { 1 }
+}
+
+/// How a partial route uses a resource, see `ResourceUse::compatible`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ResourceUse {
+    /// Section, switch in the given position, or entry signal used by the route itself.
+    Route(Option<rolling_inf::SwitchPosition>),
+    /// Section or switch in the given position used by the overlap of the route.
+    Overlap(Option<rolling_inf::SwitchPosition>),
+    /// Switch held in the given position, or signal held at stop, for flank protection.
+    Flank(Option<rolling_inf::SwitchPosition>),
+}
+
+impl ResourceUse {
+    /// Uses of the same resource by different routes are compatible when the 
+    /// overlaps share a section, when overlaps or flank protection need a switch in 
+    /// the same position, when a route runs over a flank protection switch in 
+    /// its protecting position, or when flank protection holds the same signal.
+    fn compatible(&self, other :&ResourceUse) -> bool {
+        use ResourceUse::*;
+        match (self, other) {
+            (Route(_), Route(_)) | (Route(_), Overlap(_)) | (Overlap(_), Route(_)) => false,
+            (Overlap(a), Overlap(b)) | (Flank(a), Flank(b)) => a == b,
+            (Flank(a), Route(b)) | (Flank(a), Overlap(b)) |
+            (Route(b), Flank(a)) | (Overlap(b), Flank(a)) => a.is_some() && a == b,
+        }
+    }
+}
+
+/// Use of each switch and flank protection element in the route's resources. 
+/// Other resources are used by the route itself.
+fn route_resource_uses(info :&RouteInfo) -> HashMap<rolling_inf::ObjectId, ResourceUse> {
+    let mut uses = HashMap::new();
+    for (sw,pos) in info.route.resources.switch_positions.iter() {
+        uses.insert(*sw, ResourceUse::Route(Some(*pos)));
+    }
+    for (element,_) in info.flank.iter() {
+        match element {
+            FlankProtection::Switch(sw,pos) => { uses.insert(*sw, ResourceUse::Flank(Some(*pos))); },
+            FlankProtection::Signal(signal) => { uses.insert(*signal, ResourceUse::Flank(None)); },
+        }
+    }
+    uses
+}
+
+/// Partial routes conflict when they use the same resource in incompatible ways, 
+/// see `ResourceUse::compatible`. The last partial route of a route has one choice 
+/// for each overlap alternative, and the choice also uses the sections and switches 
+/// of its overlap. Overlaps of different routes may share sections, as an overlap is 
+/// only used when a train fails to stop, but they conflict when they need a switch 
+/// in different positions. The flank protection of a route is part of its resources, 
+/// see `interlocking::add_flank_resources`.
+pub fn convert_inf(routes :&HashMap<usize, &RouteInfo>) -> planner::input::Infrastructure {
 
     let mut partial_routes = HashMap::new();
     let mut elementary_routes = Vec::new();
-    let mut partial_route_resources :HashMap<usize, HashSet<planner::input::PartialRouteId>> = HashMap::new();
+    // Resources used by each partial route and choice of overlap. 
+    let mut partial_route_uses :HashMap<(planner::input::PartialRouteId, usize), Vec<(rolling_inf::ObjectId, ResourceUse)>> = HashMap::new();
+    // Partial routes and choices using each resource.
+    let mut resource_users :HashMap<rolling_inf::ObjectId, Vec<((planner::input::PartialRouteId, usize), ResourceUse)>> = HashMap::new();
     let mut fresh = { let mut i = 0; move || { i += 1; i } };
 
     fn convert_routeentryexit(e :&rolling_inf::RouteEntryExit) -> planner::input::SignalId {
@@ -177,7 +702,9 @@ pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infra
     let mut route_boundaries :Vec<(planner::input::PartialRouteId, 
                                    planner::input::PartialRouteId, 
                                    planner::input::SignalId)> = Vec::new();
-    for (route_name,route) in routes.iter() {
+    for (route_name,info) in routes.iter() {
+        let route = &info.route;
+        let route_uses = route_resource_uses(info);
         let mut signals = vec![convert_routeentryexit(&route.entry)];
         for i in 0..(route.resources.releases.len()-1) {
             // Add each release's end/exit detector, except the 
@@ -212,10 +739,19 @@ pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infra
                 length: length as _,
             });
 
-            for resource in resources.iter() {
-                partial_route_resources.entry(*resource)
-                    .or_insert(HashSet::new())
-                    .insert((*route_name, i));
+            for choice in 0..n_choices(route, i) {
+                let mut uses = resources.iter()
+                    .map(|r| (*r, route_uses.get(r).cloned().unwrap_or(ResourceUse::Route(None))))
+                    .collect::<Vec<_>>();
+                if let Some(overlap) = route.overlaps.get(choice).filter(|_| is_last_partial(route, i)) {
+                    uses.extend(overlap.sections.iter().map(|s| (*s, ResourceUse::Overlap(None))));
+                    uses.extend(overlap.switch_positions.iter().map(|(sw,pos)| (*sw, ResourceUse::Overlap(Some(*pos)))));
+                }
+                for (resource, resource_use) in uses.iter() {
+                    resource_users.entry(*resource).or_insert(Vec::new())
+                        .push((((*route_name, i), choice), *resource_use));
+                }
+                partial_route_uses.insert(((*route_name, i), choice), uses);
             }
             elementary_route.insert((*route_name, i));
         }
@@ -223,20 +759,23 @@ pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infra
     }
 
     // second pass adds conflicting routes from resource -> partialroutes map
-    for (rn,r) in routes.iter() {
+    for (rn,info) in routes.iter() {
+        let r = &info.route;
         if r.resources.releases.len() > 0 {
-            for (i,rel) in r.resources.releases.iter().enumerate() {
-                let mut conflicting_routes = HashSet::new();
-                for resource in rel.resources.iter() {
-                    if let Some(conflicts) = partial_route_resources.get(resource) {
-                        conflicting_routes.extend(conflicts.iter().cloned()
-                                                  .filter(|(pr_e,pr_p)| pr_e != rn)
-                                                  .map(|pr| (pr,0)));
+            for i in 0..r.resources.releases.len() {
+                // The last partial route has one choice for each overlap alternative.
+                let choices = (0..n_choices(r,i)).map(|choice| {
+                    let mut conflicting_routes = HashSet::new();
+                    for (resource, resource_use) in partial_route_uses[&((*rn,i),choice)].iter() {
+                        conflicting_routes.extend(resource_users.get(resource).into_iter().flatten()
+                                                  .filter(|(_,other_use)| !resource_use.compatible(other_use))
+                                                  .map(|(pr,_)| *pr));
                     }
-                }
+                    conflicting_routes.retain(|((pr_e,_pr_p),_)| pr_e != rn);
+                    conflicting_routes
+                }).collect();
 
-                partial_routes.get_mut(&(*rn,i)).unwrap().conflicts =
-                    vec![conflicting_routes]; // TODO overlap alternatives
+                partial_routes.get_mut(&(*rn,i)).unwrap().conflicts = choices;
             }
         } else {
             // There are no resources, but we have to add the overlap choice anyway
@@ -260,12 +799,12 @@ pub fn convert_inf(routes :&rolling_inf::Routes<usize>) -> planner::input::Infra
         }
     }
 
-
     planner::input::Infrastructure { partial_routes, elementary_routes }
 }
 
 
-pub fn convert_plan(il :&Interlocking, 
+pub fn convert_plan(dgraph :&DGraph,
+                    il :&Interlocking, 
                     vehicles :&[(usize,Vehicle)], 
                     plan :&PlanSpec) -> Result<planner::input::Usage, ConvertPlanErr> {
 
@@ -277,13 +816,8 @@ pub fn convert_plan(il :&Interlocking,
         let mut planner_visits :Vec<HashSet<usize>> = Vec::new();
         for (visit_i, (visit_id, Visit { locs, dwell})) in visits.iter().enumerate() {
             let mut set = HashSet::new();
-            let bdry = if visit_i == 0 { &il.boundary_routes } else { &il.boundary_out_routes };
-            for (loc_i, loc) in locs.iter().enumerate() {
-                if let Ok(Ref::Node(pt)) = loc {
-                    set.extend(bdry.get(pt).into_iter().flat_map(move |rs| rs.iter()));
-                }  else {
-                    unimplemented!(); // TODO  other types of infrastructure references
-                }
+            for loc in locs.iter() {
+                set.extend(location_routes(dgraph, il, loc, visit_i == 0));
             }
             planner_visits.push(set);
         }
@@ -295,14 +829,15 @@ pub fn convert_plan(il :&Interlocking,
 
     let mut train_ord = Vec::new();
     for ((train_a,visit_a),(train_b,visit_b), _max_time) in &plan.order {
-        // TODO max_time between visits
-        let visit_idx = |train_id, visit_id| plan.trains.get(train_id).unwrap()
-            .1.iter().position(|(v,_)| v == visit_id).unwrap(); 
-        // TODO unwrap crashes if visit_id is missing
+        // The planner only orders the visits, the maximum time 
+        // between them is checked on the simulated dispatch in `eval_plan`.
+        let visit_idx = |train_id, visit_id| plan.trains.get(train_id)
+            .and_then(|(_,visits)| visits.iter().position(|(v,_)| v == visit_id))
+            .ok_or(ConvertPlanErr::VisitMissing);
 
         train_ord.push(planner::input::TrainOrd {
-            a: (*train_a, visit_idx(*train_a, visit_a)),
-            b: (*train_b, visit_idx(*train_b, visit_b)),
+            a: (*train_a, visit_idx(*train_a, visit_a)?),
+            b: (*train_b, visit_idx(*train_b, visit_b)?),
         });
 
     }
@@ -313,3 +848,43 @@ pub fn convert_plan(il :&Interlocking,
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::model::test_models::*;
+
+    #[test]
+    fn order_of_train_entering_second() {
+        // Two unconnected lines, where the second train of the plan enters first.
+        let mut model = straight_line(20);
+        add_line(&mut model, 5, 0, 20);
+        let (dgraph, il) = interlocking(&model);
+        let vehicle = model.vehicles.data()[0].0;
+        let route = |y| RouteSpec { from: Ref::Node(glm::vec2(0,y)), to: Ref::Node(glm::vec2(20,y)), alternative: 0 };
+
+        let mut plan = PlanSpec::new_empty("Plan".to_string());
+        let mut add_train = |y| {
+            let mut visits = ImShortGenList::new();
+            let visit = visits.insert(Visit { 
+                locs: vec![Ok(Ref::LineSeg(glm::vec2(10,y), glm::vec2(11,y)))], dwell: None });
+            (plan.trains.insert((Some(vehicle), visits)), visit)
+        };
+        let (first, second) = (add_train(0), add_train(5));
+
+        let commands = vec![
+            (first.0, *il.find_route(&route(0)).unwrap(), (100.0, Command::Train(vehicle, route(0)))),
+            (second.0, *il.find_route(&route(5)).unwrap(), (0.0, Command::Train(vehicle, route(5)))),
+        ];
+        assert_eq!(history_trains(&commands), vec![second.0, first.0]);
+        let (history,_) = history::get_history(model.vehicles.data(), &dgraph, &il, 
+                                               &dispatch_commands(&commands)).unwrap();
+
+        plan.order = vec![(second, first, None)];
+        assert!(eval_plan(&dgraph, &il, &plan, &commands, &history).is_ok());
+        plan.order = vec![(first, second, None)];
+        assert!(matches!(eval_plan(&dgraph, &il, &plan, &commands, &history), Err(TestPlanErr::WrongOrder(0))));
+        plan.order = vec![(second, first, Some(50.0))];
+        assert!(matches!(eval_plan(&dgraph, &il, &plan, &commands, &history), Err(TestPlanErr::MaxTimeExceeded(0))));
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{BTreeSet, BTreeMap,HashMap, VecDeque};
+use std::collections::{BTreeSet, BTreeMap,HashMap, HashSet, VecDeque};
 use nalgebra_glm as glm;
 
 use crate::document::model::*;
@@ -15,6 +15,15 @@ pub struct Topology {
     pub locations : HashMap<Pt,(NDType,Vc)>,
     pub trackobjects : Vec<Vec<(f64,PtA, Function,Option<AB>)>>,
     pub interval_lines :Vec<Vec<(OrderedFloat<f64>,PtC)>>,
+    /// Gradient profile of each track as (start, end, per mille) intervals,
+    /// positive when rising in the direction of increasing track position.
+    pub gradients :Vec<Vec<(f64,f64,f64)>>,
+    /// Fixed mileage of nodes and objects.
+    pub mileage_anchors :HashMap<Ref,f64>,
+    /// Model boundaries where the mileage starts, see `Model::mileage_origins`.
+    pub mileage_origins :HashSet<Pt>,
+    /// Sight distance for signals without their own, see `ModelSettings::sight_distance`.
+    pub sight_distance :f64,
 }
 
 impl Topology {
@@ -51,6 +60,18 @@ impl Topology {
 
         output
     }
+
+    /// Length-weighted mean gradient (per mille) between two positions on a track,
+    /// positive when rising in the direction from `start` to `end`.
+    pub fn mean_gradient(&self, track_idx :usize, start :f64, end :f64) -> f64 {
+        let (lo,hi) = if start < end { (start,end) } else { (end,start) };
+        if !(hi > lo) { return 0.0; }
+        let rise :f64 = self.gradients[track_idx].iter().map(|(a,b,g)| {
+            (hi.min(*b) - lo.max(*a)).max(0.0) * g
+        }).sum();
+        let g = rise / (hi - lo);
+        if start < end { g } else { -g }
+    }
 }
 
 
@@ -68,13 +89,17 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         pieces.insert(((a.x,a.y),(b.x,b.y)));
     }
 
+    let seg_len = |a :(i32,i32), b :(i32,i32)| -> f64 {
+        model.lineseg_length(glm::vec2(a.0,a.1), glm::vec2(b.0,b.1)).unwrap_or(def_len)
+    };
+
     let mut piece_map : HashMap<((i32,i32),(i32,i32)), (usize, f64, f64)> = HashMap::new();
     let mut trackobjects = Vec::new();
+    let mut gradients = Vec::new();
     while let Some((p1,p2)) = pieces.remove_any() {
         let mut list = VecDeque::new();
         list.push_back((p1,p2));
 
-        let mut length = def_len;
         let (mut a, mut b) = ((p1,p2),(p2,p1));
         drop(p1);drop(p2);
 
@@ -93,7 +118,6 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
                     } else { panic!(); }
 
                     *p = (n,p.0);
-                    length += def_len;
 
                 } else {
                     break;
@@ -103,6 +127,7 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
 
         extend(&mut a, b.0);
         extend(&mut b, a.0);
+        let length :f64 = list.iter().map(|(p,q)| seg_len(*p,*q)).sum();
         let track_idx = tracks.len();
         tracks.push((to_vec(a.0),to_vec(b.0),length));
         locs.entry(a.0).or_insert(Vec::new()).push(((track_idx, AB::A), to_vec(a.1)));
@@ -112,11 +137,19 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         //println!("List {:?}", list);
         let mut l = 0.0;
         let mut interval_map = Vec::new();
+        let mut gradient_map :Vec<(f64,f64,f64)> = Vec::new();
         for (a,b) in list.iter().cloned() {
-            piece_map.insert((a,b), (tracks.len()-1, l, def_len));
+            let seg_length = seg_len(a,b);
+            piece_map.insert((a,b), (tracks.len()-1, l, seg_length));
             interval_map.push((OrderedFloat(l),glm::vec2(a.0 as f32 ,a.1 as f32)));
-            l += def_len;
+            let gradient = model.lineseg_gradient(to_vec(a), to_vec(b));
+            match gradient_map.last_mut() {
+                Some((_,end,g)) if *g == gradient => { *end = l + seg_length; },
+                _ => { gradient_map.push((l, l + seg_length, gradient)); },
+            }
+            l += seg_length;
         }
+        gradients.push(gradient_map);
         let last_pt = list[list.len()-1].1;
         interval_map.push((OrderedFloat(l),glm::vec2(last_pt.0 as f32, last_pt.1 as f32)));
         interval_lines.push(interval_map);
@@ -144,7 +177,9 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         None
     };
 
-    for (id,Object { loc, functions, .. }) in model.objects.iter() {
+    let mut mileage_anchors = HashMap::new();
+    for (id,Object { loc, functions, mileage, .. }) in model.objects.iter() {
+        if let Some(km) = mileage { mileage_anchors.insert(Ref::Object(*id), *km); }
         if let Some((pt,param,_)) = model.get_closest_lineseg(*loc) {
             if let Some((track_idx,pos_a, pos_b, dir)) = get_from_piece_map((pt.0.x,pt.0.y), (pt.1.x,pt.1.y)) {
                 let pos = glm::lerp_scalar(pos_a, pos_b, param as f64);
@@ -157,12 +192,24 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
                         Function::Detector => {
                             track_objs.push((pos,*id,Function::Detector,None));
                         },
-                        Function::MainSignal { has_distant } => {
-                            
-                            // TODO this seems unnecessary when we can simply copy the `Function`s.
-                            track_objs.push((pos,*id, Function::MainSignal { has_distant: *has_distant },
+                        Function::MainSignal { has_distant, sight } => {
+                            // Signals without their own sight distance get the model default.
+                            let sight = Some(sight.unwrap_or(model.settings.sight_distance));
+                            track_objs.push((pos,*id, Function::MainSignal { has_distant: *has_distant, sight },
+                                             Some(get_dir_from_side(&pt, *loc))));
+                        },
+                        Function::ShuntingSignal { sight } => {
+                            let sight = Some(sight.unwrap_or(model.settings.sight_distance));
+                            track_objs.push((pos,*id, Function::ShuntingSignal { sight },
                                              Some(get_dir_from_side(&pt, *loc))));
-                        }
+                        },
+                        Function::SpeedRestriction { speed } => {
+                            track_objs.push((pos,*id, Function::SpeedRestriction { speed: *speed },
+                                             Some(get_dir_from_side(&pt, *loc))));
+                        },
+                        Function::MileageBreak { .. } => {
+                            track_objs.push((pos,*id,*f,None));
+                        },
                     }
                 }
             } else {
@@ -218,7 +265,7 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
             set_trackend(track_ends[pm[0]], (node_pt, Port::Trunk));
             set_trackend(track_ends[pm[1]], (node_pt, side.opposite().as_port()));
             set_trackend(track_ends[pm[2]], (node_pt, side.as_port()));
-            set_node(node_pt, NDType::Sw(side), qs[pm[1]] - node_pt);
+            set_node(node_pt, NDType::Sw(side, None), qs[pm[1]] - node_pt);
             break;
         }
         if found { Ok(()) } else { Err(()) }
@@ -256,7 +303,7 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
         for (n,((t1,q1),(t2,q2))) in connect_pairs.into_iter().enumerate() {
             set_trackend(t1, (node_pt, Port::Cross(AB::A, n)));
             set_trackend(t2, (node_pt, Port::Cross(AB::B, n)));
-            if n == 0 { set_node(node_pt, NDType::Crossing(CrossingType::Crossover), q1 - node_pt); }
+            if n == 0 { set_node(node_pt, NDType::Crossing(CrossingType::Crossover, None), q1 - node_pt); }
         }
         Ok(())
     }
@@ -299,16 +346,29 @@ pub fn convert(model :&Model, def_len :f64) -> Result<Topology, ()>{
 
     for (pt,x) in model.node_data.iter() {
         if let Some((ndtype,tangent)) = locx.get_mut(pt) {
-            *ndtype = *x;
+            *ndtype = match (*ndtype, *x) {
+                // The switch side follows the drawing, only the speed restriction is stored.
+                (NDType::Sw(side,_), NDType::Sw(_,speed)) => NDType::Sw(side,speed),
+                _ => *x,
+            };
         }
     }
 
+    for (pt,km) in model.node_mileage.iter() {
+        if locx.contains_key(pt) { mileage_anchors.insert(Ref::Node(*pt), *km); }
+    }
+    let mileage_origins = model.mileage_origins.iter().filter(|pt| locx.contains_key(pt)).cloned().collect();
+
     Ok(
         Topology {
             tracks: tp.into_iter().map(|(a,b,l)| (l, a.unwrap(), b.unwrap())).collect(),
             locations: locx,
             trackobjects: trackobjects,
             interval_lines: interval_lines, 
+            gradients: gradients,
+            mileage_anchors: mileage_anchors,
+            mileage_origins: mileage_origins,
+            sight_distance: model.settings.sight_distance,
         }
     )
 }
@@ -0,0 +1,518 @@
+use std::collections::{HashMap, BTreeSet};
+use log::*;
+use matches::matches;
+use ordered_float::OrderedFloat;
+
+use crate::document::model::*;
+use crate::document::objects::*;
+use crate::document::topology::Topology;
+use crate::document::dgraph::DGraph;
+use crate::document::interlocking::{Interlocking, FlankProtection};
+use crate::document::plan;
+use crate::document::analysis::Analysis;
+
+use railmlio::model as railml;
+
+/// The track end port on the other side of the node which continues
+/// straight through the node. Tracks connected in this way are joined into
+/// the same railML track.
+fn continuation(nd :&NDType, port :Port) -> Option<Port> {
+    match (nd, port) {
+        (NDType::Cont, Port::ContA) => Some(Port::ContB),
+        (NDType::Cont, Port::ContB) => Some(Port::ContA),
+        (NDType::Sw(side,_), Port::Trunk) => Some(side.opposite().as_port()),
+        (NDType::Sw(side,_), p) if p == side.opposite().as_port() => Some(Port::Trunk),
+        (NDType::Crossing(_,_), Port::Cross(ab,0)) => Some(Port::Cross(ab.other(),0)),
+        _ => None,
+    }
+}
+
+fn track_end(tracks :&[(f64,(Pt,Port),(Pt,Port))], idx :usize, ab :AB) -> (Pt,Port) {
+    match ab { AB::A => tracks[idx].1, AB::B => tracks[idx].2 }
+}
+
+/// Join the topology's tracks into chains which continue straight through
+/// continuation nodes, switches (trunk to straight leg) and crossings.
+/// Each chain is a list of (track index, forward direction).
+fn track_chains(topology :&Topology) -> Vec<Vec<(usize,bool)>> {
+    let tracks = &topology.tracks;
+    let mut ends :HashMap<(Pt,Port),(usize,AB)> = HashMap::new();
+    for (i,(_,a,b)) in tracks.iter().enumerate() {
+        ends.insert(*a, (i,AB::A));
+        ends.insert(*b, (i,AB::B));
+    }
+
+    let next = |idx :usize, fwd :bool, backward :bool| -> Option<(usize,bool)> {
+        let out_end = if fwd != backward { AB::B } else { AB::A };
+        let (pt,port) = track_end(tracks, idx, out_end);
+        let (nd,_) = topology.locations.get(&pt)?;
+        let (j,ab) = *ends.get(&(pt, continuation(nd, port)?))?;
+        // The next track is traversed away from the node when walking
+        // forward, and towards the node when walking backward.
+        Some((j, (ab == AB::A) != backward))
+    };
+
+    let mut visited = vec![false; tracks.len()];
+    let mut chains = Vec::new();
+    for i in 0..tracks.len() {
+        if visited[i] { continue; }
+
+        // Go backwards to the start of the chain
+        let mut start = (i,true);
+        let mut steps = 0;
+        while let Some(prev) = next(start.0, start.1, true) {
+            if prev.0 == i || steps > tracks.len() { break; } // loop
+            start = prev;
+            steps += 1;
+        }
+
+        let mut chain = vec![start];
+        visited[start.0] = true;
+        let mut current = start;
+        while let Some(n) = next(current.0, current.1, false) {
+            if visited[n.0] { break; }
+            visited[n.0] = true;
+            chain.push(n);
+            current = n;
+        }
+        chains.push(chain);
+    }
+    chains
+}
+
+/// Convert the drawn infrastructure to a railML 2.x document.
+/// Switches and crossings are placed on the railML track which continues
+/// straight through them, and the diverging tracks are connected to them.
+/// Parts of the model which cannot be represented in the document are
+/// returned as warnings.
+pub fn convert_railml(model :&Model, topology :&Topology, dgraph :&DGraph) -> (railml::RailML, Vec<String>) {
+    let tracks = &topology.tracks;
+    let mut warnings = Vec::new();
+    let chains = track_chains(topology);
+
+    // Position of each topology track on the railML tracks.
+    let mut track_pos :HashMap<usize,(usize,f64,bool)> = HashMap::new();
+    let mut chain_lengths = Vec::new();
+    for (chain_idx,chain) in chains.iter().enumerate() {
+        let mut offset = 0.0;
+        for (track_idx,fwd) in chain.iter() {
+            track_pos.insert(*track_idx, (chain_idx, offset, *fwd));
+            offset += tracks[*track_idx].0;
+        }
+        chain_lengths.push(offset);
+    }
+
+    let end_pos = |idx :usize, ab :AB| -> (usize,f64) {
+        let (chain_idx, offset, fwd) = track_pos[&idx];
+        let at_chain_end = (ab == AB::B) == fwd;
+        (chain_idx, if at_chain_end { offset + tracks[idx].0 } else { offset })
+    };
+
+    // Connection ids of the chain ends.
+    let mut end_conn_ids :HashMap<(Pt,Port),String> = HashMap::new();
+    let mut chain_ends = Vec::new();
+    for (chain_idx,chain) in chains.iter().enumerate() {
+        let (first,first_fwd) = chain[0];
+        let (last,last_fwd) = chain[chain.len()-1];
+        let begin = track_end(tracks, first, if first_fwd { AB::A } else { AB::B });
+        let end = track_end(tracks, last, if last_fwd { AB::B } else { AB::A });
+        end_conn_ids.insert(begin, format!("tb{}c", chain_idx+1));
+        end_conn_ids.insert(end, format!("te{}c", chain_idx+1));
+        chain_ends.push((begin,end));
+    }
+
+    let mut ports :HashMap<(Pt,Port),(usize,AB)> = HashMap::new();
+    for (i,(_,a,b)) in tracks.iter().enumerate() {
+        ports.insert(*a, (i,AB::A));
+        ports.insert(*b, (i,AB::B));
+    }
+
+    // Switches and crossings, sorted by location for stable ids.
+    let mut locations = topology.locations.iter().collect::<Vec<_>>();
+    locations.sort_by_key(|(pt,_)| (pt.x,pt.y));
+    let mut switches :Vec<Vec<railml::Switch>> = chains.iter().map(|_| Vec::new()).collect();
+    let mut switch_conn_ids :HashMap<(Pt,Port),String> = HashMap::new();
+    let (mut n_sw, mut n_cr) = (0,0);
+    for (pt,(nd,_)) in locations.iter() {
+        match nd {
+            NDType::Sw(side,speed) => {
+                let trunk = ports.get(&(**pt,Port::Trunk));
+                let deviating = ports.get(&(**pt,side.as_port()));
+                if let (Some((trunk_idx,trunk_ab)),Some(_)) = (trunk,deviating) {
+                    n_sw += 1;
+                    let id = format!("sw{}", n_sw);
+                    let conn_id = format!("{}c", id);
+                    switch_conn_ids.insert((**pt,side.as_port()), conn_id.clone());
+                    let (chain_idx, pos) = end_pos(*trunk_idx, *trunk_ab);
+                    // Switch is facing in the chain direction if the chain
+                    // arrives at the switch through the trunk track.
+                    let outgoing = (*trunk_ab == AB::B) == track_pos[trunk_idx].2;
+                    switches[chain_idx].push(railml::Switch::Switch {
+                        id: id.clone(),
+                        pos: railml::Position { offset: pos, mileage: dgraph.location_mileage(pt) },
+                        name: model.node_names.get(*pt).cloned(),
+                        description: None,
+                        length: None,
+                        connections: vec![railml::SwitchConnection {
+                            id: conn_id,
+                            r#ref: end_conn_ids.get(&(**pt,side.as_port())).cloned().unwrap_or_default(),
+                            orientation: if outgoing { railml::ConnectionOrientation::Outgoing }
+                                         else { railml::ConnectionOrientation::Incoming },
+                            course: Some(match side {
+                                Side::Left => railml::SwitchConnectionCourse::Left,
+                                Side::Right => railml::SwitchConnectionCourse::Right,
+                            }),
+                            radius: None,
+                            max_speed: speed.map(|v| v*3.6), // railML speeds are in km/h
+                            passable: None,
+                        }],
+                        track_continue_course: Some(railml::SwitchConnectionCourse::Straight),
+                        track_continue_radius: None,
+                    });
+                }
+            },
+            NDType::Crossing(type_,_) => {
+                // railmlio has no representation of slips, so slip crossings
+                // are exported as plain crossings.
+                if !matches!(type_, CrossingType::Crossover) {
+                    warnings.push(format!("Slip crossing {} exported as a plain crossing",
+                                          model.ref_label(&Ref::Node(**pt))));
+                }
+                let through = ports.get(&(**pt,Port::Cross(AB::A,0)));
+                if let Some((through_idx,through_ab)) = through {
+                    n_cr += 1;
+                    let id = format!("cr{}", n_cr);
+                    let (chain_idx, pos) = end_pos(*through_idx, *through_ab);
+                    // Does the chain arrive at the crossing from the A side?
+                    let from_a = (*through_ab == AB::B) == track_pos[through_idx].2;
+                    let mut connections = Vec::new();
+                    for (i,ab) in [AB::A, AB::B].iter().enumerate() {
+                        let port = Port::Cross(*ab,1);
+                        if let Some(end_conn) = end_conn_ids.get(&(**pt,port)) {
+                            let conn_id = format!("{}c{}", id, i+1);
+                            switch_conn_ids.insert((**pt,port), conn_id.clone());
+                            connections.push(railml::SwitchConnection {
+                                id: conn_id,
+                                r#ref: end_conn.clone(),
+                                orientation: if (*ab == AB::B) == from_a { railml::ConnectionOrientation::Outgoing }
+                                             else { railml::ConnectionOrientation::Incoming },
+                                course: None,
+                                radius: None,
+                                max_speed: None,
+                                passable: None,
+                            });
+                        }
+                    }
+                    switches[chain_idx].push(railml::Switch::Crossing {
+                        id: id,
+                        pos: railml::Position { offset: pos, mileage: dgraph.location_mileage(pt) },
+                        track_continue_course: None,
+                        track_continue_radius: None,
+                        normal_position: None,
+                        length: None,
+                        connections: connections,
+                    });
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Trackside objects
+    let mut objects :Vec<railml::Objects> = chains.iter().map(|_| railml::Objects::empty()).collect();
+    let mut object_list = Vec::new();
+    for (track_idx, objs) in topology.trackobjects.iter().enumerate() {
+        for (pos, pta, func, dir) in objs.iter() {
+            let (chain_idx, offset, fwd) = track_pos[&track_idx];
+            let chain_pos = if fwd { offset + pos } else { offset + tracks[track_idx].0 - pos };
+            let up = matches!(dir, Some(AB::A)) == fwd;
+            object_list.push((chain_idx, chain_pos, *pta, func.clone(), up));
+        }
+    }
+    object_list.sort_by_key(|(c,pos,pta,_,_)| (*c, OrderedFloat(*pos), pta.x, pta.y));
+    let (mut n_sig, mut n_det) = (0,0);
+    for (chain_idx, pos, pta, func, up) in object_list {
+        let pos = railml::Position { offset: pos, mileage: dgraph.object_mileage(&pta) };
+        match func {
+            Function::MainSignal { sight, .. } => {
+                n_sig += 1;
+                objects[chain_idx].signals.push(railml::Signal {
+                    id: format!("sig{}", n_sig),
+                    pos: pos,
+                    name: model.objects.get(&pta).and_then(|o| o.name.clone()),
+                    dir: if up { railml::TrackDirection::Up } else { railml::TrackDirection::Down },
+                    sight: sight,
+                    r#type: railml::SignalType::Main,
+                });
+            },
+            Function::ShuntingSignal { sight } => {
+                n_sig += 1;
+                objects[chain_idx].signals.push(railml::Signal {
+                    id: format!("sig{}", n_sig),
+                    pos: pos,
+                    name: model.objects.get(&pta).and_then(|o| o.name.clone()),
+                    dir: if up { railml::TrackDirection::Up } else { railml::TrackDirection::Down },
+                    sight: sight,
+                    r#type: railml::SignalType::Shunting,
+                });
+            },
+            Function::Detector => {
+                n_det += 1;
+                objects[chain_idx].train_detectors.push(railml::TrainDetector {
+                    id: format!("det{}", n_det),
+                    pos: pos,
+                    name: model.objects.get(&pta).and_then(|o| o.name.clone()),
+                });
+            },
+            Function::SpeedRestriction { .. } => {
+                // Speed changes are not yet supported by railmlio.
+                warnings.push(format!("Speed restriction {} not exported",
+                                      model.ref_label(&Ref::Object(pta))));
+            },
+            Function::MileageBreak { .. } => {
+                // Mileage changes are not yet supported by railmlio.
+                warnings.push(format!("Mileage break {} not exported",
+                                      model.ref_label(&Ref::Object(pta))));
+            },
+        }
+    }
+
+    let mut end_connection = |(pt,port) :(Pt,Port)| -> railml::TrackEndConnection {
+        let own_id = end_conn_ids[&(pt,port)].clone();
+        match topology.locations.get(&pt) {
+            Some((NDType::BufferStop,_)) => railml::TrackEndConnection::BufferStop,
+            Some((NDType::OpenEnd,_)) => railml::TrackEndConnection::OpenEnd,
+            Some((nd,_)) => {
+                if let Some(sw_conn) = switch_conn_ids.get(&(pt,port)) {
+                    railml::TrackEndConnection::Connection(own_id, sw_conn.clone())
+                } else if let Some(other) = continuation(nd, port).and_then(|p| end_conn_ids.get(&(pt,p))) {
+                    // Chain ends connected to each other (a loop).
+                    railml::TrackEndConnection::Connection(own_id, other.clone())
+                } else {
+                    warnings.push(format!("Could not connect track end at {}",
+                                          model.ref_label(&Ref::Node(pt))));
+                    railml::TrackEndConnection::OpenEnd
+                }
+            },
+            None => railml::TrackEndConnection::OpenEnd,
+        }
+    };
+
+    let mut railml_tracks = Vec::new();
+    for (((chain_idx,(begin,end)),switches),objects) in
+        chain_ends.into_iter().enumerate().zip(switches.into_iter()).zip(objects.into_iter()) {
+        let mut switches = switches;
+        switches.sort_by_key(|s| match s {
+            railml::Switch::Switch { pos, .. } | railml::Switch::Crossing { pos, .. } =>
+                OrderedFloat(pos.offset) });
+        railml_tracks.push(railml::Track {
+            id: format!("track{}", chain_idx+1),
+            code: None,
+            name: None,
+            description: None,
+            begin: railml::Node {
+                id: format!("tb{}", chain_idx+1),
+                pos: railml::Position { offset: 0.0, mileage: dgraph.location_mileage(&begin.0) },
+                connection: end_connection(begin),
+            },
+            end: railml::Node {
+                id: format!("te{}", chain_idx+1),
+                pos: railml::Position { offset: chain_lengths[chain_idx], mileage: dgraph.location_mileage(&end.0) },
+                connection: end_connection(end),
+            },
+            switches: switches,
+            objects: objects,
+        });
+    }
+
+    (railml::RailML {
+        infrastructure: Some(railml::Infrastructure { tracks: railml_tracks }),
+    }, warnings)
+}
+
+/// Write the railML document to a file, returning the conversion warnings.
+pub fn export_railml(filename :&str, analysis :&Analysis) -> Result<Vec<String>, String> {
+    let topology = &analysis.data().topology.as_ref().ok_or(format!("Topology not available"))?.1;
+    let dgraph = &analysis.data().dgraph.as_ref().ok_or(format!("Dgraph not available"))?.1;
+    let (doc, warnings) = convert_railml(analysis.model(), topology, dgraph);
+    std::fs::write(filename, railmlio::xml::write_railml(&doc)).map_err(|e| format!("{}", e))?;
+    for w in warnings.iter() { warn!("railML export: {}", w); }
+    info!("Exported railML to file {:?}", filename);
+    Ok(warnings)
+}
+
+pub fn export_railml_interactive(analysis :&Analysis) -> Result<Option<(String,Vec<String>)>, String> {
+    if let Some(filename) = tinyfiledialogs::save_file_dialog_with_filter("Export to railML file", "",
+                                   &["*.railml", "*.xml"], "railML 2.x") {
+        export_railml(&filename, analysis).map(|w| Some((filename,w)))
+    } else {
+        info!("User cancelled railML export");
+        Ok(None)
+    }
+}
+
+/// Column headers of the interlocking route table, see `route_table`.
+pub const ROUTE_TABLE_COLUMNS :&[&str] = &["Route", "Class", "Entry", "Exit", "Alternative", 
+    "Switch positions", "TVD sections", "Release groups", "Length (m)", "Flank protection", 
+    "Conflicting routes"];
+
+/// One row for each route in the interlocking, with the columns in `ROUTE_TABLE_COLUMNS`.
+/// Routes conflict when any of their partial routes conflict in the planner, 
+/// see `plan::convert_inf`.
+pub fn route_table(model :&Model, dgraph :&DGraph, il :&Interlocking) -> Vec<Vec<String>> {
+    use rolling::input::staticinfrastructure::{SwitchPosition, StaticObject, Edges};
+    let inf = &dgraph.rolling_inf;
+    let switch_name = |sw :&usize| dgraph.switch_ids.get_by_left(sw)
+        .map(|pt| model.ref_label(&Ref::Node(*pt))).unwrap_or(format!("sw{}", sw));
+    let signal_name = |s :&usize| dgraph.signal_ref(*s)
+        .map(|r| model.ref_label(&r)).unwrap_or(format!("sig{}", s));
+    let section_name = |s :&usize| {
+        let mut limits = dgraph.section_limits(*s).iter().map(|r| model.ref_label(r)).collect::<Vec<_>>();
+        limits.sort();
+        if limits.is_empty() { format!("T{}", s) } else { limits.join("-") }
+    };
+    let position = |p :&SwitchPosition| match p { SwitchPosition::Left => "left", SwitchPosition::Right => "right" };
+
+    let mut conflicting :Vec<BTreeSet<usize>> = vec![BTreeSet::new(); il.routes.len()];
+    let plan_inf = plan::convert_inf(&il.routes.iter().enumerate().collect());
+    for ((route,_),partial_route) in plan_inf.partial_routes.iter() {
+        for ((other,_),_) in partial_route.conflicts.iter().flatten() {
+            if other != route { conflicting[*route].insert(*other); }
+        }
+    }
+
+    il.routes.iter().enumerate().map(|(i,r)| {
+        let route = &r.route;
+        let length :f64 = r.path.iter().map(|(a,b)| match inf.nodes[*a].edges {
+            Edges::Single(_,d) => d,
+            Edges::Switchable(sw) => match &inf.objects[sw] {
+                StaticObject::Switch { left_link, right_link, .. } => 
+                    if left_link.0 == *b { left_link.1 } else { right_link.1 },
+                _ => 0.0,
+            },
+            _ => 0.0,
+        }).sum();
+        // Flank protection switches are listed in their own column.
+        let switches = route.resources.switch_positions.iter()
+            .filter(|(sw,p)| !r.flank.iter().any(|(f,_)| *f == FlankProtection::Switch(*sw,*p)))
+            .map(|(sw,p)| format!("{} {}", switch_name(sw), position(p))).collect::<Vec<_>>();
+        let sections = route.resources.sections.iter().map(section_name).collect::<Vec<_>>();
+        let releases = route.resources.releases.iter().map(|rel| {
+            let resources = rel.resources.iter().filter(|x| **x != rel.trigger)
+                .map(|x| match inf.objects.get(*x) {
+                    Some(StaticObject::Switch { .. }) => switch_name(x),
+                    Some(StaticObject::Signal { .. }) => signal_name(x),
+                    _ => section_name(x),
+                })
+                .collect::<Vec<_>>();
+            if resources.is_empty() { section_name(&rel.trigger) } 
+            else { format!("{} ({})", section_name(&rel.trigger), resources.join(" ")) }
+        }).collect::<Vec<_>>();
+        let flank = r.flank.iter().map(|(f,_)| match f {
+            FlankProtection::Switch(sw,p) => format!("{} {}", switch_name(sw), position(p)),
+            FlankProtection::Signal(s) => format!("{} stop", signal_name(s)),
+        }).collect::<Vec<_>>();
+        let conflicts = conflicting[i].iter()
+            .map(|j| model.route_name(&il.routes[*j].id)).collect::<Vec<_>>();
+
+        vec![
+            model.route_name(&r.id),
+            format!("{:?}", r.class),
+            model.ref_label(&r.id.from),
+            model.ref_label(&r.id.to),
+            format!("{}", r.id.alternative),
+            switches.join("; "),
+            sections.join("; "),
+            releases.join("; "),
+            format!("{:.1}", length),
+            flank.join("; "),
+            conflicts.join("; "),
+        ]
+    }).collect()
+}
+
+fn csv_field(s :&str) -> String {
+    if s.contains(|c :char| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn export_route_table_csv(filename :&str, analysis :&Analysis) -> Result<(), String> {
+    let dgraph = &analysis.data().dgraph.as_ref().ok_or(format!("Dgraph not available"))?.1;
+    let il = &analysis.data().interlocking.as_ref().ok_or(format!("Interlocking not available"))?.1;
+    let mut csv = String::new();
+    let header = ROUTE_TABLE_COLUMNS.iter().map(|c| csv_field(c)).collect::<Vec<_>>();
+    csv.push_str(&header.join(","));
+    csv.push_str("\n");
+    for row in route_table(analysis.model(), dgraph, il) {
+        csv.push_str(&row.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(","));
+        csv.push_str("\n");
+    }
+    std::fs::write(filename, csv).map_err(|e| format!("{}", e))?;
+    info!("Exported route table to file {:?}", filename);
+    Ok(())
+}
+
+pub fn export_route_table_csv_interactive(analysis :&Analysis) -> Result<Option<String>, String> {
+    if let Some(filename) = tinyfiledialogs::save_file_dialog_with_filter("Export route table", "",
+                                   &["*.csv"], "CSV file") {
+        export_route_table_csv(&filename, analysis).map(|_| Some(filename))
+    } else {
+        info!("User cancelled route table export");
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::model::test_models::*;
+    use nalgebra_glm as glm;
+
+    #[test]
+    fn export_switch() {
+        let mut model = switch_model(10, 4);
+        add_object(&mut model, glm::vec2(2.0,-0.3), main_signal());
+
+        let (topology, dgraph) = dgraph(&model);
+        let (doc, warnings) = convert_railml(&model, &topology, &dgraph);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        let tracks = doc.infrastructure.unwrap().tracks;
+        assert_eq!(tracks.len(), 2);
+        let switches = tracks.iter().flat_map(|t| t.switches.iter()).collect::<Vec<_>>();
+        assert_eq!(switches.len(), 1);
+        assert_eq!(tracks.iter().map(|t| t.objects.signals.len()).sum::<usize>(), 1);
+
+        // The diverging track is connected to the switch, and the switch
+        // refers back to the diverging track's end.
+        let (sw_conn, sw_ref) = match switches[0] {
+            railml::Switch::Switch { connections, .. } =>
+                (connections[0].id.clone(), connections[0].r#ref.clone()),
+            _ => panic!("expected a switch"),
+        };
+        let track_connections = tracks.iter()
+            .flat_map(|t| vec![&t.begin.connection, &t.end.connection])
+            .filter_map(|c| match c {
+                railml::TrackEndConnection::Connection(id,r) => Some((id.clone(),r.clone())),
+                _ => None,
+            }).collect::<Vec<_>>();
+        assert_eq!(track_connections, vec![(sw_ref, sw_conn)]);
+    }
+
+    #[test]
+    fn route_table_section_names() {
+        let mut model = straight_line(10);
+        for (x,name) in &[(2.5, "D1"), (7.5, "D2")] {
+            add_named_object(&mut model, glm::vec2(*x,0.0), Function::Detector, name);
+        }
+
+        let (dgraph, il) = interlocking(&model);
+        let rows = route_table(&model, &dgraph, &il);
+        let sections = ROUTE_TABLE_COLUMNS.iter().position(|c| *c == "TVD sections").unwrap();
+        assert!(!rows.is_empty());
+        for row in rows.iter() { assert_eq!(row[sections], "D1-D2"); }
+    }
+}
@@ -1,22 +1,196 @@
-use crate::document::model::Model;
+use crate::document::model::*;
+use serde::{Serialize, Deserialize};
 use std::fs::File;
 use log::*;
 
+/// Model files ending with this extension are stored in the
+/// human-readable RON format, all other files are stored as CBOR.
+pub const TEXT_EXTENSION :&str = ".ron";
+
+pub fn is_text_format(filename :&str) -> bool {
+    filename.to_lowercase().ends_with(TEXT_EXTENSION)
+}
+
+/// Text files are stored as RON, with the same structure as the CBOR files, so that 
+/// both formats are upgraded by the same `MIGRATIONS`. Integers are written as
+/// 64-bit integers (RON does not support the 128-bit integers of `serde_cbor::Value`),
+/// and so are floats without a fractional part, which are read back as floats.
+struct TextValue<'a>(&'a serde_cbor::Value);
+
+impl<'a> Serialize for TextValue<'a> {
+    fn serialize<S :serde::Serializer>(&self, s :S) -> Result<S::Ok, S::Error> {
+        use serde_cbor::Value;
+        use serde::ser::Error;
+        match self.0 {
+            Value::Integer(i) if *i >= std::i64::MIN as i128 && *i <= std::i64::MAX as i128 => 
+                s.serialize_i64(*i as i64),
+            Value::Float(x) if x.fract() == 0.0 && x.abs() < 1e18 => s.serialize_i64(*x as i64),
+            Value::Float(x) if x.is_finite() => s.serialize_f64(*x),
+            Value::Text(x) => s.serialize_str(x),
+            Value::Bool(x) => s.serialize_bool(*x),
+            Value::Null => s.serialize_unit(),
+            Value::Array(xs) => s.collect_seq(xs.iter().map(TextValue)),
+            Value::Map(xs) => s.collect_map(xs.iter().map(|(k,v)| (TextValue(k), TextValue(v)))),
+            Value::Tag(_,x) => TextValue(x).serialize(s),
+            x => Err(S::Error::custom(format!("Value {:?} can not be stored in a text file", x))),
+        }
+    }
+}
+
+/// Fields of the model which are hash sets, and are sorted in text files so that 
+/// saving the same model twice gives the same file, and small edits give small diffs.
+/// The hash maps are already sorted by key in `serde_cbor::Value::Map`.
+const TEXT_SORTED_FIELDS :&[&str] = &["linesegs", "mileage_origins"];
+
+fn sort_sets(model :&mut serde_cbor::Value) {
+    use serde_cbor::Value;
+    if let Value::Map(map) = model {
+        for field in TEXT_SORTED_FIELDS {
+            if let Some(Value::Array(xs)) = map.get_mut(&Value::Text(field.to_string())) { xs.sort(); }
+        }
+    }
+}
+
+/// Version of the model file format written by this program.
+/// Increase this number and add a step to `MIGRATIONS` when changing 
+/// the serialized layout of the model (`Object`, `Function`, `NDType`, 
+/// `PlanSpec`, `Dispatch`, etc.).
+pub const FORMAT_VERSION :usize = 1;
+
+/// Migration steps on the untyped CBOR value, `MIGRATIONS[i]` upgrades
+/// a model from format version `i` to version `i+1`.
+const MIGRATIONS :&[fn(serde_cbor::Value) -> Result<serde_cbor::Value, String>] = &[
+    migrate_v0_v1,
+];
+
+/// Version 0 is the unversioned file format. Version 1 adds:
+///  * the deviating speed restriction to switches and crossings, `Sw(side)` becomes 
+///    `Sw(side, None)`, and `Crossing(type)` becomes `Crossing(type, None)`,
+///  * `Model::lineseg_lengths`, `Model::lineseg_gradients`, `Model::node_mileage`, 
+///    `Model::mileage_origins` and `Model::node_names`, which are empty in older files,
+///  * `Model::settings`. Older files get the default settings, but without overlaps, 
+///    so that their routes and plans are unchanged.
+///
+/// The sight distance, fixed mileage and name of objects are optional, and objects 
+/// without a name are given one when the model is loaded.
+fn migrate_v0_v1(model :serde_cbor::Value) -> Result<serde_cbor::Value, String> {
+    use serde_cbor::Value;
+    let mut map = match model { Value::Map(m) => m, _ => { return Err(format!("Model is not a map")); } };
+    let key = Value::Text("node_data".to_string());
+    let node_data = match map.remove(&key) {
+        Some(Value::Map(nodes)) => nodes.into_iter().map(|(pt,nd)| (pt, match nd {
+            Value::Map(variant) => Value::Map(variant.into_iter().map(|(name,x)| {
+                let x = match &name {
+                    Value::Text(n) if n == "Sw" || n == "Crossing" => Value::Array(vec![x, Value::Null]),
+                    _ => x,
+                };
+                (name,x)
+            }).collect()),
+            nd => nd,
+        })).collect(),
+        _ => { return Err(format!("Node data missing")); },
+    };
+    map.insert(key, Value::Map(node_data));
+
+    for field in &["lineseg_lengths", "lineseg_gradients", "node_mileage", "node_names"] {
+        map.insert(Value::Text(field.to_string()), Value::Map(Default::default()));
+    }
+    map.insert(Value::Text("mileage_origins".to_string()), Value::Array(Vec::new()));
+
+    let settings = ModelSettings { routes: RouteSettings::without_overlaps(), ..Default::default() };
+    let settings = serde_cbor::value::to_value(settings).map_err(|e| format!("{}", e))?;
+    map.insert(Value::Text("settings".to_string()), settings);
+    Ok(Value::Map(map))
+}
+
+#[derive(Serialize,Deserialize)]
+struct VersionedFile<T> {
+    junction_format_version :usize,
+    model :T,
+}
+
+fn invalid_data(msg :String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn check_version(version :usize) -> Result<(), std::io::Error> {
+    if version > FORMAT_VERSION {
+        return Err(invalid_data(format!("File format version {} is newer than the supported \
+                                          version {}. Upgrade Junction to open this file.",
+                                          version, FORMAT_VERSION)));
+    }
+    Ok(())
+}
+
+/// Split the file contents into format version and model. Files without 
+/// a version header have version 0.
+fn split_version(value :serde_cbor::Value) -> Result<(usize, serde_cbor::Value), std::io::Error> {
+    use serde_cbor::Value;
+    let key = Value::Text("junction_format_version".to_string());
+    match value {
+        Value::Map(mut map) if map.contains_key(&key) => {
+            let version = match map.remove(&key) {
+                Some(Value::Integer(v)) if v >= 0 => v as usize,
+                v => { return Err(invalid_data(format!("Unknown file format version {:?}", v))); }
+            };
+            let model = map.remove(&Value::Text("model".to_string()))
+                .ok_or(invalid_data(format!("Model missing from file")))?;
+            Ok((version, model))
+        },
+        v => Ok((0, v)),
+    }
+}
+
+fn migrate(mut version :usize, mut model :serde_cbor::Value) -> Result<serde_cbor::Value, std::io::Error> {
+    check_version(version)?;
+    while version < FORMAT_VERSION {
+        info!("Upgrading model from file format version {} to {}", version, version+1);
+        model = MIGRATIONS[version](model)
+            .map_err(|e| invalid_data(format!("Could not upgrade model from file format version {}: {}", version, e)))?;
+        version += 1;
+    }
+    Ok(model)
+}
+
 pub fn load(filename :&str) -> Result<Model, std::io::Error> {
-    let m = serde_cbor::from_reader(File::open(&filename)?)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let value = if is_text_format(filename) {
+        let text = std::fs::read_to_string(filename)?;
+        ron::de::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    } else {
+        serde_cbor::from_reader(File::open(&filename)?)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    };
+    let (version, value) = split_version(value)?;
+    let value = migrate(version, value)?;
+    let m = serde_cbor::value::from_value(value)
+        .map_err(|e| invalid_data(format!("Could not read model (file format version {}): {}", version, e)))?;
     Ok(m)
 }
 
 pub fn save(filename :&str, m :Model) -> Result<(),std::io::Error> {
     info!("Will save file to file name {:?}", filename);
-    serde_cbor::to_writer(&File::create(filename)?, &m)
+    if is_text_format(filename) {
+        use serde_cbor::Value;
+        let mut model = serde_cbor::value::to_value(m)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        sort_sets(&mut model);
+        let mut f = std::collections::BTreeMap::new();
+        f.insert(Value::Text("junction_format_version".to_string()), Value::Integer(FORMAT_VERSION as i128));
+        f.insert(Value::Text("model".to_string()), model);
+        let text = ron::ser::to_string_pretty(&TextValue(&Value::Map(f)), Default::default())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        std::fs::write(filename, text)?;
+        return Ok(());
+    }
+    let f = VersionedFile { junction_format_version: FORMAT_VERSION, model: m };
+    serde_cbor::to_writer(&File::create(filename)?, &f)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
     Ok(())
 }
 
 pub fn save_interactive(m :Model) -> Result<Option<String>,std::io::Error> {
-    if let Some(filename) = tinyfiledialogs::save_file_dialog("Save model to file", "") {
+    if let Some(filename) = tinyfiledialogs::save_file_dialog_with_filter("Save model to file", "",
+                                   &["*.junc", "*.ron"], "Junction model (binary or text)") {
         save(&filename, m).map(|_| Some(filename))
     } else {
         info!("User cancelled save");
@@ -25,7 +199,8 @@ pub fn save_interactive(m :Model) -> Result<Option<String>,std::io::Error> {
 }
 
 pub fn load_interactive() -> Result<Option<(Model,String)>, std::io::Error> {
-    if let Some(filename) = tinyfiledialogs::open_file_dialog("Open model from file", "", None) {
+    if let Some(filename) = tinyfiledialogs::open_file_dialog("Open model from file", "",
+                                   Some((&["*.junc", "*.ron"], "Junction model (binary or text)"))) {
         info!("Loading file from {:?}", filename);
         let m = load(&filename)?;
         Ok(Some((m,filename)))
@@ -77,3 +252,95 @@ impl FileInfo {
                                    self.filename.as_ref().map(|x| x.as_str()).unwrap_or("Untitled"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_cbor::Value;
+    use std::collections::BTreeMap;
+    use nalgebra_glm as glm;
+
+    fn temp_file(name :&str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    fn text(s :&str) -> Value { Value::Text(s.to_string()) }
+
+    fn to_value(x :impl Serialize) -> Value { serde_cbor::value::to_value(x).unwrap() }
+
+    /// Model as written before the file format version was added, with a 
+    /// track from a switch which has no speed restriction.
+    fn unversioned_model() -> Value {
+        let (a,b) :(Pt,Pt) = (glm::vec2(0,0), glm::vec2(1,0));
+        let mut node_data = BTreeMap::new();
+        node_data.insert(to_value(a), Value::Map(vec![(text("Sw"), text("Left"))].into_iter().collect()));
+        let mut model = BTreeMap::new();
+        model.insert(text("linesegs"), Value::Array(vec![to_value((a,b))]));
+        model.insert(text("objects"), Value::Map(BTreeMap::new()));
+        model.insert(text("node_data"), Value::Map(node_data));
+        model.insert(text("vehicles"), to_value(ImShortGenList::<Vehicle>::new()));
+        model.insert(text("dispatches"), to_value(ImShortGenList::<Dispatch>::new()));
+        model.insert(text("plans"), to_value(ImShortGenList::<PlanSpec>::new()));
+        Value::Map(model)
+    }
+
+    fn versioned(version :usize, model :Value) -> Value {
+        let mut f = BTreeMap::new();
+        f.insert(text("junction_format_version"), Value::Integer(version as i128));
+        f.insert(text("model"), model);
+        Value::Map(f)
+    }
+
+    fn assert_upgraded(m :&Model) {
+        assert_eq!(m.linesegs.len(), 1);
+        assert_eq!(m.node_data.get(&glm::vec2(0,0)), Some(&NDType::Sw(Side::Left, None)));
+        assert_eq!(m.settings.sight_distance, DEFAULT_SIGHT_DISTANCE);
+        assert!(m.lineseg_lengths.is_empty());
+        // Routes in older models have no overlaps.
+        assert_eq!(m.settings.routes.overlap_length, 0.0);
+    }
+
+    #[test]
+    fn migrate_unversioned_binary_file() {
+        let filename = temp_file("junction_test_unversioned.junc");
+        serde_cbor::to_writer(&File::create(&filename).unwrap(), &unversioned_model()).unwrap();
+        assert_upgraded(&load(&filename).unwrap());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let file = versioned(FORMAT_VERSION + 1, unversioned_model());
+
+        let filename = temp_file("junction_test_newer.junc");
+        serde_cbor::to_writer(&File::create(&filename).unwrap(), &file).unwrap();
+        let err = load(&filename).err().expect("newer binary file was loaded");
+        assert!(format!("{}", err).contains("newer"));
+
+        let filename = temp_file("junction_test_newer.ron");
+        std::fs::write(&filename, ron::ser::to_string_pretty(&TextValue(&file), Default::default()).unwrap()).unwrap();
+        let err = load(&filename).err().expect("newer text file was loaded");
+        assert!(format!("{}", err).contains("newer"));
+    }
+
+    #[test]
+    fn text_file_roundtrip() {
+        let mut model = Model::empty();
+        for x in 0..5 { model.linesegs.insert((glm::vec2(x,0), glm::vec2(x+1,0))); }
+        model.lineseg_lengths.insert((glm::vec2(0,0), glm::vec2(1,0)), 123.5);
+        model.node_data.insert(glm::vec2(0,0), NDType::BufferStop);
+
+        let filename = temp_file("junction_test_roundtrip.ron");
+        save(&filename, model.clone()).unwrap();
+        let text = std::fs::read_to_string(&filename).unwrap();
+        let loaded = load(&filename).unwrap();
+        assert_eq!(loaded.linesegs, model.linesegs);
+        assert_eq!(loaded.lineseg_lengths, model.lineseg_lengths);
+        assert_eq!(loaded.node_data, model.node_data);
+        assert_eq!(loaded.settings.routes.overlap_length, model.settings.routes.overlap_length);
+        assert!(loaded.settings.routes.overlap_length > 0.0);
+
+        // Saving the same model again gives the same file.
+        save(&filename, loaded).unwrap();
+        assert_eq!(std::fs::read_to_string(&filename).unwrap(), text);
+    }
+}
@@ -10,6 +10,7 @@ use crate::document::dispatch::*;
 use crate::document::model::*;
 use crate::document::analysis::*;
 use crate::document::*;
+use crate::document::dgraph::DGraph;
 use crate::gui::diagram::DiagramViewAction;
 use crate::gui::infrastructure::draw::highlight_node;
 use crate::document::infview::InfView;
@@ -208,13 +209,15 @@ pub fn command_icons(config :&Config,
                     (_,None) => {
                         widgets::show_text(&format!("Invalid route start/end points."));
                     }
-                    (Command::Route(_),_) => {
-                        widgets::show_text(&format!("Route request t={:.1}", cmd_t));
+                    (Command::Route(r),_) => {
+                        widgets::show_text(&format!("Route request {} t={:.1}", 
+                                                    analysis.model().route_name(r), cmd_t));
                     },
-                    (Command::Train(v,_),_) => {
+                    (Command::Train(v,r),_) => {
                         let v = analysis.model().vehicles.get(*v).map(|v| v.name.as_str())
                             .unwrap_or("Unknown vehicle");
-                        widgets::show_text(&format!("{} entering t={:.1}", v, cmd_t));
+                        widgets::show_text(&format!("{} entering {} t={:.1}", v, 
+                                                    analysis.model().route_name(r), cmd_t));
                     },
                 }
                 igEndTooltip();
@@ -253,6 +256,81 @@ pub fn time_slider(config :&Config, draw :&Draw, viewport :&DiagramViewport, t :
 	}
 }
 
+/// Chainage labels along the top of the diagram, at round intervals,
+/// and a line at each chainage break.
+pub fn mileage_axis(config :&Config, dgraph :&DGraph, draw :&Draw, view :&DiagramViewport) {
+    let col = config.color_u32(RailUIColorName::GraphTimeSliderText);
+    let range = view.pos.1 - view.pos.0;
+    if !(range > 0.0) { return; }
+    let step = 10.0f64.powf((range / 5.0).log10().floor());
+
+    let breaks = dgraph.mileage_breaks.iter().filter(|(pos,_,_)| view.pos.0 < *pos && *pos < view.pos.1);
+    let mut pieces = vec![view.pos.0];
+    pieces.extend(breaks.clone().map(|(pos,_,_)| *pos));
+    pieces.push(view.pos.1);
+
+    unsafe {
+        let text = |p :ImVec2, text :String| {
+            ImDrawList_AddText(draw.draw_list, p, col,
+                               text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _ );
+        };
+        for piece in pieces.windows(2) {
+            let (start, end) = (piece[0], piece[1]);
+            let chainage_start = dgraph.chainage(start);
+            let mut chainage = (chainage_start / step).ceil() * step;
+            while chainage < chainage_start + (end - start) {
+                let p = to_screen(draw, view, view.time.0, start + (chainage - chainage_start));
+                ImDrawList_AddLine(draw.draw_list, p, p + ImVec2 { x: 0.0, y: 6.0 }, col, 1.0);
+                text(p + ImVec2 { x: 2.0, y: 6.0 }, format!("km {:.3}", chainage / 1000.0));
+                chainage += step;
+            }
+        }
+        for (pos,before,after) in breaks {
+            let p = to_screen(draw, view, view.time.0, *pos);
+            ImDrawList_AddLine(draw.draw_list, p, p + ImVec2 { x: 0.0, y: draw.size.y }, col, 1.0);
+            text(p + ImVec2 { x: 2.0, y: 20.0 }, format!("km {:.3} = {:.3}", before / 1000.0, after / 1000.0));
+        }
+    }
+}
+
+/// Height in pixels of the gradient profile strip at the bottom of the diagram.
+const GRADIENT_PROFILE_HEIGHT :f32 = 50.0;
+
+/// Draw the gradient of the infrastructure under the diagram, as bars
+/// above (rising) or below (falling) a zero line, in the direction of increasing mileage.
+pub fn gradient_profile(config :&Config, dgraph :&DGraph, draw :&Draw, view :&DiagramViewport) {
+    let col = config.color_u32(RailUIColorName::GraphGradient);
+    let edges = dgraph.edge_gradients.iter().filter_map(|((a,b),g)| {
+        let (km_a, km_b) = (*dgraph.mileage.get(a)?, *dgraph.mileage.get(b)?);
+        if km_a < km_b { Some((km_a, km_b, *g)) } else { None }
+    }).collect::<Vec<_>>();
+    let max_gradient = edges.iter().map(|(_,_,g)| g.abs()).fold(0.0, f64::max);
+    if !(max_gradient > 0.0) { return; }
+
+    unsafe {
+        let zero_y = draw.pos.y + draw.size.y - 0.5*GRADIENT_PROFILE_HEIGHT;
+        let scale = 0.45*GRADIENT_PROFILE_HEIGHT / max_gradient as f32;
+        let x_at = |km :f64| to_screen(draw, view, view.time.0, km).x;
+        ImDrawList_AddLine(draw.draw_list, 
+                           ImVec2 { x: draw.pos.x, y: zero_y },
+                           ImVec2 { x: draw.pos.x + draw.size.x, y: zero_y }, col, 1.0);
+
+        for (km_a, km_b, g) in edges {
+            let (x_a, x_b) = (x_at(km_a), x_at(km_b));
+            let y = zero_y - scale*(g as f32);
+            ImDrawList_AddRect(draw.draw_list,
+                               ImVec2 { x: x_a, y: y.min(zero_y) },
+                               ImVec2 { x: x_b, y: y.max(zero_y) }, col, 0.0, 0, 1.0);
+            if g != 0.0 && x_b - x_a > 40.0 {
+                let text = format!("{:.1}\u{2030}", g);
+                ImDrawList_AddText(draw.draw_list, 
+                                   ImVec2 { x: x_a + 2.0, y: zero_y - GRADIENT_PROFILE_HEIGHT*0.5 },
+                                   col, text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _ );
+            }
+        }
+    }
+}
+
 pub fn to_screen(draw :&Draw, v :&DiagramViewport, t: f64, x :f64) -> ImVec2 {
     ImVec2 {
         x: draw.pos.x + draw.size.x*(((x - v.pos.0)/(v.pos.1 - v.pos.0)) as f32),
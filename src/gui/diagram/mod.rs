@@ -57,6 +57,10 @@ pub fn diagram_view(config :&Config, inf_canvas :Option<&Draw>, inf_view :&InfVi
 
         // Need to get a DispatchOutput from analysis.
         draw::diagram(config, graph, &draw, dv.viewport.as_ref().unwrap());
+        if let Some((_,dgraph)) = analysis.data().dgraph.as_ref() {
+            draw::mileage_axis(config, dgraph, &draw, dv.viewport.as_ref().unwrap());
+            draw::gradient_profile(config, dgraph, &draw, dv.viewport.as_ref().unwrap());
+        }
         action = draw::command_icons(config, inf_canvas, inf_view, analysis, graph, &draw, dv).or(action);
         draw::time_slider(config, &draw, dv.viewport.as_ref().unwrap(), dv.time);
 
@@ -106,7 +106,7 @@ pub fn base(config :&Config, analysis :&Analysis, inf_view :&InfView,
                         ImDrawList_AddCircleFilled(draw.draw_list, 
                             draw.pos + inf_view.view.world_ptc_to_screen(pt), 4.0, col, 8);
                     },
-                    NDType::Sw(side) => {
+                    NDType::Sw(side,_) => {
                         let angle = if matches!(side, Side::Left) { 45.0 } else { -45.0 };
                         let p1 = draw.pos + inf_view.view.world_ptc_to_screen(pt);
                         let p2 = p1 + util::to_imvec(15.0*normalize(&tangent));
@@ -133,7 +133,7 @@ pub fn base(config :&Config, analysis :&Analysis, inf_view :&InfView,
                         ImDrawList_AddPolyline(draw.draw_list,pline.as_ptr(), pline.len() as i32, col, false, 2.5);
 
                     },
-                    NDType::Crossing(type_) => {
+                    NDType::Crossing(type_,_) => {
                         let left_conn  = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Left));
                         let right_conn = matches!(type_, CrossingType::DoubleSlip | CrossingType::SingleSlip(Side::Right));
 
@@ -167,6 +167,10 @@ pub fn base(config :&Config, analysis :&Analysis, inf_view :&InfView,
                         }
                     },
                 }
+
+                if let Some(name) = m.node_names.get(pt0) {
+                    name_label(draw, draw.pos + inf_view.view.world_ptc_to_screen(pt), col, name);
+                }
             }
         }
 
@@ -183,10 +187,21 @@ pub fn base(config :&Config, analysis :&Analysis, inf_view :&InfView,
             let empty = vec![];
             let state = object_states.get(pta).unwrap_or(&empty);
             obj.draw(draw.pos, &inf_view.view, draw.draw_list, col, state, config);
+            if let Some(name) = obj.name.as_ref() {
+                name_label(draw, draw.pos + inf_view.view.world_ptc_to_screen(obj.loc), col, name);
+            }
         }
     }
 }
 
+fn name_label(draw :&Draw, p :ImVec2, col :u32, name :&str) {
+    unsafe {
+        let p = p + ImVec2 { x: 6.0, y: 6.0 };
+        ImDrawList_AddText(draw.draw_list, p, col, 
+                           name.as_ptr() as _, name.as_ptr().offset(name.len() as isize) as _);
+    }
+}
+
 pub fn route(config :&Config, analysis :&Analysis, inf_view :&InfView, draw :&Draw, route_idx :usize) -> Option<()> { 
     unsafe {
         let il = &analysis.data().interlocking.as_ref()?.1;
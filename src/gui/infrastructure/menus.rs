@@ -15,81 +15,345 @@ use crate::document::interlocking::*;
 use crate::gui::widgets;
 use crate::gui::plan;
 use crate::config::RailUIColorName;
+use crate::util::order_ivec;
+use nalgebra_glm as glm;
 
 
 
 pub fn node_editor(analysis :&mut Analysis, pt :Pt) -> Option<()> {
-    let (nd,_tangent) = analysis.data().topology.as_ref()?.1.locations.get(&pt)?;
+    let (nd,_tangent) = *analysis.data().topology.as_ref()?.1.locations.get(&pt)?;
     unsafe {
     match nd {
         NDType::OpenEnd | NDType::BufferStop => {
             if let Some(new_value) =
-                widgets::radio_select(&[(const_cstr!("Open end").as_ptr(), *nd == NDType::OpenEnd, NDType::OpenEnd),
-                                   (const_cstr!("Buffer stop").as_ptr(), *nd == NDType::BufferStop, NDType::BufferStop)]) {
+                widgets::radio_select(&[(const_cstr!("Open end").as_ptr(), nd == NDType::OpenEnd, NDType::OpenEnd),
+                                   (const_cstr!("Buffer stop").as_ptr(), nd == NDType::BufferStop, NDType::BufferStop)]) {
 
                 analysis.edit_model(|m| {
                     m.node_data.insert(pt, *new_value);
                     None
                 });
             }
+
+            if nd == NDType::OpenEnd {
+                let mut is_origin = analysis.model().mileage_origins.contains(&pt);
+                igCheckbox(const_cstr!("Mileage starts here").as_ptr(), &mut is_origin);
+                if igIsItemEdited() {
+                    analysis.edit_model(|m| {
+                        if is_origin { m.mileage_origins.insert(pt); } 
+                        else { m.mileage_origins.remove(&pt); }
+                        None
+                    });
+                }
+            }
         },
-        NDType::Sw(side) => {
+        NDType::Sw(side,speed) => {
             widgets::show_text(&format!("Switch ({:?})", side));
 
-            // TODO 
-            let mut speed = 60.0;
-            igInputFloat(const_cstr!("Deviating speed restr.").as_ptr(), &mut speed, 1.0, 10.0,
-                         const_cstr!("%.1f").as_ptr(), 0 as _);
+            if let Some(new_speed) = deviating_speed_input(speed) {
+                analysis.edit_model(|m| {
+                    m.node_data.insert(pt, NDType::Sw(side, new_speed));
+                    Some(EditClass::NodeSpeed(pt))
+                });
+            }
         },
-        NDType::Crossing(type_) => {
+        NDType::Crossing(type_,speed) => {
             widgets::show_text(&format!("Crossing ({:?})", type_));
             if let Some(new_value) =
-                widgets::radio_select(&[(const_cstr!("Crossover").as_ptr(), *type_ == CrossingType::Crossover, CrossingType::Crossover),
-                                   (const_cstr!("Single slip (above)").as_ptr(), *type_ == CrossingType::SingleSlip(Side::Left), CrossingType::SingleSlip(Side::Left)),
-                                   (const_cstr!("Single slip (below)").as_ptr(), *type_ == CrossingType::SingleSlip(Side::Right), CrossingType::SingleSlip(Side::Right)),
-                                   (const_cstr!("Double slip").as_ptr(), *type_ == CrossingType::DoubleSlip, CrossingType::DoubleSlip)]) {
+                widgets::radio_select(&[(const_cstr!("Crossover").as_ptr(), type_ == CrossingType::Crossover, CrossingType::Crossover),
+                                   (const_cstr!("Single slip (above)").as_ptr(), type_ == CrossingType::SingleSlip(Side::Left), CrossingType::SingleSlip(Side::Left)),
+                                   (const_cstr!("Single slip (below)").as_ptr(), type_ == CrossingType::SingleSlip(Side::Right), CrossingType::SingleSlip(Side::Right)),
+                                   (const_cstr!("Double slip").as_ptr(), type_ == CrossingType::DoubleSlip, CrossingType::DoubleSlip)]) {
 
                 analysis.edit_model(|m| {
-                    m.node_data.insert(pt, NDType::Crossing(*new_value));
+                    m.node_data.insert(pt, NDType::Crossing(*new_value, speed));
                     None
                 });
             }
 
-            // TODO 
-            let mut speed = 60.0;
-            igInputFloat(const_cstr!("Deviating speed restr.").as_ptr(), &mut speed, 1.0, 10.0,
-                         const_cstr!("%.1f").as_ptr(), 0 as _);
+            if type_ != CrossingType::Crossover {
+                if let Some(new_speed) = deviating_speed_input(speed) {
+                    analysis.edit_model(|m| {
+                        m.node_data.insert(pt, NDType::Crossing(type_, new_speed));
+                        Some(EditClass::NodeSpeed(pt))
+                    });
+                }
+            }
         }
         _ => {},
     }
     }
+
+    if !matches!(nd, NDType::Cont | NDType::Err) {
+        if let Some(name) = name_input(analysis.model(), Ref::Node(pt)) {
+            analysis.edit_model(|m| {
+                if name.is_empty() { m.node_names.remove(&pt); } else { m.node_names.insert(pt, name); }
+                Some(EditClass::NodeName(pt))
+            });
+        }
+    }
+
+    let computed = analysis.data().dgraph.as_ref().and_then(|(_,dg)| dg.location_mileage(&pt));
+    if let Some(new_mileage) = mileage_input(analysis.model().node_mileage.get(&pt).cloned(), computed) {
+        analysis.edit_model(|m| {
+            match new_mileage {
+                Some(km) => { m.node_mileage.insert(pt, km); },
+                None => { m.node_mileage.remove(&pt); },
+            }
+            Some(EditClass::NodeMileage(pt))
+        });
+    }
     Some(())
 }
 
+/// Speed restriction input, shown in km/h and stored in m/s. 
+/// Zero means no restriction.
+fn deviating_speed_input(speed :Option<f64>) -> Option<Option<f64>> {
+    let mut kmh = speed.map(|v| (v * 3.6) as f32).unwrap_or(0.0);
+    unsafe {
+        igInputFloat(const_cstr!("Deviating speed restr. (km/h)").as_ptr(), &mut kmh, 1.0, 10.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() {
+            return Some(if kmh > 0.0 { Some(kmh as f64 / 3.6) } else { None });
+        }
+    }
+    None
+}
+
+
+thread_local! {
+    /// Name typed into a name input which was not applied because it is already
+    /// used, shown while the input is active.
+    static REJECTED_NAME :std::cell::RefCell<Option<(Ref,String)>> = std::cell::RefCell::new(None);
+}
+
+/// Name input for nodes and objects. Names are used to refer to nodes and objects
+/// when they are moved, so a name which is already used by another node or object 
+/// is not applied.
+fn name_input(model :&Model, thing :Ref) -> Option<String> {
+    let name = model.name_of(&thing).unwrap_or("").to_string();
+    let new_name = widgets::edit_text(const_cstr!("Name").as_ptr(), name);
+    let active = unsafe { igIsItemActive() };
+    REJECTED_NAME.with(|rejected| {
+        let mut rejected = rejected.borrow_mut();
+        let new_name = match new_name {
+            Some(n) if !n.is_empty() && model.find_named(&n).map(|r| r != thing).unwrap_or(false) => {
+                *rejected = Some((thing, n));
+                None
+            },
+            Some(n) => { *rejected = None; Some(n) },
+            None => None,
+        };
+        match &*rejected {
+            Some((r,_)) if *r == thing && !active => { *rejected = None; },
+            Some((r,n)) if *r == thing => { 
+                widgets::show_text(&format!("The name {:?} is already used.", n)); 
+            },
+            _ => {},
+        }
+        new_name
+    })
+}
+
+/// Sight distance input for signals, where `None` means the model's default sight distance.
+fn sight_input(model :&Model, pta :PtA, sight :Option<f64>) -> Option<(Option<f64>, Option<EditClass>)> {
+    let mut sight_dist = sight.unwrap_or(model.settings.sight_distance) as f32;
+    let mut new_sight = None;
+    unsafe {
+        igInputFloat(const_cstr!("Sight distance (m)").as_ptr(), &mut sight_dist, 1.0, 10.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() && sight_dist >= 0.0 {
+            new_sight = Some((Some(sight_dist as f64), Some(EditClass::ObjectSight(pta))));
+        }
+        if sight.is_some() {
+            if igButton(const_cstr!("Use default sight distance").as_ptr(), ImVec2 { x: 0.0, y: 0.0 }) {
+                new_sight = Some((None, None));
+            }
+        } else {
+            widgets::show_text("(default sight distance)");
+        }
+    }
+    new_sight
+}
+
+/// Fixed mileage input, shown in km and stored in m. When the mileage
+/// is not fixed, the calculated mileage is shown instead.
+fn mileage_input(fixed :Option<f64>, computed :Option<f64>) -> Option<Option<f64>> {
+    let mut is_fixed = fixed.is_some();
+    let mut km = (fixed.or(computed).unwrap_or(0.0) / 1000.0) as f32;
+    unsafe {
+        igCheckbox(const_cstr!("Fixed mileage").as_ptr(), &mut is_fixed);
+        if igIsItemEdited() {
+            return Some(if is_fixed { Some(km as f64 * 1000.0) } else { None });
+        }
+        if is_fixed {
+            igInputFloat(const_cstr!("Mileage (km)").as_ptr(), &mut km, 0.1, 1.0,
+                         const_cstr!("%.3f").as_ptr(), 0 as _);
+            if igIsItemEdited() {
+                return Some(Some(km as f64 * 1000.0));
+            }
+        } else if let Some(c) = computed {
+            widgets::show_text(&format!("Mileage: km {:.3}", c / 1000.0));
+        }
+    }
+    None
+}
+
+/// Edit the length and gradient of a single line segment, or of the whole track 
+/// between two nodes (divided evenly on its line segments). Gradients are 
+/// given as rising to the right (or upwards for vertical segments).
+pub fn lineseg_editor(analysis :&mut Analysis, a :Pt, b :Pt) -> Option<()> {
+    let topo = &analysis.data().topology.as_ref()?.1;
+    let mut track_pts :Vec<Pt> = topo.interval_lines.iter().find_map(|line| {
+        let pts :Vec<Pt> = line.iter().map(|(_,p)| glm::vec2(p.x.round() as i32, p.y.round() as i32)).collect();
+        if pts.windows(2).any(|w| order_ivec(w[0],w[1]) == order_ivec(a,b)) { Some(pts) } else { None }
+    })?;
+    if order_ivec(track_pts[0], track_pts[track_pts.len()-1]).0 != track_pts[0] { track_pts.reverse(); }
+    let track_segs :Vec<(Pt,Pt)> = track_pts.windows(2).map(|w| (w[0],w[1])).collect();
+
+    let model = analysis.model();
+    let seg_len = |(p,q) :(Pt,Pt)| model.lineseg_length(p,q).unwrap_or(DEFAULT_LINESEG_LENGTH);
+    let mut segment_length = seg_len((a,b)) as f32;
+    let track_len = track_segs.iter().map(|l| seg_len(*l)).sum::<f64>();
+    let mut track_length = track_len as f32;
+
+    let (p,q) = order_ivec(a,b);
+    let mut segment_gradient = model.lineseg_gradient(p,q) as f32;
+    let mut track_gradient = (track_segs.iter().map(|(p,q)| seg_len((*p,*q)) * model.lineseg_gradient(*p,*q))
+                              .sum::<f64>() / track_len) as f32;
+
+    let mut set_lengths = None;
+    let mut set_gradients = None;
+    unsafe {
+        widgets::show_text("Length");
+        igInputFloat(const_cstr!("Segment (m)").as_ptr(), &mut segment_length, 1.0, 10.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() && segment_length > 0.0 {
+            set_lengths = Some(vec![(order_ivec(a,b), segment_length as f64)]);
+        }
+        igInputFloat(const_cstr!("Track (m)").as_ptr(), &mut track_length, 1.0, 10.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() && track_length > 0.0 {
+            let l = track_length as f64 / track_segs.len() as f64;
+            set_lengths = Some(track_segs.iter().map(|(p,q)| (order_ivec(*p,*q), l)).collect());
+        }
+
+        widgets::show_text("Gradient (\u{2030}, rising to the right)");
+        igInputFloat(const_cstr!("Segment (\u{2030})").as_ptr(), &mut segment_gradient, 0.5, 5.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() {
+            set_gradients = Some(vec![((p,q), segment_gradient as f64)]);
+        }
+        igInputFloat(const_cstr!("Track (\u{2030})").as_ptr(), &mut track_gradient, 0.5, 5.0,
+                     const_cstr!("%.1f").as_ptr(), 0 as _);
+        if igIsItemEdited() {
+            set_gradients = Some(track_segs.iter().map(|(p,q)| {
+                let g = track_gradient as f64;
+                (order_ivec(*p,*q), if order_ivec(*p,*q) == (*p,*q) { g } else { -g })
+            }).collect());
+        }
+    }
+
+    if let Some(lengths) = set_lengths {
+        analysis.edit_model(|m| {
+            for (seg,l) in lengths { m.lineseg_lengths.insert(seg, l); }
+            Some(EditClass::LineSegLength(a,b))
+        });
+    }
+    if let Some(gradients) = set_gradients {
+        analysis.edit_model(|m| {
+            for (seg,g) in gradients { 
+                if g == 0.0 { m.lineseg_gradients.remove(&seg); } 
+                else { m.lineseg_gradients.insert(seg, g); }
+            }
+            Some(EditClass::LineSegGradient(a,b))
+        });
+    }
+    Some(())
+}
 
 pub fn object_menu(analysis :&mut Analysis, pta :PtA) -> Option<()> {
+    if let Some(name) = name_input(analysis.model(), Ref::Object(pta)) {
+        analysis.edit_model(|new| {
+            new.objects.get_mut(&pta).unwrap().name = if name.is_empty() { None } else { Some(name) };
+            Some(EditClass::ObjectName(pta))
+        });
+    }
+
     let obj = analysis.model().objects.get(&pta)?;
 
-    let mut set_distant = None;
+    let mut set_function = None;
     for f in obj.functions.iter() {
         match f {
             Function::Detector => { widgets::show_text("Detector"); },
-            Function::MainSignal { has_distant } => {
+            Function::MainSignal { has_distant, sight } => {
                 widgets::show_text("Main signal");
                 let mut has_distant = *has_distant;
                 unsafe {
                     igCheckbox(const_cstr!("Distant signal").as_ptr(), &mut has_distant);
                     if igIsItemEdited() {
-                        set_distant = Some(has_distant);
+                        set_function = Some((Function::MainSignal { has_distant, sight: *sight }, None));
                     }
                 }
-            }
+                if let Some((sight,cl)) = sight_input(analysis.model(), pta, *sight) {
+                    set_function = Some((Function::MainSignal { has_distant, sight }, cl));
+                }
+            },
+            Function::ShuntingSignal { sight } => {
+                widgets::show_text("Shunting signal");
+                if let Some((sight,cl)) = sight_input(analysis.model(), pta, *sight) {
+                    set_function = Some((Function::ShuntingSignal { sight }, cl));
+                }
+            },
+            Function::SpeedRestriction { speed } => {
+                widgets::show_text("Speed restriction");
+                let mut kmh = (*speed * 3.6) as f32;
+                unsafe {
+                    igInputFloat(const_cstr!("Speed (km/h)").as_ptr(), &mut kmh, 1.0, 10.0,
+                                 const_cstr!("%.1f").as_ptr(), 0 as _);
+                    if igIsItemEdited() && kmh > 0.0 {
+                        set_function = Some((Function::SpeedRestriction { speed: kmh as f64 / 3.6 },
+                                             Some(EditClass::ObjectSpeed(pta))));
+                    }
+                }
+            },
+            Function::MileageBreak { before, after } => {
+                widgets::show_text("Mileage break");
+                let mut km_before = (*before / 1000.0) as f32;
+                let mut km_after = (*after / 1000.0) as f32;
+                unsafe {
+                    igInputFloat(const_cstr!("Before (km)").as_ptr(), &mut km_before, 0.1, 1.0,
+                                 const_cstr!("%.3f").as_ptr(), 0 as _);
+                    if igIsItemEdited() {
+                        set_function = Some((Function::MileageBreak { before: km_before as f64 * 1000.0, after: *after },
+                                             Some(EditClass::ObjectMileage(pta))));
+                    }
+                    igInputFloat(const_cstr!("After (km)").as_ptr(), &mut km_after, 0.1, 1.0,
+                                 const_cstr!("%.3f").as_ptr(), 0 as _);
+                    if igIsItemEdited() {
+                        set_function = Some((Function::MileageBreak { before: *before, after: km_after as f64 * 1000.0 },
+                                             Some(EditClass::ObjectMileage(pta))));
+                    }
+                }
+            },
         }
     }
-    if let Some(d) = set_distant {
+    if let Some((f,cl)) = set_function {
         analysis.edit_model(|new| {
-            new.objects.get_mut(&pta).unwrap().functions = vec![Function::MainSignal { has_distant: d }];
-            None
+            new.objects.get_mut(&pta).unwrap().functions = vec![f];
+            cl
+        });
+    }
+
+    // Mileage breaks have their own chainage.
+    let obj = analysis.model().objects.get(&pta)?;
+    if obj.functions.iter().any(|f| matches!(f, Function::MileageBreak { .. })) { return Some(()); }
+    let fixed = obj.mileage;
+    let computed = analysis.data().dgraph.as_ref().and_then(|(_,dg)| dg.object_mileage(&pta));
+    if let Some(new_mileage) = mileage_input(fixed, computed) {
+        analysis.edit_model(|new| {
+            new.objects.get_mut(&pta).unwrap().mileage = new_mileage;
+            Some(EditClass::ObjectMileage(pta))
         });
     }
     Some(())
@@ -116,12 +380,21 @@ pub fn route_selector(analysis :&mut Analysis, dispatch_view :&Option<DispatchVi
         let mut some = false;
         let mut action = None;
         igIndent(14.0);
-        for idx in routes {
+        let train_routes = routes.iter().filter(|i| il.routes[**i].class == RouteClass::Train);
+        let shunting_routes = routes.iter().filter(|i| il.routes[**i].class == RouteClass::Shunting);
+        let mut shunting_header = false;
+        for idx in train_routes.chain(shunting_routes) {
             some = true;
+            if il.routes[*idx].class == RouteClass::Shunting && !shunting_header {
+                widgets::show_text("Shunting routes:");
+                shunting_header = true;
+            }
             igPushIDInt(*idx as _);
 
             let is_boundary = matches!(il.routes[*idx].route.entry, rolling_inf::RouteEntryExit::Boundary(_));
-            let text = CString::new(format!("Route to {:?}", (il.routes[*idx].route).exit)).unwrap();
+            let route_id = &il.routes[*idx].id;
+            let alt = if route_id.alternative > 0 { format!(" (alt. {})", route_id.alternative) } else { String::new() };
+            let text = CString::new(format!("Route to {}{}", analysis.model().ref_label(&route_id.to), alt)).unwrap();
 
             if is_boundary {
                 if igBeginMenu(text.as_ptr(), true) {
@@ -153,24 +426,34 @@ pub fn route_selector(analysis :&mut Analysis, dispatch_view :&Option<DispatchVi
 
 // TODO: return dispatch_view instead of &mut?
 pub fn add_plan_visit(analysis :&mut Analysis, 
-                      dispatch_view :&mut Option<DispatchView>, thing :Ref) {
+                      dispatch_view :&mut Option<DispatchView>, loc :PlanLoc) {
     let mut action = None;
 
     let have_manual = matches!(&dispatch_view, Some(DispatchView::Manual(_)));
     if have_manual { return; }
+    // Visits at points are only added to an open plan.
+    let have_auto = matches!(&dispatch_view, Some(DispatchView::Auto(_)));
+    if loc.is_err() && !have_auto { return; }
 
     unsafe {
         if let Some(DispatchView::Auto(AutoDispatchView { plan_idx, .. })) = &dispatch_view {
-            widgets::show_text("Add visit to plan");
+            if loc.is_ok() {
+                widgets::show_text("Add visit to plan");
+            } else {
+                widgets::sep();
+                widgets::show_text("Add visit at this point to plan");
+            }
 
             if let Some(plan) = analysis.model().plans.get(*plan_idx) {
                 igIndent(14.0);
                 for (train_id, (_veh, visits)) in plan.trains.iter() {
                     igPushIDInt(*train_id as _);
+                    if loc.is_err() { igPushIDInt(1); }
                     if igSelectable(const_cstr!("##pat").as_ptr(), false, 0 as _, ImVec2::zero()) {
                         action = Some(Some((*plan_idx,*train_id)));
                     }
                     igSameLine(0.0,-1.0); widgets::show_text(&format!("Train {}", train_id));
+                    if loc.is_err() { igPopID(); }
                     igPopID();
                 }
                 igUnindent(14.0);
@@ -186,7 +469,7 @@ pub fn add_plan_visit(analysis :&mut Analysis,
     if let Some(opt_train) = action {
         let mut set_plan = None;
         analysis.edit_model(|m| {
-            let visit = Visit { locs: vec![Ok(thing)], dwell: None, };
+            let visit = Visit { locs: vec![loc], dwell: None, };
             let visits = if let Some((plan_idx,  train_id)) = opt_train {
                 let (_,visits) = m.plans.get_mut(plan_idx).unwrap()
                                  .trains.get_mut(train_id).unwrap();
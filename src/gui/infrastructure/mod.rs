@@ -204,8 +204,8 @@ pub fn move_selected_objects(analysis :&mut Analysis, inf_view :&mut InfView, de
 
     let selection_before = inf_view.selection.clone();
 
+    // Dispatches and plans follow the moved objects by name, see `Model::relink_named_refs`.
     for (a,b) in changed_ptas {
-        model_rename_object(&mut model,a,b);
         inf_view.selection.remove(&Ref::Object(a));
         inf_view.selection.insert(Ref::Object(b));
     }
@@ -266,6 +266,10 @@ fn is_boundary_extension(analysis :&Analysis, p1 :Pt, p2 :Pt) -> Option<(Pt,Pt)>
 }
 
 fn model_rename_node(model :&mut Model, a :Pt, b :Pt) {
+    if let Some(name) = model.node_names.remove(&a) { model.node_names.insert(b, name); }
+    if let Some(km) = model.node_mileage.remove(&a) { model.node_mileage.insert(b, km); }
+    if model.mileage_origins.remove(&a).is_some() { model.mileage_origins.insert(b); }
+
     for (_,dispatch) in model.dispatches.iter_mut() {
         for (_,(_,command)) in dispatch.commands.iter_mut() {
             match command {
@@ -294,36 +298,6 @@ fn model_rename_node(model :&mut Model, a :Pt, b :Pt) {
     }
 }
 
-fn model_rename_object(model :&mut Model, a :PtA, b :PtA) {
-    for (_,dispatch) in model.dispatches.iter_mut() {
-        for (_,(_,command)) in dispatch.commands.iter_mut() {
-            match command {
-                Command::Train(_,r) | Command::Route(r) => {
-                    if r.from == Ref::Object(a) {
-                        r.from = Ref::Object(b);
-                    }
-                    if r.to == Ref::Object(a) {
-                        r.to = Ref::Object(b);
-                    }
-                }
-            };
-        }
-    }
-
-    for (_,p) in model.plans.iter_mut() {
-        for (_,(_veh, visits)) in p.trains.iter_mut() {
-            for (_,v) in visits.iter_mut() {
-                for l in v.locs.iter_mut() {
-                    if l == &Ok(Ref::Object(a)) {
-                        *l = Ok(Ref::Object(b));
-                    }
-                }
-            }
-        }
-    }
-}
-
-
 fn interact_insert(config :&Config, analysis :&mut Analysis, 
                    inf_view :&InfView, draw :&Draw, obj :Option<Object>) {
     unsafe {
@@ -451,7 +425,20 @@ fn object_select(inf_view :&mut InfView) {
                         Object {
                             loc: glm::vec2(0.0, 0.0),
                             tangent: glm::vec2(1,0),
-                            functions: vec![Function::MainSignal { has_distant: false}],
+                            functions: vec![Function::MainSignal { has_distant: false, sight: None }],
+                            mileage: None,
+                            name: None,
+                        }
+                        ));
+            } 
+            if igSelectable(const_cstr!("Shunting signal").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                inf_view.action = Action::InsertObject(Some( 
+                        Object {
+                            loc: glm::vec2(0.0, 0.0),
+                            tangent: glm::vec2(1,0),
+                            functions: vec![Function::ShuntingSignal { sight: None }],
+                            mileage: None,
+                            name: None,
                         }
                         ));
             } 
@@ -461,6 +448,30 @@ fn object_select(inf_view :&mut InfView) {
                             loc: glm::vec2(0.0, 0.0),
                             tangent: glm::vec2(1,0),
                             functions: vec![Function::Detector],
+                            mileage: None,
+                            name: None,
+                        }
+                        ));
+            } 
+            if igSelectable(const_cstr!("Speed restriction").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                inf_view.action = Action::InsertObject(Some( 
+                        Object {
+                            loc: glm::vec2(0.0, 0.0),
+                            tangent: glm::vec2(1,0),
+                            functions: vec![Function::SpeedRestriction { speed: 40.0 / 3.6 }],
+                            mileage: None,
+                            name: None,
+                        }
+                        ));
+            } 
+            if igSelectable(const_cstr!("Mileage break").as_ptr(), false, 0 as _, ImVec2::zero()) {
+                inf_view.action = Action::InsertObject(Some( 
+                        Object {
+                            loc: glm::vec2(0.0, 0.0),
+                            tangent: glm::vec2(1,0),
+                            functions: vec![Function::MileageBreak { before: 0.0, after: 0.0 }],
+                            mileage: None,
+                            name: None,
                         }
                         ));
             } 
@@ -476,7 +487,9 @@ fn context_menu(analysis :&mut Analysis,
                 draw :&Draw, preview_route :&mut Option<usize>) {
     unsafe {
     if igBeginPopup(const_cstr!("ctx").as_ptr(), 0 as _) {
-        context_menu_contents(analysis, inf_view, dispatch_view, preview_route);
+        let mouse :ImVec2 = igGetMousePosOnOpeningCurrentPopup_nonUDT2().into();
+        let pt = inf_view.view.screen_to_world_ptc(mouse - draw.pos);
+        context_menu_contents(analysis, inf_view, dispatch_view, preview_route, pt);
         igEndPopup();
     }
 
@@ -523,7 +536,7 @@ fn selection_title(inf_view :&InfView) -> String {
 
 fn context_menu_contents(analysis :&mut Analysis, inf_view :&mut InfView,
                          dispatch_view :&mut Option<DispatchView>,
-                         preview_route :&mut Option<usize>) {
+                         preview_route :&mut Option<usize>, pt :PtC) {
     unsafe {
     widgets::show_text(&selection_title(inf_view));
 
@@ -536,14 +549,14 @@ fn context_menu_contents(analysis :&mut Analysis, inf_view :&mut InfView,
     widgets::sep();
     if inf_view.selection.len() == 1 {
         let thing = inf_view.selection.iter().nth(0).cloned().unwrap();
-        context_menu_single(analysis, dispatch_view ,thing,preview_route);
+        context_menu_single(analysis, dispatch_view ,thing,preview_route, pt);
     }
     }
 }
 
 fn context_menu_single(analysis :&mut Analysis, 
                        dispatch_view :&mut Option<DispatchView>,
-                       thing :Ref, preview_route :&mut Option<usize>) {
+                       thing :Ref, preview_route :&mut Option<usize>, pt :PtC) {
 
     // Node editor
     if let Ref::Node(pt) = thing { 
@@ -551,6 +564,12 @@ fn context_menu_single(analysis :&mut Analysis,
         widgets::sep();
     }
 
+    // Line segment length editor
+    if let Ref::LineSeg(a,b) = thing { 
+        menus::lineseg_editor(analysis, a, b);
+        widgets::sep();
+    }
+
     // Object editor
     if let Ref::Object(pta) = thing { 
         menus::object_menu(analysis, pta);
@@ -565,7 +584,15 @@ fn context_menu_single(analysis :&mut Analysis,
     widgets::sep();
 
     // Add visits to auto dispatch
-    menus::add_plan_visit(analysis, dispatch_view, thing);
+    menus::add_plan_visit(analysis, dispatch_view, Ok(thing));
+
+    // Add visit at the clicked point on the track
+    if let Ref::LineSeg(a,b) = thing {
+        let a = glm::vec2(a.x as f32, a.y as f32);
+        let b = glm::vec2(b.x as f32, b.y as f32);
+        let (p,_) = util::project_to_line(pt, a, b);
+        menus::add_plan_visit(analysis, dispatch_view, Err(p));
+    }
 }
 
 
@@ -6,6 +6,7 @@ use crate::app::*;
 use crate::document::Document;
 use crate::gui;
 use crate::file;
+use crate::export;
 use crate::gui::widgets;
 
 pub fn load(app :&mut App) {
@@ -82,7 +83,13 @@ pub fn main_menu(app :&mut App) {
                 }
 
                 if igMenuItemBool(const_cstr!("Export to railML...").as_ptr(), std::ptr::null(), false, true) {
-                    // TODO 
+                    match export::export_railml_interactive(&app.document.analysis) {
+                        Err(e) => { error!("Error exporting railML: {}", e); },
+                        Ok(Some((filename, warnings))) if !warnings.is_empty() => {
+                            warn!("railML file {:?} is incomplete, {} warnings", filename, warnings.len());
+                        },
+                        _ => {},
+                    }
                 }
 
                 widgets::sep();
@@ -98,6 +105,10 @@ pub fn main_menu(app :&mut App) {
                                   std::ptr::null(), app.windows.vehicles, true) {
                     app.windows.vehicles = !app.windows.vehicles;
                 }
+                if igMenuItemBool(const_cstr!("Model settings").as_ptr(), 
+                                  std::ptr::null(), app.windows.settings, true) {
+                    app.windows.settings = !app.windows.settings;
+                }
                 if igMenuItemBool(const_cstr!("Signal designer").as_ptr(), 
                                   std::ptr::null(), app.windows.synthesis_window.is_some(), true) {
                     if app.windows.synthesis_window.is_none() {
@@ -128,6 +139,14 @@ pub fn main_menu(app :&mut App) {
                                   std::ptr::null(), app.windows.debug, true) {
                     app.windows.debug = !app.windows.debug;
                 }
+                if igMenuItemBool(const_cstr!("Issues").as_ptr(), 
+                                  std::ptr::null(), app.windows.issues, true) {
+                    app.windows.issues = !app.windows.issues;
+                }
+                if igMenuItemBool(const_cstr!("Route table").as_ptr(), 
+                                  std::ptr::null(), app.windows.route_table.open, true) {
+                    app.windows.route_table.open = !app.windows.route_table.open;
+                }
                 if igMenuItemBool(const_cstr!("Configure colors").as_ptr(), 
                                   std::ptr::null(), app.windows.config, true) {
                     app.windows.config = !app.windows.config;
@@ -71,7 +71,13 @@ pub fn main(app :&mut App) -> bool {
     app.windows.debug = windows::debug::debug_window(app.windows.debug, &app, 
                                                      inf_canvas.as_ref(), &app.document.inf_view );
     windows::vehicles::edit_vehicles_window(&mut app.windows.vehicles, &mut app.document);
+    windows::settings::edit_settings_window(&mut app.windows.settings, &mut app.document);
     windows::config::edit_config_window(&mut app.windows.config, &mut app.config);
+    windows::issues::issues_window(&mut app.windows.issues, &app.document.analysis, 
+                                   inf_canvas.as_ref(), &mut app.document.inf_view,
+                                   &mut app.document.dispatch_view);
+    app.windows.route_table.draw(&app.config, &app.document.analysis, 
+                                 inf_canvas.as_ref(), &app.document.inf_view);
 
     app.windows.import_window.draw(&mut app.document.analysis);
     if let Some(win) = &mut app.windows.synthesis_window { if !win.draw(&mut app.document.analysis) {
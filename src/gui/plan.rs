@@ -13,6 +13,7 @@ use crate::config::*;
 use crate::document::infview::{InfView, unround_coord};
 use crate::gui::infrastructure::draw::box_around;
 use crate::document::dispatch::DispatchOutput;
+use crate::document::plan::PlanDiagnosis;
 
 enum Action { 
     VisitDelete { key :VisitKey },
@@ -20,9 +21,12 @@ enum Action {
     VisitMoveBefore { source: VisitKey, target :VisitKey },
     VisitMoveToEnd { source: VisitKey, target: usize }, // Train id
     OrderDeleteAt { key :VisitKey },
+    VisitDwell { key :VisitKey, dwell :Option<f64> },
+    OrderMaxTime { idx :usize, max_time :Option<f64> },
     TrainVehicle { train: usize, vehicle: usize },
     NewTrain,
     RemoveTrain { train: usize },
+    CopyDispatch { dispatch: usize },
 }
 
 pub fn edit_plan(config :&Config, 
@@ -51,7 +55,20 @@ pub fn edit_plan(config :&Config,
         }
 
         igSameLine(0.0,-1.0);
-        plan_dispatches(config, analysis, auto_dispatch);
+        if let Some(a) = plan_dispatches(config, analysis, auto_dispatch) {
+            action = Some(a);
+        }
+
+        let diagnosis :Vec<PlanDiagnosis> = match analysis.data().plandiagnosis.get(plan_idx) {
+            Some(Some((generation, d))) if generation == analysis.generation() => d.clone(),
+            _ => Vec::new(),
+        };
+        for d in diagnosis.iter().filter(|d| d.train.is_none()) {
+            igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                                config.color_u32(RailUIColorName::CanvasSignalStop));
+            widgets::show_text(&format!("\u{f071} {}", d.message));
+            igPopStyleColor(1);
+        }
 
         widgets::sep();
 
@@ -109,6 +126,8 @@ pub fn edit_plan(config :&Config,
                     action = Some(Action::TrainVehicle { train: *train_id, vehicle: new_vehicle });
                 }
                 igPopItemWidth();
+                diagnosis_icon(config, diagnosis.iter()
+                               .filter(|d| d.train == Some(*train_id) && d.visit.is_none()));
 
                 // If there are no visits, draw a yellow suggested visit box 
                 if visits.iter().next().is_none() {
@@ -163,6 +182,8 @@ pub fn edit_plan(config :&Config,
                 // Draw the visit here.
                 edit_visit(config, analysis, auto_dispatch, 
                            vkey, visit, &mut hovered_visit, &mut action, first_visit);
+                diagnosis_icon(config, diagnosis.iter()
+                               .filter(|d| d.train == Some(train_id) && d.visit == Some(visit_id)));
                 igSameLine(0.0,-1.0);
 
                 let new_pos =  igGetCursorScreenPos_nonUDT2().into();
@@ -183,7 +204,7 @@ pub fn edit_plan(config :&Config,
 
             // Draw constraints
             let draw_list = igGetWindowDrawList();
-            for ((strain,svisit),(ttrain,tvisit),_) in plan.order.iter() {
+            for ((strain,svisit),(ttrain,tvisit),max_time) in plan.order.iter() {
                 let pos1 = visit_pos.get(&VisitKey { train: *strain, visit: *svisit, location: None });
                 let pos2 = visit_pos.get(&VisitKey { train: *ttrain, visit: *tvisit, location: None });
                 if let (Some(pos1),Some(pos2)) = (pos1.cloned(),pos2.cloned()) {
@@ -194,6 +215,12 @@ pub fn edit_plan(config :&Config,
                                        config.color_u32(RailUIColorName::GraphCommandRoute), 8);
                     ImDrawList_AddLine(draw_list, pos1, pos2, 
                                        config.color_u32(RailUIColorName::GraphTrainFront), 4.0);
+                    if let Some(max_time) = max_time {
+                        let text = format!("\u{2264} {:.0} s", max_time);
+                        let p = ImVec2 { x: 0.5*(pos1.x+pos2.x) + 6.0, y: 0.5*(pos1.y+pos2.y) - 6.0 };
+                        ImDrawList_AddText(draw_list, p, igGetColorU32(ImGuiCol__ImGuiCol_Text as _, 1.0),
+                                           text.as_ptr() as _, text.as_ptr().offset(text.len() as isize) as _);
+                    }
                     //ImDrawList_AddLine(draw_list, elbow, pos2, 
                                        //app.config.color_u32(RailUIColorName::GraphTrainFront), 4.0);
                 }
@@ -285,6 +312,21 @@ pub fn edit_plan(config :&Config,
                 None
             });
         },
+        Some(Action::OrderMaxTime { idx, max_time }) => {
+            analysis.edit_model(|m| {
+                let plan = m.plans.get_mut(plan_idx)?;
+                plan.order.get_mut(idx)?.2 = max_time;
+                Some(EditClass::OrderMaxTime(plan_idx, idx))
+            });
+        },
+        Some(Action::VisitDwell { key, dwell }) => {
+            analysis.edit_model(|m| {
+                let plan = m.plans.get_mut(plan_idx)?;
+                let (_,train) = plan.trains.get_mut(key.train)?;
+                train.get_mut(key.visit)?.dwell = dwell;
+                Some(EditClass::VisitDwell(plan_idx, key.train, key.visit))
+            });
+        },
         Some(Action::VisitDelete { key }) => {
             analysis.edit_model(|m| {
                 let plan = m.plans.get_mut(plan_idx)?;
@@ -304,6 +346,23 @@ pub fn edit_plan(config :&Config,
                 None
             });
         }
+        Some(Action::CopyDispatch { dispatch }) => {
+            // Only copy dispatches planned for the current model.
+            let source = match analysis.data().plandispatches.get(plan_idx) {
+                Some(Some((generation,dispatches))) if generation == analysis.generation() => 
+                    dispatches.get(dispatch).map(|d| d.dispatch.clone()),
+                _ => None,
+            };
+            if let Some(source) = source {
+                let mut model = analysis.model().clone();
+                let plan_name = model.plans.get(plan_idx).map(|p| p.name.clone())
+                    .unwrap_or("Plan".to_string());
+                let name = format!("{} (dispatch {})", plan_name, dispatch+1);
+                let id = model.dispatches.insert(Dispatch::from_vec(name, source.commands));
+                analysis.set_model(model, None);
+                new_dispatchview = Some(Some(DispatchView::Manual(ManualDispatchView::new(id))));
+            }
+        },
         _ => {},
     }
 
@@ -336,7 +395,8 @@ pub fn planning_icon(config :&Config, analysis :&Analysis, generation :usize, di
     }
 }
 
-fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchView)  {
+fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchView) -> Option<Action> {
+    let mut action = None;
     unsafe {
         if let Some(Some((generation,dispatches))) = analysis.data().plandispatches.get(adv.plan_idx) {
             planning_icon(config,analysis,*generation,dispatches);
@@ -369,8 +429,21 @@ fn plan_dispatches(config :&Config, analysis :&Analysis, adv :&mut AutoDispatchV
 
                 igEndCombo();
             }
+
+            if let Some(dispatch_idx) = dispatch_idx {
+                igSameLine(0.0,-1.0);
+                if igButton(const_cstr!("\u{f0c5} Copy as manual").as_ptr(), ImVec2::zero()) {
+                    action = Some(Action::CopyDispatch { dispatch: dispatch_idx });
+                }
+                if igIsItemHovered(0) {
+                    igBeginTooltip();
+                    widgets::show_text("Copy this dispatch into a new manual dispatch which can be edited.");
+                    igEndTooltip();
+                }
+            }
         }
     }
+    action
 }
 
 fn visit_move(m: &mut Model, plan :usize, source :VisitKey, t_train_idx: usize, idx :Option<usize>) -> Option<()> {
@@ -531,6 +604,25 @@ fn drop_visitkey(key :*const i8) -> Option<VisitKey> {
 }
 
 
+/// Warning icon with the diagnosis messages as tooltip, drawn on the same line.
+fn diagnosis_icon<'a>(config :&Config, diagnosis :impl Iterator<Item = &'a PlanDiagnosis>) {
+    let messages = diagnosis.map(|d| d.message.as_str()).collect::<Vec<_>>();
+    if messages.len() == 0 { return; }
+    unsafe {
+        igSameLine(0.0,-1.0);
+        igAlignTextToFramePadding();
+        igPushStyleColorU32(ImGuiCol__ImGuiCol_Text as _, 
+                            config.color_u32(RailUIColorName::CanvasSignalStop));
+        widgets::show_text("\u{f071}");
+        igPopStyleColor(1);
+        if igIsItemHovered(0) {
+            igBeginTooltip();
+            for m in messages { widgets::show_text(m); }
+            igEndTooltip();
+        }
+    }
+}
+
 fn edit_visit(config :&Config, vm :&Analysis, auto_dispatch :&mut AutoDispatchView, 
               visit_key :VisitKey, visit :&Visit, hovered_visit :&mut Option<VisitKey>, 
               action :&mut Option<Action>, first_visit :bool) {
@@ -602,6 +694,16 @@ unsafe {
             igPopID();
         }
 
+        if let Some(dwell) = visit.dwell {
+            igAlignTextToFramePadding();
+            widgets::show_text(&format!("{:.0}s", dwell));
+            if igIsItemHovered(0) {
+                igBeginTooltip();
+                widgets::show_text(&format!("Dwell time {:.0} s", dwell));
+                igEndTooltip();
+            }
+        }
+
         igEndChild();
     }
     igPopStyleColor(1);
@@ -632,6 +734,46 @@ unsafe {
                 if igSelectable(const_cstr!("\u{f55a} Remove ordering constraints").as_ptr(), false, 0 as _, ImVec2::zero()) {
                     *action = Some(Action::OrderDeleteAt { key });
                 }
+
+                if let Some(plan) = vm.model().plans.get(auto_dispatch.plan_idx) {
+                    for (order_idx,(a,b,max_time)) in plan.order.iter().enumerate() {
+                        if *a != (key.train, key.visit) && *b != (key.train, key.visit) { continue; }
+                        igPushIDInt(order_idx as _);
+                        widgets::sep();
+                        widgets::show_text(&format!("\u{f239} ({}) visit {} before \u{f239} ({}) visit {}", 
+                                                    a.0, a.1, b.0, b.1));
+                        let mut bounded = max_time.is_some();
+                        if igCheckbox(const_cstr!("Maximum time between visits").as_ptr(), &mut bounded) {
+                            let max_time = if bounded { Some(DEFAULT_ORDER_MAX_TIME) } else { None };
+                            *action = Some(Action::OrderMaxTime { idx: order_idx, max_time });
+                        }
+                        if let Some(max_time) = max_time {
+                            let mut max_time = *max_time as f32;
+                            igInputFloat(const_cstr!("Max. time (s)").as_ptr(), &mut max_time, 1.0, 10.0,
+                                         const_cstr!("%.0f").as_ptr(), 0 as _);
+                            if igIsItemEdited() && max_time >= 0.0 {
+                                *action = Some(Action::OrderMaxTime { idx: order_idx, max_time: Some(max_time as f64) });
+                            }
+                        }
+                        igPopID();
+                    }
+                }
+
+                widgets::sep();
+
+                let mut stop = visit.dwell.is_some();
+                if igCheckbox(const_cstr!("Stop at visit").as_ptr(), &mut stop) {
+                    let dwell = if stop { Some(DEFAULT_DWELL) } else { None };
+                    *action = Some(Action::VisitDwell { key, dwell });
+                }
+                if let Some(dwell) = visit.dwell {
+                    let mut dwell = dwell as f32;
+                    igInputFloat(const_cstr!("Dwell time (s)").as_ptr(), &mut dwell, 1.0, 10.0,
+                                 const_cstr!("%.0f").as_ptr(), 0 as _);
+                    if igIsItemEdited() && dwell >= 0.0 {
+                        *action = Some(Action::VisitDwell { key, dwell: Some(dwell as f64) });
+                    }
+                }
             },
             _ => {
                 widgets::show_text("No visit selected.");
@@ -691,7 +833,7 @@ fn good_location_marker(config :&Config, vm :&Analysis, loc :&PlanLoc, first_vis
                         config.color_u32(RailUIColorName::CanvasRoutePath)
                     };
                 },
-                NDType::Sw(_)  => {
+                NDType::Sw(_,_)  => {
                     name = const_cstr!("\u{f074}");
                     col = config.color_u32(RailUIColorName::GraphCommandRoute);
                 },
@@ -699,7 +841,7 @@ fn good_location_marker(config :&Config, vm :&Analysis, loc :&PlanLoc, first_vis
                     name = const_cstr!("\u{f337}");
                     col = config.color_u32(RailUIColorName::GraphTrainFront);
                 }
-                NDType::Crossing(_) => { 
+                NDType::Crossing(_,_) => { 
                     name = const_cstr!("\u{f074}");
                     col = config.color_u32(RailUIColorName::GraphBlockReserved);
                 },
@@ -712,12 +854,12 @@ fn good_location_marker(config :&Config, vm :&Analysis, loc :&PlanLoc, first_vis
             col = config.color_u32(RailUIColorName::CanvasTrackDrawing);
         },
         Ok(Ref::Object(_)) => {
-            // TODO check object type or just allow signals?
             name = const_cstr!("-O");
             col = config.color_u32(RailUIColorName::CanvasSignalStop);
         }
-        Err(ptc) =>  {
-            return Err(());
+        Err(_) =>  {
+            name = const_cstr!("\u{f3c5}");
+            col = config.color_u32(RailUIColorName::CanvasTrackDrawing);
         }
     };
     igPushStyleColorU32(ImGuiCol__ImGuiCol_Button as _, col);
@@ -0,0 +1,61 @@
+use backend_glfw::imgui::*;
+use const_cstr::*;
+use std::ffi::CString;
+use nalgebra_glm as glm;
+
+use crate::document::*;
+use crate::document::model::*;
+use crate::document::analysis::Analysis;
+use crate::document::infview::{InfView, unround_coord};
+use crate::document::issues::*;
+use crate::gui::widgets;
+use crate::gui::widgets::Draw;
+
+/// List of the analysis issues. Clicking an issue selects and centers its
+/// location in the infrastructure view, or opens the dispatch or plan.
+pub fn issues_window(popen :&mut bool, analysis :&Analysis, inf_canvas :Option<&Draw>,
+                     inf_view :&mut InfView, dispatch_view :&mut Option<DispatchView>) {
+    if !*popen { return; }
+    unsafe {
+        widgets::next_window_center_when_appearing();
+        igBegin(const_cstr!("Issues").as_ptr(), popen as _, 0 as _);
+
+        let issues = &analysis.data().issues;
+        if issues.len() == 0 {
+            widgets::show_text("No issues.");
+        }
+        for (i,issue) in issues.iter().enumerate() {
+            igPushIDInt(i as _);
+            let text = CString::new(issue.message.as_str()).unwrap();
+            if igSelectable(text.as_ptr(), false, 0 as _, ImVec2::zero()) {
+                match issue.location {
+                    Some(IssueLocation::Infrastructure(r)) => {
+                        inf_view.selection = std::iter::once(r).collect();
+                        if let Some(draw) = inf_canvas {
+                            inf_view.view.center_on(ref_location(r), draw.size);
+                        }
+                    },
+                    Some(IssueLocation::Dispatch(idx)) => {
+                        *dispatch_view = Some(DispatchView::Manual(ManualDispatchView::new(idx)));
+                    },
+                    Some(IssueLocation::Plan(plan_idx)) => {
+                        *dispatch_view = Some(DispatchView::Auto(AutoDispatchView {
+                            plan_idx, dispatch: None, action: PlanViewAction::None, }));
+                    },
+                    None => {},
+                }
+            }
+            igPopID();
+        }
+
+        igEnd();
+    }
+}
+
+fn ref_location(r :Ref) -> PtC {
+    match r {
+        Ref::Node(pt) => glm::vec2(pt.x as f32, pt.y as f32),
+        Ref::LineSeg(a,b) => 0.5*glm::vec2((a.x+b.x) as f32, (a.y+b.y) as f32),
+        Ref::Object(pta) => unround_coord(pta),
+    }
+}
@@ -4,4 +4,7 @@ pub mod config;
 pub mod quit;
 pub mod logview;
 pub mod synthesis;
+pub mod settings;
+pub mod routetable;
+pub mod issues;
 
@@ -0,0 +1,88 @@
+use backend_glfw::imgui::*;
+use const_cstr::*;
+use log::*;
+use std::ffi::CString;
+
+use crate::config::Config;
+use crate::document::analysis::{Analysis, Generation};
+use crate::document::infview::InfView;
+use crate::export;
+use crate::gui::infrastructure;
+use crate::gui::widgets;
+use crate::gui::widgets::Draw;
+
+pub struct RouteTableWindow {
+    pub open :bool,
+    selected :Option<usize>,
+    /// Rows of the route table, for the interlocking of the given generation.
+    rows :Option<(Generation, Vec<Vec<String>>)>,
+}
+
+impl RouteTableWindow {
+    pub fn new() -> Self {
+        RouteTableWindow { open: false, selected: None, rows: None }
+    }
+
+    /// Table of the interlocking's routes. Clicking a row highlights the
+    /// route in the infrastructure view.
+    pub fn draw(&mut self, config :&Config, analysis :&Analysis,
+                inf_canvas :Option<&Draw>, inf_view :&InfView) {
+        if !self.open { return; }
+        unsafe {
+        widgets::next_window_center_when_appearing();
+        igBegin(const_cstr!("Route table").as_ptr(), &mut self.open as _,
+                ImGuiWindowFlags__ImGuiWindowFlags_HorizontalScrollbar as _);
+
+        let data = analysis.data();
+        match (&data.dgraph, &data.interlocking) {
+            (Some((_,dgraph)), Some((generation,il))) => {
+                if igButton(const_cstr!("Export to CSV...").as_ptr(), ImVec2::zero()) {
+                    match export::export_route_table_csv_interactive(analysis) {
+                        Err(e) => { error!("Error exporting route table: {}", e); },
+                        _ => {},
+                    }
+                }
+
+                if self.rows.as_ref().map(|(g,_)| g != generation).unwrap_or(true) {
+                    self.rows = Some((*generation, export::route_table(analysis.model(), dgraph, il)));
+                }
+                let rows = &self.rows.as_ref().unwrap().1;
+                if self.selected.map(|i| i >= rows.len()).unwrap_or(false) { self.selected = None; }
+
+                igColumns(export::ROUTE_TABLE_COLUMNS.len() as _, const_cstr!("routetable").as_ptr(), true);
+                for c in export::ROUTE_TABLE_COLUMNS.iter() {
+                    widgets::show_text(c);
+                    igNextColumn();
+                }
+                igSeparator();
+                for (i,row) in rows.iter().enumerate() {
+                    igPushIDInt(i as _);
+                    let name = CString::new(row[0].as_str()).unwrap();
+                    if igSelectable(name.as_ptr(), self.selected == Some(i),
+                                    ImGuiSelectableFlags__ImGuiSelectableFlags_SpanAllColumns as _, ImVec2::zero()) {
+                        self.selected = if self.selected == Some(i) { None } else { Some(i) };
+                    }
+                    igNextColumn();
+                    for cell in row.iter().skip(1) {
+                        widgets::show_text(cell);
+                        igNextColumn();
+                    }
+                    igPopID();
+                }
+                igColumns(1, std::ptr::null(), false);
+            },
+            _ => {
+                widgets::show_text("Interlocking is not available.");
+            },
+        }
+
+        igEnd();
+        }
+
+        if let (Some(route_idx), Some(inf)) = (self.selected, inf_canvas) {
+            inf.begin_draw();
+            infrastructure::draw::route(config, analysis, inf_view, inf, route_idx);
+            inf.end_draw();
+        }
+    }
+}
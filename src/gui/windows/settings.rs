@@ -0,0 +1,59 @@
+use crate::document::Document;
+use crate::document::model::*;
+use const_cstr::*;
+use backend_glfw::imgui::*;
+use crate::gui::widgets;
+
+pub fn edit_settings(doc :&mut Document) {
+    unsafe {
+    let settings = &doc.analysis.model().settings;
+
+    let mut sight = settings.sight_distance as f32;
+    igInputFloat(const_cstr!("Default sight distance (m)").as_ptr(), &mut sight, 1.0, 10.0,
+                 const_cstr!("%.1f").as_ptr(), 0 as _);
+    if igIsItemEdited() && sight >= 0.0 {
+        doc.analysis.edit_model(|m| {
+            m.settings.sight_distance = sight as f64;
+            Some(EditClass::SightDistance)
+        });
+    }
+
+    widgets::show_text("Interlocking routes");
+    let routes = &settings.routes;
+    let mut tolerance = routes.section_tolerance as f32;
+    let mut min_length = routes.route_minimum_length as f32;
+    let mut overlap = routes.overlap_length as f32;
+    let mut edited = false;
+    igInputFloat(const_cstr!("Section tolerance (m)").as_ptr(), &mut tolerance, 1.0, 10.0,
+                 const_cstr!("%.1f").as_ptr(), 0 as _);
+    edited |= igIsItemEdited();
+    igInputFloat(const_cstr!("Minimum route length (m)").as_ptr(), &mut min_length, 1.0, 10.0,
+                 const_cstr!("%.1f").as_ptr(), 0 as _);
+    edited |= igIsItemEdited();
+    igInputFloat(const_cstr!("Overlap length (m)").as_ptr(), &mut overlap, 1.0, 10.0,
+                 const_cstr!("%.1f").as_ptr(), 0 as _);
+    edited |= igIsItemEdited();
+    if edited && tolerance >= 0.0 && min_length >= 0.0 && overlap >= 0.0 {
+        doc.analysis.edit_model(|m| {
+            m.settings.routes = RouteSettings {
+                section_tolerance: tolerance as f64,
+                route_minimum_length: min_length as f64,
+                overlap_length: overlap as f64,
+            };
+            Some(EditClass::RouteSettings)
+        });
+    }
+    }
+}
+
+pub fn edit_settings_window(popen :&mut bool, doc :&mut Document) {
+    if !*popen { return; }
+    unsafe {
+    widgets::next_window_center_when_appearing();
+    igBegin(const_cstr!("Model settings").as_ptr(), popen as *mut bool, 0 as _);
+
+    edit_settings(doc);
+
+    igEnd();
+    }
+}
@@ -32,7 +32,7 @@ pub struct SynthesisWindow {
 fn add_objects(analysis :&mut Analysis, objs :&Design) {
     use crate::document::topology;
     let mut model = analysis.model().clone();
-    let topo = topology::convert(&model, 50.0).unwrap();
+    let topo = topology::convert(&model, DEFAULT_LINESEG_LENGTH).unwrap();
     for (track_idx, pos, func, dir) in objs.iter() {
         let sideways = match dir {
             None => 0.0,
@@ -46,6 +46,8 @@ fn add_objects(analysis :&mut Analysis, objs :&Design) {
             loc: pt, 
             tangent: glm::vec2(tangent.x.round() as _, tangent.y.round() as _),
             functions: vec![*func],
+            mileage: None,
+            name: None,
         };
         obj.move_to(&model, pt + sideways*glm::vec2(normal.x as f32, normal.y as f32));
         //println!("ADding object {:?}", obj);
@@ -56,6 +58,8 @@ fn add_objects(analysis :&mut Analysis, objs :&Design) {
                 loc: pt, 
                 tangent: glm::vec2(tangent.x.round() as _, tangent.y.round() as _),
                 functions: vec![Function::Detector],
+                mileage: None,
+                name: None,
             };
             obj.move_to(&model, pt + sideways*glm::vec2(normal.x as f32, normal.y as f32));
             //println!("ADding object {:?}", obj);
@@ -181,10 +185,11 @@ impl SynthesisWindow {
 
         self.thread_pool.execute(move || {
             use crate::document::topology;
-            let topo = topology::convert(&model, 50.0).unwrap();
+            let topo = topology::convert(&model, DEFAULT_LINESEG_LENGTH).unwrap();
             let vehicles = model.vehicles.iter().cloned().collect::<Vec<_>>();
 
-            let result = full_synthesis(&SynthesisBackground { topology: &topo, plans: &plans, vehicles: &vehicles }, 
+            let result = full_synthesis(&SynthesisBackground { topology: &topo, plans: &plans, vehicles: &vehicles,
+                                                                     route_settings: &model.settings.routes }, 
                            |msg| tx.send(msg).is_ok());
 
             if let Err(e) = result {
@@ -0,0 +1,163 @@
+// Headless analysis of a model file.
+//
+// Runs the derivation pipeline of the background thread in `document::analysis`
+// (topology, dgraph, interlocking, dispatches, plans), but without opening a
+// window, and writes a summary report in TOML format.
+//
+
+use log::*;
+use serde::Serialize;
+
+use std::collections::HashMap;
+
+use crate::file;
+use crate::document::model::*;
+use crate::document::topology;
+use crate::document::analysis::{self, SetData};
+use crate::document::issues::IssueLocation;
+use crate::document::dispatch;
+
+#[derive(Serialize)]
+#[derive(Debug)]
+pub struct Report {
+    pub filename :String,
+    pub num_routes :usize,
+    pub num_boundary_routes :usize,
+    pub num_signal_routes :usize,
+    pub dispatches :Vec<DispatchReport>,
+    pub plans :Vec<PlanReport>,
+}
+
+#[derive(Serialize)]
+#[derive(Debug)]
+pub struct DispatchReport {
+    pub id :usize,
+    pub name :String,
+    pub num_commands :usize,
+    pub end_time :Option<f64>,
+    pub error :Option<String>,
+}
+
+#[derive(Serialize)]
+#[derive(Debug)]
+pub struct PlanReport {
+    pub id :usize,
+    pub name :String,
+    pub num_dispatches :usize,
+    pub end_times :Vec<f64>,
+    pub error :Option<String>,
+}
+
+/// Load the model from file and run the full analysis pipeline on it.
+pub fn analyze_file(filename :&str) -> Result<Report, String> {
+    let model = file::load(filename).map_err(|e| format!("Could not load {:?}: {}", filename, e))?;
+    analyze(filename.to_string(), &model)
+}
+
+pub fn analyze(filename :String, model :&Model) -> Result<Report, String> {
+    let topology = topology::convert(model, DEFAULT_LINESEG_LENGTH)
+        .map_err(|_| format!("Topology conversion failed"))?;
+    info!("Topology successful with {} tracks", topology.tracks.len());
+
+    let mut il = None;
+    let mut dispatch_outputs = HashMap::new();
+    let mut plan_outputs = HashMap::new();
+    let mut plan_diagnosis = HashMap::new();
+    let mut issues = Vec::new();
+    analysis::run_pipeline(model, &topology, 0, |data| {
+        match data {
+            SetData::Interlocking(_, x) => { il = Some(x); },
+            SetData::Dispatch(_, idx, output) => { dispatch_outputs.insert(idx, output); },
+            SetData::PlanDispatch(_, idx, outputs) => { plan_outputs.insert(idx, outputs); },
+            SetData::PlanDiagnosis(_, idx, diagnosis) => { plan_diagnosis.insert(idx, diagnosis); },
+            SetData::Issues(_, xs) => { issues.extend(xs); },
+            SetData::DGraph(_, _) => {},
+        }
+        true
+    });
+
+    for issue in issues.iter() { warn!("{}", issue.message); }
+    let il = il.ok_or_else(|| issues.iter().map(|i| i.message.clone()).collect::<Vec<_>>().join(" "))?;
+
+    let issue_at = |location :IssueLocation| issues.iter().find(|i| match (i.location, location) {
+        (Some(IssueLocation::Dispatch(a)), IssueLocation::Dispatch(b)) => a == b,
+        (Some(IssueLocation::Plan(a)), IssueLocation::Plan(b)) => a == b,
+        _ => false,
+    }).map(|i| i.message.clone());
+
+    let mut dispatches = Vec::new();
+    for (id,d) in model.dispatches.iter() {
+        dispatches.push(DispatchReport {
+            id: *id,
+            name: d.name.clone(),
+            num_commands: d.commands.len(),
+            end_time: dispatch_outputs.get(id).map(|o| dispatch::max_time(&o.history)),
+            error: issue_at(IssueLocation::Dispatch(*id)),
+        });
+    }
+
+    let mut plans = Vec::new();
+    for (id,p) in model.plans.iter() {
+        let end_times :Vec<f64> = plan_outputs.get(id).into_iter().flatten()
+            .map(|o| dispatch::max_time(&o.history)).collect();
+        let diagnosis = plan_diagnosis.get(id).filter(|d| end_times.len() == 0 && d.len() > 0)
+            .map(|diagnosis| diagnosis.iter().map(|d| match (d.train, d.visit) {
+                (Some(t),Some(v)) => format!("Train {} visit {}: {}", t, v, d.message),
+                (Some(t),None) => format!("Train {}: {}", t, d.message),
+                _ => d.message.clone(),
+            }).collect::<Vec<_>>().join(" "));
+        plans.push(PlanReport {
+            id: *id,
+            name: p.name.clone(),
+            num_dispatches: end_times.len(),
+            end_times,
+            error: issue_at(IssueLocation::Plan(*id)).or(diagnosis),
+        });
+    }
+
+    Ok(Report {
+        filename,
+        num_routes: il.routes.len(),
+        num_boundary_routes: il.boundary_routes.values().map(|r| r.len()).sum(),
+        num_signal_routes: il.signal_routes.values().map(|r| r.len()).sum(),
+        dispatches,
+        plans,
+    })
+}
+
+/// Command line entry point: `junction --headless <model file> [<report file>]`.
+/// Writes the report to standard output if no report file is given.
+pub fn run(args :&[String]) -> Result<(), String> {
+    let input = args.get(0).ok_or(format!("Usage: junction --headless <model file> [<report file>]"))?;
+    let report = analyze_file(input)?;
+    let output = toml::to_string(&report).map_err(|e| format!("Could not write report: {}", e))?;
+    match args.get(1) {
+        Some(filename) => {
+            std::fs::write(filename, output)
+                .map_err(|e| format!("Could not write report to {:?}: {}", filename, e))?;
+            info!("Report written to {:?}", filename);
+        },
+        None => { print!("{}", output); },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::model::test_models::*;
+    use nalgebra_glm as glm;
+
+    #[test]
+    fn analyze_straight_line() {
+        let mut model = straight_line(10);
+        add_object(&mut model, glm::vec2(5.0,-0.3), main_signal());
+
+        let report = analyze("straight line".to_string(), &model).unwrap();
+        // Boundary to signal and signal to boundary in the signal's direction,
+        // and boundary to boundary in the other direction.
+        assert_eq!(report.num_routes, 3);
+        assert_eq!(report.num_boundary_routes, 2);
+        assert_eq!(report.num_signal_routes, 1);
+    }
+}
@@ -1,14 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use log::*;
 use matches::*;
 use const_cstr::const_cstr;
 use crate::document::model::*;
 use crate::document::model;
+use crate::document::objects::*;
+use crate::document::infview::round_coord;
 use crate::document::analysis::*;
 use crate::file;
 use crate::app::*;
 use crate::gui::widgets;
 use std::sync::mpsc;
+use nalgebra_glm as glm;
 
 pub enum ImportError {
 }
@@ -117,27 +120,38 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
     if tx.send(ImportState::Ping).is_err() { return; }
     info!("Read file {:?}", filename);
 
-    let parsed = match railmlio::xml::parse_railml(&s) {
-        Ok(p) => p,
-        Err(e) => {
-            let _ = tx.send(ImportState::SourceFileError(format!("Parse error: {:?}", e)));
-            return;
-        },
-    };
-    if tx.send(ImportState::Ping).is_err() { return; }
-    info!("Parsed railml");
-
-    let topomodel = match railmlio::topo::convert_railml_topo(parsed) {
-        Ok(m) => m,
-        Err(e) => {
-            let _ = tx.send(ImportState::SourceFileError(format!("Model conversion error: {:?}", e)));
-            return;
-        },
+    let topomodel = if railmlio::railml3::is_railml3(&s) {
+        // railML 3.x: netElements/netRelations are read directly into the topo model
+        match railmlio::railml3::parse_railml3_topo(&s) {
+            Ok(m) => m,
+            Err(e) => {
+                let _ = tx.send(ImportState::SourceFileError(format!("railML 3 conversion error: {}", e)));
+                return;
+            },
+        }
+    } else {
+        let parsed = match railmlio::xml::parse_railml(&s) {
+            Ok(p) => p,
+            Err(e) => {
+                let _ = tx.send(ImportState::SourceFileError(format!("Parse error: {:?}", e)));
+                return;
+            },
+        };
+        if tx.send(ImportState::Ping).is_err() { return; }
+        info!("Parsed railml");
+
+        match railmlio::topo::convert_railml_topo(parsed) {
+            Ok(m) => m,
+            Err(e) => {
+                let _ = tx.send(ImportState::SourceFileError(format!("Model conversion error: {:?}", e)));
+                return;
+            },
+        }
     };
     if tx.send(ImportState::Ping).is_err() { return; }
     info!("Converted to topomodel");
 
-    let plotmodel = match convert_railplot(topomodel) {
+    let (plotmodel, edge_tracks) = match convert_railplot(&topomodel) {
         Ok(m) => m,
         Err(e) => {
             let _ = tx.send(e);
@@ -169,7 +183,7 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
     if tx.send(ImportState::Ping).is_err() { return; }
 
     info!("Found model");
-    let model = match convert_junction(plot) {
+    let model = match convert_junction(plot, &topomodel, &edge_tracks) {
         Ok(m) => m,
         Err(e) => {
             let _ = tx.send(e);
@@ -182,8 +196,15 @@ pub fn load_railml_file(filename :String, tx :mpsc::Sender<ImportState>)  {
 }
 
 
-pub fn convert_railplot(topo :railmlio::topo::Topological) 
-    -> Result<railplotlib::model::SchematicGraph<()>, ImportState> {
+/// Identifies a plot edge by its end nodes and ports.
+pub type EdgeKey = ((String, railplotlib::model::Port), (String, railplotlib::model::Port));
+
+/// Map from plot edges to topology track index, and whether the edge 
+/// goes in the opposite direction of the track.
+pub type EdgeTracks = HashMap<EdgeKey, (usize, bool)>;
+
+pub fn convert_railplot(topo :&railmlio::topo::Topological) 
+    -> Result<(railplotlib::model::SchematicGraph<()>, EdgeTracks), ImportState> {
 
     use railmlio::topo;
     use railplotlib::model as plot;
@@ -273,6 +294,7 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
                 nodes: Vec::new(),
                 edges: Vec::new(),
             };
+            let mut edge_tracks = HashMap::new();
 
             fn to_dir(dir :isize) -> plot::Dir { 
                 match dir {
@@ -295,8 +317,10 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
                             plot::Shape::Switch(plot::Side::Left, to_dir(dir)),
                         topo::TopoNode::Switch(topo::Side::Right) => 
                             plot::Shape::Switch(plot::Side::Right, to_dir(dir)),
-
-                        _ => unimplemented!(),
+                        topo::TopoNode::Crossing(_) => {
+                            return Err(ImportState::SourceFileError(
+                                format!("Crossings are not yet supported in the schematic plot.")));
+                        },
                     }
                 });
             }
@@ -311,7 +335,8 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
                 let mut nb = track_connections.get(&(track_idx,topo::AB::B))
                     .ok_or(ImportState::SourceFileError(format!("Inconsistent connections.")))?;
 
-                if model.nodes[na.0].pos > model.nodes[nb.0].pos {
+                let swapped = model.nodes[na.0].pos > model.nodes[nb.0].pos;
+                if swapped {
                     std::mem::swap(&mut na, &mut nb);
                 }
 
@@ -332,11 +357,12 @@ pub fn convert_railplot(topo :railmlio::topo::Topological)
 
                 debug!("Edge {} {:?} {:?}", model.edges.len(), a,b);
 
+                edge_tracks.insert((a.clone(),b.clone()), (track_idx, swapped));
                 model.edges.push(plot::Edge { a,b, objects :Vec::new() });
             }
 
 
-            Ok(model)
+            Ok((model, edge_tracks))
         }
     }
 }
@@ -350,7 +376,9 @@ pub fn round_pt_tol((x,y) :(f64,f64)) -> Result<Pt,()> {
     Ok(glm::vec2(x.round() as _, (-20.0 + y.round()) as _))
 }
 
-pub fn convert_junction(plot :railplotlib::solvers::SchematicOutput<()>) -> Result<Model, ImportState> {
+pub fn convert_junction(plot :railplotlib::solvers::SchematicOutput<()>,
+                        topo :&railmlio::topo::Topological,
+                        edge_tracks :&EdgeTracks) -> Result<Model, ImportState> {
     debug!("Starting conversion of railplotlib schematic output");
     for (e,pts) in &plot.lines {
         debug!("Line {:?}", pts);
@@ -374,6 +402,7 @@ pub fn convert_junction(plot :railplotlib::solvers::SchematicOutput<()>) -> Resu
         // TODO
     }
 
+    let mut lines = Vec::new();
     for (e,pts) in plot.lines {
         let pts = pts.into_iter().map(|x| round_pt_tol(x)).collect::<Result<Vec<_>,()>>()
             .map_err(|_| ImportState::PlotError(format!("Solution contains point not on grid")))?;
@@ -384,12 +413,115 @@ pub fn convert_junction(plot :railplotlib::solvers::SchematicOutput<()>) -> Resu
                 model.linesegs.insert((p1,p2));
             }
         }
+        lines.push((e,pts));
+    }
+
+    // Trackside objects are placed on the plotted lines after all the
+    // line segments are in the model, so that `Object::move_to` can find 
+    // the closest line segment.
+    let mut used_names = HashSet::new();
+    for (e,pts) in lines {
+        let (track_idx, swapped) = match edge_tracks.get(&(e.a.clone(), e.b.clone())) {
+            Some(x) => *x,
+            None => {
+                warn!("Could not find track for plotted edge {:?}", (&e.a, &e.b));
+                continue;
+            }
+        };
+        if pts.len() < 2 { continue; }
+        let track = &topo.tracks[track_idx];
+        let pline = pts.iter().map(|p| glm::vec2(p.x as f32, p.y as f32)).collect::<Vec<PtC>>();
+
+        let mut place = |pos :f64, func :Function, dir :Option<railmlio::model::TrackDirection>, name :Option<String>| {
+            let param = if track.length > 0.0 { (pos - track.offset) / track.length } else { 0.0 };
+            let param = if swapped { 1.0 - param } else { param };
+            let (pt, tangent) = pline_point(&pline, param.max(0.0).min(1.0) as f32);
+            let pt = match dir {
+                // Signals are placed on the right hand side of the travel direction.
+                Some(dir) => {
+                    let along = matches!(dir, railmlio::model::TrackDirection::Up) != swapped;
+                    let travel = if along { tangent } else { -tangent };
+                    pt + 0.25*glm::vec2(travel.y, -travel.x)
+                },
+                None => pt,
+            };
+            let name = name.map(|n| unique_name(&mut used_names, n));
+            let mut obj = Object { loc: pt, tangent: glm::zero(), functions: vec![func], mileage: None, name };
+            // Objects which are close together in the file may end up on the
+            // same grid point, so shift them along the track until a free
+            // point is found.
+            let shifted = (0..20).map(|i| if i % 2 == 0 { (i/2) as f32 } else { -((i+1)/2) as f32 })
+                .find(|&k| {
+                    obj.move_to(&model, pt + 0.1*k*tangent);
+                    !model.objects.contains_key(&round_coord(obj.loc))
+                });
+            match shifted {
+                Some(k) => {
+                    if k != 0.0 {
+                        warn!("Object {:?} moved along the track to avoid overlapping another object",
+                              obj.name);
+                    }
+                    model.objects.insert(round_coord(obj.loc), obj);
+                },
+                None => {
+                    warn!("Skipping object {:?}, no free location near the other objects", obj.name);
+                },
+            }
+        };
+
+        for signal in track.objects.signals.iter() {
+            use railmlio::model::SignalType;
+            match signal.r#type {
+                SignalType::Main | SignalType::Combined => {
+                    let has_distant = matches!(signal.r#type, SignalType::Combined);
+                    place(signal.pos.offset, Function::MainSignal { has_distant, sight: signal.sight }, Some(signal.dir),
+                          signal.name.clone());
+                },
+                SignalType::Shunting => {
+                    place(signal.pos.offset, Function::ShuntingSignal { sight: signal.sight }, Some(signal.dir),
+                          signal.name.clone());
+                },
+                _ => { 
+                    warn!("Skipping unsupported signal type {:?} for signal {:?}", signal.r#type, signal.id);
+                },
+            }
+        }
+        for detector in track.objects.train_detectors.iter() {
+            place(detector.pos.offset, Function::Detector, None, detector.name.clone());
+        }
     }
 
     Ok(model)
 
 }
 
+/// Names refer to objects when they are moved, see `Model::relink_named_refs`,
+/// so names used more than once in the file get a number added.
+fn unique_name(used :&mut HashSet<String>, name :String) -> String {
+    if used.insert(name.clone()) { return name; }
+    let new_name = (2..).map(|i| format!("{} ({})", name, i)).find(|n| !used.contains(n)).unwrap();
+    warn!("Object name {:?} is used more than once, renamed to {:?}", name, new_name);
+    used.insert(new_name.clone());
+    new_name
+}
+
+/// Point and unit tangent at the given parameter (0.0 to 1.0) along a polyline,
+/// measured by length.
+fn pline_point(pline :&[PtC], param :f32) -> (PtC, PtC) {
+    let total :f32 = pline.iter().zip(pline.iter().skip(1)).map(|(a,b)| glm::distance(a,b)).sum();
+    let mut remaining = param * total;
+    for (a,b) in pline.iter().zip(pline.iter().skip(1)) {
+        let l = glm::distance(a,b);
+        if l == 0.0 { continue; }
+        if remaining <= l {
+            return (glm::lerp(a, b, remaining / l), glm::normalize(&(b-a)));
+        }
+        remaining -= l;
+    }
+    let n = pline.len();
+    (pline[n-1], glm::normalize(&(pline[n-1]-pline[n-2])))
+}
+
 pub fn line_segments(a :Pt, b :Pt) -> Result<Vec<(Pt,Pt)>, ()> {
     use nalgebra_glm as glm;
     let mut out = Vec::new();
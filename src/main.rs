@@ -5,6 +5,8 @@ mod file;
 mod gui;
 mod util;
 mod import;
+mod export;
+mod headless;
 
 mod synthesis;
 
@@ -16,6 +18,16 @@ fn main() {
     let logstring = gui::windows::logview::StringLogger::init(log::LevelFilter::Trace).unwrap();
     info!("Starting {} v{}.", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
+    // Run analysis without GUI
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(idx) = args.iter().position(|x| x == "--headless") {
+        if let Err(e) = headless::run(&args[(idx+1)..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // User config not directly related to model or ui state. (colors, fonts, etc.)
     let config = config::Config::load();
     let background_jobs = app::BackgroundJobs::new();
@@ -36,7 +48,6 @@ fn main() {
     };
 
 
-   let args: Vec<String> = std::env::args().collect();
    let big = args.iter().find(|x| *x == "--big").is_some();
 
     backend_glfw::backend(&app.document.fileinfo.window_title(),
@@ -87,6 +87,7 @@ pub fn concrete_dispatch(dgraph :&DGraph, il :&Interlocking, ad :&AbstractComman
         };
 
         'rs: for route_idx in route_idxs {
+            if il.routes[*route_idx].class != RouteClass::Train { continue 'rs; }
             let route = &il.routes[*route_idx].route;
             let switches = route.resources.switch_positions.iter()
                 .map(|(sw,side)| (*dgraph.switch_ids.get_by_left(sw).unwrap(), *side))
@@ -122,6 +123,9 @@ fn convert_routeentryexit(dgraph :&DGraph, id_map :Option<&HashMap<PtA,PtA>>,
             Ok(*dgraph.node_ids.get_by_left(&nd).unwrap()),
         rolling_inf::RouteEntryExit::Signal(signal) |
         rolling_inf::RouteEntryExit::SignalTrigger { signal, .. } => {
+            if let Some(pt) = dgraph.buffer_stop_signals.get(&signal) {
+                return Ok(*pt);
+            }
             let signal_id = *dgraph.object_ids.get_by_left(&signal).unwrap();
             if let Some(id_map) = id_map {
                 Err(*id_map.get(&signal_id).unwrap())
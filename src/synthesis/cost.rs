@@ -6,29 +6,29 @@ use crate::synthesis::abstractdispatch::*;
 use rolling::input::dispatch::DispatchAction;
 use crate::document::history::convert_vehicle;
 use crate::document::dispatch::max_time;
-use crate::document::plan::eval_plan;
+use crate::document::plan::{eval_plan, TrainCommand};
 use crate::document::history::History;
 
 
 pub fn measure_dispatch(bg :&SynthesisBackground, dgraph :&DGraph, il :&Interlocking,
     planspec_id :usize, dispatch :&AbstractDispatch) -> Result<f64,()> {
 
-    let commands = mk_commands(bg, &dgraph, &il, planspec_id, dispatch)?;
+    let (commands, train_commands) = mk_commands(bg, &dgraph, &il, planspec_id, dispatch)?;
     //println!("Testing c {:?}", commands);
     let history = rolling::evaluate_plan(&dgraph.rolling_inf, 
          &il.routes.iter().map(|r| r.route.clone()).enumerate().collect(),
          &rolling::input::dispatch::Dispatch { actions: commands },
          None);
-    if eval_plan(&dgraph, &bg.plans[planspec_id], &history).is_ok() {
+    if eval_plan(&dgraph, &il, &bg.plans[planspec_id], &train_commands, &history).is_ok() {
         Ok(max_time(&history))
     } else {
          Err(())
     }
 }
 
-pub fn measure(bg :&SynthesisBackground, allplans :&MultiPlan, design :&Design) -> f64 {
+pub fn measure(bg :&SynthesisBackground, allplans :&MultiPlan, design :&Design) -> Result<f64,String> {
     //println!("cost::measure");
-    let (topo,dgraph,il) = create_model(bg,design);
+    let (topo,dgraph,il) = create_model(bg,design)?;
     let mut total_cost = 0.0;
     //println!("Testing design {:?}", design);
     //println!("Testing design on plans {:?}", allplans);
@@ -45,17 +45,21 @@ pub fn measure(bg :&SynthesisBackground, allplans :&MultiPlan, design :&Design)
         }
     }
 
-    total_cost
+    Ok(total_cost)
 }
 
+/// Dispatch actions for the abstract dispatch, and the same actions as commands 
+/// for each plan train, in the order the actions are run.
 fn mk_commands(bg :&SynthesisBackground, dgraph :&DGraph, il:&Interlocking, 
                planspec_id :usize, abstract_dispatch :&AbstractDispatch) 
-    -> Result<Vec<DispatchAction<usize>>,()> {
+    -> Result<(Vec<DispatchAction<usize>>, Vec<TrainCommand>),()> {
 
     let planspec = &bg.plans[planspec_id];
     let mut output = Vec::new();
+    let mut train_commands = Vec::new();
     for acmd in abstract_dispatch.iter() {
         for route_idx in concrete_dispatch(dgraph, il, acmd)? {
+            let route_spec = il.routes[route_idx].id;
             if il.routes[route_idx].route.entry.is_boundary() {
                 let (vehicle_id,_) = planspec.trains.get(acmd.train).unwrap();
                 let (_,vehicle) = &bg.vehicles[vehicle_id.unwrap()];
@@ -63,11 +67,13 @@ fn mk_commands(bg :&SynthesisBackground, dgraph :&DGraph, il:&Interlocking,
                     format!("train{}", acmd.train), 
                     convert_vehicle(&vehicle),
                     route_idx));
+                train_commands.push((acmd.train, route_idx, (0.0, Command::Train(vehicle_id.unwrap(), route_spec))));
             } else {
                 output.push(DispatchAction::Route(route_idx));
+                train_commands.push((acmd.train, route_idx, (0.0, Command::Route(route_spec))));
             }
         }
     }
-    Ok(output)
+    Ok((output, train_commands))
 }
 
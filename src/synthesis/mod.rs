@@ -30,10 +30,11 @@ pub struct SynthesisBackground<'a> {
     pub topology :&'a Topology,
     pub plans :&'a [PlanSpec],
     pub vehicles :&'a [(usize,Vehicle)],
+    pub route_settings :&'a RouteSettings,
 }
 
 #[derive(Debug)]
-pub enum SynErr { Aborted }
+pub enum SynErr { Aborted, Model(String) }
 
 pub type Design = Vec<Object>;
 pub type Object = (usize,f64,Function,Option<AB>);
@@ -48,7 +49,8 @@ pub fn full_synthesis( bg :&SynthesisBackground,
     output(FullSynMsg::ModelAvailable(format!("Maximal model"), 0.0, 
                                       maximal_objects.clone())).ok_or(SynErr::Aborted)?;
 
-    let mut signal_set_iterator = reduce::reduced_signal_sets(bg, maximal_objects);
+    let mut signal_set_iterator = reduce::reduced_signal_sets(bg, maximal_objects)
+        .map_err(SynErr::Model)?;
 
     // Try all minimal signal sets
     // TODO reorg to breadth first?
@@ -69,14 +71,16 @@ pub fn full_synthesis( bg :&SynthesisBackground,
             }
         }
         output(FullSynMsg::TryingSignalSet()).ok_or(SynErr::Aborted)?;
-        let (score,design) = optimize::optimize_locations(bg, &adispatch, &design);
+        let (score,design) = optimize::optimize_locations(bg, &adispatch, &design)
+            .map_err(SynErr::Model)?;
         output(FullSynMsg::ModelAvailable(format!("reduced {}",n), score, design.clone())).ok_or(SynErr::Aborted)?;
         n += 1;
 
         let mut add_signal_iterator = add::add_signal(bg, design);
         while let Some(mut design) = add_signal_iterator.next() {
             output(FullSynMsg::TryingSignalSet()).ok_or(SynErr::Aborted)?;
-            let (score,design) = optimize::optimize_locations(bg, &adispatch, &design);
+            let (score,design) = optimize::optimize_locations(bg, &adispatch, &design)
+                .map_err(SynErr::Model)?;
             output(FullSynMsg::ModelAvailable(format!("added {}", n), score, design)).ok_or(SynErr::Aborted)?;
             n += 1;
         }
@@ -87,22 +91,37 @@ pub fn full_synthesis( bg :&SynthesisBackground,
 }
 
 
-pub fn create_model(bg :&SynthesisBackground, design :&Vec<Object>) -> (Topology,dgraph::DGraph,interlocking::Interlocking) {
+/// Objects in the design are given ids outside the range of the rounded
+/// drawing coordinates, so that they don't collide with the ids of the
+/// objects kept from the input model.
+const DESIGN_ID_Y :i32 = std::i32::MIN;
+
+pub fn design_object_id(idx :usize) -> PtA { glm::vec2(idx as i32, DESIGN_ID_Y) }
+fn design_detector_id(idx :usize) -> PtA { glm::vec2(idx as i32, DESIGN_ID_Y + 1) }
+
+pub fn create_model(bg :&SynthesisBackground, design :&Vec<Object>) 
+    -> Result<(Topology,dgraph::DGraph,interlocking::Interlocking), String> {
     let mut topo = (*bg.topology).clone();
-    topo.trackobjects = topo.tracks.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+    // Keep the shunting signals, speed restrictions and mileage breaks, they are not part 
+    // of the main signal design.
+    topo.trackobjects = topo.trackobjects.iter().map(|objs| objs.iter()
+        .filter(|(_,_,func,_)| matches!(func, Function::ShuntingSignal { .. } | Function::SpeedRestriction { .. } 
+                                                | Function::MileageBreak { .. })).cloned()
+        .collect()).collect::<Vec<_>>();
     for (obj_idx,(track_idx,pos,func,dir)) in design.iter().enumerate() {
-        topo.trackobjects[*track_idx].push((*pos, glm::vec2(obj_idx as i32, 0), *func, *dir));
+        topo.trackobjects[*track_idx].push((*pos, design_object_id(obj_idx), *func, *dir));
         if matches!(func, Function::MainSignal { .. }) {
-            topo.trackobjects[*track_idx].push((*pos, glm::vec2(obj_idx as i32, 1), Function::Detector, None));
+            topo.trackobjects[*track_idx].push((*pos, design_detector_id(obj_idx), Function::Detector, None));
         }
     }
 
-    let dgraph = dgraph::DGraphBuilder::convert(&topo).unwrap();
-    let il = interlocking::calc(&dgraph);
+    let dgraph = dgraph::DGraphBuilder::convert(&topo)
+        .map_err(|_| format!("Could not convert the design to a railway graph."))?;
+    let (il,_) = interlocking::calc(&dgraph, bg.route_settings)?;
 
     //println!("create_model interlocking");
     //for r in il.routes.iter() {
         //println!("route  {:?}", r);
     //}
-    (topo,dgraph,il)
+    Ok((topo,dgraph,il))
 }
@@ -6,20 +6,22 @@ use itertools::*;
 use std::mem::replace;
 use permutation::Permutation;
 
-pub fn optimize_locations(bg :&SynthesisBackground, adispatch :&MultiPlan, design :&Design) -> (f64,Design) {
+pub fn optimize_locations(bg :&SynthesisBackground, adispatch :&MultiPlan, design :&Design) -> Result<(f64,Design),String> {
     info!("optimize_locations: starting");
     let order = permutation::sort_by_key(&design[..], 
                  |(tr,pos,_,_)| (*tr, OrderedFloat(*pos)));
-    let baseline_value = cost::measure(bg, adispatch, design);
+    let baseline_value = cost::measure(bg, adispatch, design)?;
     let mut n = 0;
     let start_pt = design_encode(bg, design, &order);
     info!("Encoding first design {:?}\n  {:?}", design, start_pt);
     let (cost, best_pt) = powell_optimize_unit(start_pt, |new_pt| {
         n += 1;
+        // Designs which can not be modelled are not feasible.
         cost::measure(bg, adispatch, &design_decode(bg, new_pt, design, &order))
-    }).unwrap();
+            .unwrap_or(std::f64::INFINITY)
+    }).map_err(|e| format!("Could not optimize signal locations: {:?}", e))?;
     info!("optimize_locations: {} iterations", n);
-    (cost, design_decode(bg, &best_pt, design, &order))
+    Ok((cost, design_decode(bg, &best_pt, design, &order)))
 }
 
 fn design_encode(bg :&SynthesisBackground, design :&Design, order :&Permutation) 
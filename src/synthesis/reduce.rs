@@ -2,7 +2,6 @@ use matches::*;
 use std::collections::HashMap;
 use std::collections::BTreeSet;
 use rolling::input::staticinfrastructure as rolling_inf;
-use nalgebra_glm as glm;
 
 use crate::synthesis::*;
 use crate::synthesis::abstractdispatch::*;
@@ -12,19 +11,18 @@ use crate::document::interlocking;
 use crate::document::plan;
 
 pub fn reduced_signal_sets<'a>(bg :&'a SynthesisBackground, design :Design) 
-    -> impl Iterator<Item = (Design, MultiPlan)> + 'a {
+    -> Result<impl Iterator<Item = (Design, MultiPlan)> + 'a, String> {
         
-    let (topo,dgraph,il) = create_model(bg, &design);
-    let inf = plan::convert_inf(&il.routes.iter()
-                                .map(|i| i.route.clone()).enumerate().collect());
-    let plans = bg.plans.iter().map(|p| plan::convert_plan(&il, bg.vehicles, p))
-        .collect::<Result<Vec<_>,_>>().unwrap();
+    let (topo,dgraph,il) = create_model(bg, &design)?;
+    let inf = plan::convert_inf(&il.routes.iter().enumerate().collect());
+    let plans = bg.plans.iter().map(|p| plan::convert_plan(&dgraph, &il, bg.vehicles, p))
+        .collect::<Result<Vec<_>,_>>().map_err(|e| format!("Could not convert plan: {:?}", e))?;
 
     //println!("create optmizer");
     let mut optimizer = planner::optimize::SignalOptimizer::new(inf, plans.into());
     //println!("create optmizer ok");
 
-    Iter { bg, topo, dgraph, il, optimizer }
+    Ok(Iter { bg, topo, dgraph, il, optimizer })
 }
 
 pub struct Iter<'a> {
@@ -88,7 +86,7 @@ fn convert_signals(topo :&Topology, dgraph :&dgraph::DGraph,
                         .unwrap_or(false) {
 
                         design.push((track_idx, *pos, *func, *dir));
-                        id_map.insert(glm::vec2(id.x as _, 0) , glm::vec2(design.len() as _, 0));
+                        id_map.insert(design_object_id(id.x as _), design_object_id(design.len()));
                     }
                 },
                 Function::MainSignal { .. } => {
@@ -96,16 +94,19 @@ fn convert_signals(topo :&Topology, dgraph :&dgraph::DGraph,
                         .unwrap_or(false) {
 
                             
-                        id_map.insert(glm::vec2(id.x as _, 0) , glm::vec2(design.len() as _, 0));
+                        id_map.insert(design_object_id(id.x as _), design_object_id(design.len()));
                         design.push((track_idx, *pos, *func, *dir));
 
                     } else if sig_id.get(id).map(|o| detectors.contains(&planner::input::SignalId::Signal(*o)))
                         .unwrap_or(false) {
 
-                        id_map.insert(glm::vec2(id.x as _, 0) , glm::vec2(design.len() as _, 0));
+                        id_map.insert(design_object_id(id.x as _), design_object_id(design.len()));
                         design.push((track_idx, *pos, Function::Detector, None));
                     }
                 },
+                // Shunting signals, speed restrictions and mileage breaks are kept from the 
+                // input model, see `create_model`.
+                Function::ShuntingSignal { .. } | Function::SpeedRestriction { .. } | Function::MileageBreak { .. } => {},
             }
         }
     }